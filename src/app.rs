@@ -0,0 +1,844 @@
+use crate::config::{
+    ensure_config_file, ClickAction, ConfigError, MixerMode, QuitAction, Settings,
+};
+use crate::config_watcher::{diff_settings, ConfigWatcher, ReloadAction};
+use crate::error::Error;
+use crate::events::{EventBus, MixerEvent};
+use crate::hotkey::HotkeyAction;
+use crate::instance::{InstanceCommand, MixerStatus};
+use crate::menu::MenuCommand;
+use crate::platform::{EventHandler, Platform, Point, Timer, TrayEvent, TrayShell};
+use crate::supervisor::{RestartPolicy, Supervisor};
+use crate::volume_mixer_process::{Health, VolumeMixerProcess};
+use crate::volume_mixer_tray_icon::VolumeMixerTrayIcon;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use std::time::Instant;
+
+const SOUND_SETTINGS_URI: &str = "ms-settings:sound";
+
+/// Owns the mixer process and the tray icon and routes events between them.
+pub struct App<P: Platform, S: TrayShell> {
+    platform: P,
+    settings: Settings,
+    config_watcher: Option<ConfigWatcher>,
+    tray_icon: VolumeMixerTrayIcon<S, P>,
+    volume_mixer_process: VolumeMixerProcess<P>,
+    supervisor: Supervisor,
+    /// Mixers left to the user, opened by a click or released by the tray,
+    /// which must not be adopted.
+    excluded_pids: Vec<u32>,
+    events: EventBus,
+    /// Last visibility published, to notice changes made by anyone.
+    mixer_visible: bool,
+}
+
+impl<P: Platform, S: TrayShell> App<P, S> {
+    pub fn new(
+        platform: P,
+        shell: S,
+        settings: Settings,
+        config_watcher: Option<ConfigWatcher>,
+        show_tray_icon: bool,
+    ) -> Result<App<P, S>, Error> {
+        let volume_mixer_process = VolumeMixerProcess::new(&platform, &settings.mixer, &[])?;
+        info!("Run Volume Mixer with pid {}", volume_mixer_process.pid);
+
+        let tray_icon = VolumeMixerTrayIcon::new(
+            shell,
+            platform.clone(),
+            volume_mixer_process.hwnd,
+            &settings,
+            show_tray_icon,
+        );
+
+        let mixer_visible = platform.is_visible(volume_mixer_process.hwnd);
+
+        Ok(App {
+            platform,
+            settings,
+            config_watcher,
+            tray_icon,
+            volume_mixer_process,
+            supervisor: Supervisor::new(RestartPolicy::default(), Instant::now()),
+            excluded_pids: Vec::new(),
+            events: EventBus::default(),
+            mixer_visible,
+        })
+    }
+
+    /// Bus the app publishes its `MixerEvent`s to.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Publish whether the mixer was shown or hidden since the last call,
+    /// whether by the tray, by a command or by the user.
+    fn publish_visibility(&mut self) {
+        let visible = self.platform.is_visible(self.volume_mixer_process.hwnd);
+        if visible != self.mixer_visible {
+            self.mixer_visible = visible;
+            self.events.publish(if visible {
+                MixerEvent::MixerShown
+            } else {
+                MixerEvent::MixerHidden
+            });
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: Settings) {
+        let actions = diff_settings(&self.settings, &settings);
+        if self.settings.http != settings.http {
+            warn!("HTTP settings changed, they apply after restarting the tray");
+        }
+        if self.settings.mqtt != settings.mqtt {
+            warn!("MQTT settings changed, they apply after restarting the tray");
+        }
+        self.settings = settings;
+
+        for action in actions {
+            debug!("Apply reloaded settings: {:?}", action);
+            match action {
+                ReloadAction::UpdateTooltip(tooltip) => self.tray_icon.set_tooltip(&tooltip),
+                ReloadAction::UpdateAutoHide(auto_hide) => self.tray_icon.set_auto_hide(&auto_hide),
+                ReloadAction::UpdatePlacement(placement) => {
+                    self.tray_icon.set_placement(&placement)
+                }
+                ReloadAction::UpdateHotkeys(hotkeys) => self.tray_icon.set_hotkeys(&hotkeys),
+                ReloadAction::RestartMixer => self.replace_volume_mixer(),
+                ReloadAction::RespawnMixer => self.respawn_volume_mixer(),
+            }
+        }
+    }
+
+    fn reload_config(&mut self) {
+        let reloaded = match self.config_watcher.as_mut() {
+            Some(config_watcher) => config_watcher.poll(),
+            None => return,
+        };
+
+        self.apply_reloaded(reloaded);
+    }
+
+    fn force_reload_config(&mut self) {
+        let reloaded = match self.config_watcher.as_mut() {
+            Some(config_watcher) => config_watcher.reload(),
+            None => {
+                warn!("Could not determine config directory, nothing to reload");
+                return;
+            }
+        };
+
+        self.apply_reloaded(Some(reloaded));
+    }
+
+    fn apply_reloaded(&mut self, reloaded: Option<Result<Settings, ConfigError>>) {
+        match reloaded {
+            Some(Ok(settings)) => {
+                info!("Config file changed, apply new settings");
+                self.apply_settings(settings);
+                self.events.publish(MixerEvent::ConfigReloaded);
+            }
+            Some(Err(err)) => error!("Keep current settings, config reload failed: {}", err),
+            None => {}
+        }
+    }
+
+    /// Restart the mixer on request, `false` if it was kept. A mixer opened by
+    /// the user is only ended if the tray may end it on quit.
+    fn restart_volume_mixer(&mut self) -> bool {
+        if self.volume_mixer_process.is_borrowed()
+            && self.settings.mixer.adopted_on_quit != QuitAction::Terminate
+        {
+            warn!(
+                "Volume mixer with pid {} was opened by the user, keep it instead of restarting",
+                self.volume_mixer_process.pid
+            );
+            return false;
+        }
+        // Taken over to be ended, not released to the user.
+        if !self.volume_mixer_process.take_over(&self.platform) {
+            warn!(
+                "Cannot end volume mixer with pid {}, keep it instead of restarting",
+                self.volume_mixer_process.pid
+            );
+            return false;
+        }
+
+        self.replace_volume_mixer();
+        true
+    }
+
+    /// Move on to a mixer matching the current settings. A borrowed mixer is
+    /// left to the user as on quit instead of being ended.
+    fn replace_volume_mixer(&mut self) {
+        if self.volume_mixer_process.is_borrowed() {
+            info!(
+                "Release volume mixer with pid {} opened by the user",
+                self.volume_mixer_process.pid
+            );
+            // Otherwise a mixer with the same executable would simply be adopted again.
+            self.excluded_pids.push(self.volume_mixer_process.pid);
+            self.volume_mixer_process
+                .shutdown(&self.platform, &self.settings.mixer);
+        } else {
+            self.volume_mixer_process
+                .terminate(&self.platform, &self.settings.mixer);
+        }
+
+        if let Err(err) = self.start_volume_mixer() {
+            error!("Failed to start volume mixer: {}", err);
+            // Leave further attempts to the supervisor.
+            self.supervisor.on_start_failed(Instant::now());
+        }
+    }
+
+    /// Restart a mixer the tray started if it does not end with the tray as
+    /// configured. An adopted mixer never does.
+    fn respawn_volume_mixer(&mut self) {
+        let process = &self.volume_mixer_process;
+        if process.is_adopted()
+            || process.is_killed_on_tray_exit() == self.settings.mixer.spawned_ends_with_tray()
+        {
+            return;
+        }
+
+        info!("Restart volume mixer, so that it ends with the tray only as configured");
+        self.replace_volume_mixer();
+    }
+
+    fn start_volume_mixer(&mut self) -> Result<(), Error> {
+        // A pid of a closed mixer may be reused by one the user started.
+        let platform = &self.platform;
+        self.excluded_pids
+            .retain(|pid| platform.is_process_running(*pid).unwrap_or(true));
+        let volume_mixer_process =
+            VolumeMixerProcess::new(&self.platform, &self.settings.mixer, &self.excluded_pids)?;
+        info!("Run Volume Mixer with pid {}", volume_mixer_process.pid);
+
+        self.tray_icon
+            .set_volume_mixer_hwnd(volume_mixer_process.hwnd);
+        self.volume_mixer_process = volume_mixer_process;
+        self.supervisor.on_started(Instant::now());
+        self.events.publish(MixerEvent::MixerRestarted {
+            pid: self.volume_mixer_process.pid,
+        });
+
+        Ok(())
+    }
+
+    /// Open another mixer in `mode` where the tray icon was clicked.
+    fn launch_volume_mixer(&mut self, mode: MixerMode) {
+        let anchor = self.platform.cursor_pos().unwrap_or_else(|err| {
+            warn!("Could not get cursor position: {}", err);
+            Point::default()
+        });
+        match VolumeMixerProcess::launch(&self.platform, &self.settings.mixer, mode, anchor) {
+            Ok(pid) => {
+                info!("Run Volume Mixer as {:?} with pid {}", mode, pid);
+                self.excluded_pids.push(pid);
+            }
+            Err(err) => error!("Failed to open volume mixer: {}", err),
+        }
+    }
+
+    /// Notice a closed or crashed mixer and bring it back, backing off when
+    /// it keeps failing.
+    fn supervise(&mut self) {
+        let now = Instant::now();
+
+        if self.supervisor.is_running() {
+            match self
+                .volume_mixer_process
+                .check(&self.platform, &self.settings.mixer)
+            {
+                Health::Running => return,
+                Health::WindowChanged(hwnd) => {
+                    info!("Volume mixer window was recreated");
+                    self.tray_icon.set_volume_mixer_hwnd(hwnd);
+                    return;
+                }
+                Health::Exited(reason) => {
+                    warn!(
+                        "Volume mixer with pid {} {}",
+                        self.volume_mixer_process.pid, reason
+                    );
+                    self.supervisor.on_exit(reason, now);
+                }
+            }
+        }
+
+        if self.supervisor.should_restart(now) {
+            if let Err(err) = self.start_volume_mixer() {
+                error!("Failed to restart volume mixer: {}", err);
+                self.supervisor.on_start_failed(now);
+            }
+        }
+    }
+
+    fn shell_open(&self, target: &str) {
+        if let Err(err) = self.platform.shell_open(target) {
+            error!("{}", err);
+        }
+    }
+
+    fn open_settings(&self) {
+        let Some(config_path) = self.config_watcher.as_ref().map(ConfigWatcher::path) else {
+            warn!("Could not determine config directory, nothing to open");
+            return;
+        };
+
+        if let Err(err) = ensure_config_file(config_path) {
+            error!(
+                "Failed to create config file \"{}\": {}",
+                config_path.display(),
+                err
+            );
+            return;
+        }
+        self.shell_open(&config_path.to_string_lossy());
+    }
+
+    fn status(&self) -> MixerStatus {
+        let hwnd = self.volume_mixer_process.hwnd;
+
+        MixerStatus {
+            pid: self.volume_mixer_process.pid,
+            hwnd: hwnd.0,
+            running: self.supervisor.is_running(),
+            visible: self.platform.is_visible(hwnd),
+            uptime: self.supervisor.uptime(Instant::now()),
+            restart_count: self.supervisor.status().restart_count,
+        }
+    }
+
+    fn show_about(&self) {
+        let status = self.supervisor.status();
+        let last_exit = status
+            .last_exit
+            .map(|reason| reason.to_string())
+            .unwrap_or_else(|| "none".to_string());
+        let text = format!(
+            "{} {}\n\nKeeps the Windows Volume Mixer one click away in the tray.\n\n\
+             Mixer restarts: {}\nLast mixer exit: {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            status.restart_count,
+            last_exit
+        );
+        self.tray_icon.show_message("About", &text);
+    }
+}
+
+impl<P: Platform, S: TrayShell> Drop for App<P, S> {
+    fn drop(&mut self) {
+        self.events.publish(MixerEvent::Exiting);
+        self.events.close();
+        self.volume_mixer_process
+            .shutdown(&self.platform, &self.settings.mixer);
+    }
+}
+
+impl<P: Platform, S: TrayShell> EventHandler for App<P, S> {
+    fn handle_tray_event(&mut self, event: TrayEvent) {
+        // Do not wait for the next check if the mixer was closed meanwhile.
+        self.supervise();
+        let action = match event {
+            TrayEvent::LeftButtonDown => Some(self.settings.tray.left_click),
+            TrayEvent::MiddleButtonDown => Some(self.settings.tray.middle_click),
+            // Always opens the menu.
+            TrayEvent::RightButtonDown => None,
+        };
+        match action {
+            Some(ClickAction::OpenMixer) => self.launch_volume_mixer(MixerMode::Mixer),
+            Some(ClickAction::OpenTrayMixer) => self.launch_volume_mixer(MixerMode::TrayMixer),
+            Some(ClickAction::OpenSlider) => self.launch_volume_mixer(MixerMode::Slider),
+            Some(ClickAction::Nothing) => {}
+            Some(ClickAction::Toggle) | None => self.tray_icon.handle_tray_event(event),
+        }
+        self.publish_visibility();
+    }
+
+    fn handle_timer(&mut self, timer: Timer) {
+        match timer {
+            Timer::ConfigReload => self.reload_config(),
+            Timer::Supervise => self.supervise(),
+        }
+        self.publish_visibility();
+    }
+
+    fn handle_foreground_changed(&mut self) {
+        self.tray_icon.check_focus();
+        self.publish_visibility();
+    }
+
+    fn handle_menu_command(&mut self, command: MenuCommand) {
+        debug!("Menu command {:?}", command);
+        match command {
+            MenuCommand::ToggleMixer => self.tray_icon.toggle_volume_mixer(),
+            MenuCommand::OpenSoundSettings => self.shell_open(SOUND_SETTINGS_URI),
+            MenuCommand::RestartMixer => {
+                if !self.restart_volume_mixer() {
+                    self.tray_icon.show_message(
+                        "Restart mixer",
+                        "The volume mixer was opened by you, close it yourself to restart it.",
+                    );
+                }
+            }
+            MenuCommand::OpenSettings => self.open_settings(),
+            MenuCommand::About => self.show_about(),
+            MenuCommand::Exit => self.tray_icon.quit(),
+        }
+        self.publish_visibility();
+    }
+
+    fn handle_hotkey(&mut self, action: HotkeyAction) {
+        debug!("Hotkey {:?}", action);
+        self.tray_icon.handle_hotkey(action);
+        self.publish_visibility();
+    }
+
+    fn handle_instance_command(&mut self, command: InstanceCommand) -> Option<String> {
+        info!("Command \"{}\" from another launch", command.name());
+        match command {
+            InstanceCommand::Show => self.tray_icon.show_volume_mixer(),
+            InstanceCommand::Toggle => self.tray_icon.toggle_volume_mixer(),
+            InstanceCommand::Hide => self.tray_icon.hide_volume_mixer(),
+            InstanceCommand::Reload => self.force_reload_config(),
+            InstanceCommand::RestartMixer => {
+                self.restart_volume_mixer();
+            }
+            InstanceCommand::Status => return Some(self.status().encode()),
+            InstanceCommand::Quit => self.tray_icon.quit(),
+        }
+        self.publish_visibility();
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Received;
+    use crate::fake_platform::{FakePlatform, TempConfig};
+    use crate::platform::WindowManager;
+    use crate::supervisor::{ExitReason, SupervisorStatus};
+    use std::fs;
+    use std::time::Duration;
+
+    type FakeApp = App<FakePlatform, FakePlatform>;
+
+    fn start(platform: &FakePlatform, config: &TempConfig) -> FakeApp {
+        config.write("", Duration::from_secs(60));
+        let watcher = ConfigWatcher::new(config.path());
+
+        App::new(
+            platform.clone(),
+            platform.clone(),
+            Settings::default(),
+            Some(watcher),
+            true,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reload_updates_tooltip_and_keeps_mixer() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("reload_tooltip");
+        let mut app = start(&platform, &config);
+        let pid = app.volume_mixer_process.pid;
+
+        config.write("[tray]\ntooltip = \"Mixer\"\n", Duration::ZERO);
+        app.handle_timer(Timer::ConfigReload);
+
+        assert_eq!(platform.state().tray_tooltip.as_deref(), Some("Mixer"));
+        assert_eq!(app.volume_mixer_process.pid, pid);
+        assert_eq!(platform.state().spawned.len(), 1);
+    }
+
+    #[test]
+    fn reload_registers_hotkeys_that_toggle_mixer() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("reload_hotkeys");
+        let mut app = start(&platform, &config);
+        let hwnd = app.volume_mixer_process.hwnd;
+        assert!(platform.state().hotkeys.is_empty());
+
+        config.write("[hotkeys]\ntoggle = \"Win+Shift+V\"\n", Duration::ZERO);
+        app.handle_timer(Timer::ConfigReload);
+        assert_eq!(
+            platform.state().hotkeys.keys().copied().collect::<Vec<_>>(),
+            vec![HotkeyAction::ToggleMixer]
+        );
+
+        app.handle_hotkey(HotkeyAction::ToggleMixer);
+        assert!(platform.state().window(hwnd).unwrap().visible);
+        app.handle_hotkey(HotkeyAction::ToggleMixer);
+        assert!(!platform.state().window(hwnd).unwrap().visible);
+    }
+
+    #[test]
+    fn reload_with_new_exec_restarts_mixer() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("reload_exec");
+        let mut app = start(&platform, &config);
+        let old_pid = app.volume_mixer_process.pid;
+
+        config.write("[mixer]\nexec_name = \"Mixer.exe\"\n", Duration::ZERO);
+        app.handle_timer(Timer::ConfigReload);
+
+        let state = platform.state();
+        assert!(!state.is_process_alive(old_pid));
+        assert!(state.spawned[1].ends_with("Mixer.exe"));
+        assert!(state.is_process_alive(app.volume_mixer_process.pid));
+    }
+
+    #[test]
+    fn reload_with_new_exec_leaves_borrowed_mixer_running() {
+        let platform = FakePlatform::default();
+        let (old_pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let config = TempConfig::new("reload_exec_borrowed");
+        let mut app = start(&platform, &config);
+        assert!(app.volume_mixer_process.is_borrowed());
+
+        config.write("[mixer]\nexec_name = \"Mixer.exe\"\n", Duration::ZERO);
+        app.handle_timer(Timer::ConfigReload);
+
+        let state = platform.state();
+        assert!(state.is_process_alive(old_pid));
+        assert!(state.close_requests.is_empty());
+        assert!(state.spawned[0].ends_with("Mixer.exe"));
+        assert_ne!(app.volume_mixer_process.pid, old_pid);
+    }
+
+    #[test]
+    fn reload_to_leave_mixer_running_respawns_it_outside_job() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("reload_quit_action");
+        let mut app = start(&platform, &config);
+        let old_pid = app.volume_mixer_process.pid;
+        assert!(app.volume_mixer_process.is_killed_on_tray_exit());
+
+        config.write(
+            "[mixer]\nspawned_on_quit = \"leave-running\"\n",
+            Duration::ZERO,
+        );
+        app.handle_timer(Timer::ConfigReload);
+
+        assert_ne!(app.volume_mixer_process.pid, old_pid);
+        assert!(!app.volume_mixer_process.is_killed_on_tray_exit());
+        {
+            let state = platform.state();
+            assert!(!state.is_process_alive(old_pid));
+            assert!(!state.spawn_options[1].kill_on_tray_exit);
+        }
+
+        config.write(
+            "[mixer]\nspawned_on_quit = \"hide\"\n",
+            Duration::from_secs(30),
+        );
+        app.handle_timer(Timer::ConfigReload);
+        assert_eq!(platform.state().spawned.len(), 2);
+    }
+
+    #[test]
+    fn restart_command_replaces_spawned_mixer() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("menu_restart");
+        let mut app = start(&platform, &config);
+        let old_pid = app.volume_mixer_process.pid;
+
+        app.handle_menu_command(MenuCommand::RestartMixer);
+
+        let state = platform.state();
+        assert_eq!(state.spawned.len(), 2);
+        assert!(!state.is_process_alive(old_pid));
+        assert!(state.is_process_alive(app.volume_mixer_process.pid));
+    }
+
+    #[test]
+    fn restart_command_keeps_borrowed_mixer() {
+        let platform = FakePlatform::default();
+        let (old_pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let config = TempConfig::new("menu_restart_kept");
+        let mut app = start(&platform, &config);
+        assert!(app.volume_mixer_process.is_borrowed());
+
+        app.handle_menu_command(MenuCommand::RestartMixer);
+        app.handle_instance_command(InstanceCommand::RestartMixer);
+
+        assert_eq!(app.volume_mixer_process.pid, old_pid);
+        let state = platform.state();
+        assert!(state.is_process_alive(old_pid));
+        assert!(state.close_requests.is_empty());
+        assert!(state.spawned.is_empty());
+        assert_eq!(state.messages.len(), 1);
+    }
+
+    #[test]
+    fn restart_command_ends_borrowed_mixer_if_quit_would() {
+        let platform = FakePlatform::default();
+        let (old_pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let config = TempConfig::new("menu_restart_borrowed");
+        let mut app = start(&platform, &config);
+        app.settings.mixer.adopted_on_quit = QuitAction::Terminate;
+        let subscription = app.events().subscribe();
+
+        app.handle_menu_command(MenuCommand::RestartMixer);
+
+        let new_pid = app.volume_mixer_process.pid;
+        assert_ne!(new_pid, old_pid);
+        assert!(!app.volume_mixer_process.is_adopted());
+        {
+            let state = platform.state();
+            assert!(!state.is_process_alive(old_pid));
+            assert!(state.is_process_alive(new_pid));
+        }
+        assert_eq!(
+            subscription.recv_timeout(Duration::ZERO),
+            Received::Event(MixerEvent::MixerRestarted { pid: new_pid })
+        );
+    }
+
+    #[test]
+    fn failed_restart_backs_off() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("menu_restart_failed");
+        let mut app = start(&platform, &config);
+
+        platform.state_mut().spawned_exit_code = Some(1);
+        app.handle_menu_command(MenuCommand::RestartMixer);
+        app.handle_timer(Timer::Supervise);
+
+        // The next attempt is only due after the initial delay.
+        assert_eq!(platform.state().spawned.len(), 2);
+        assert!(!app.supervisor.is_running());
+        assert_eq!(app.supervisor.status().restart_count, 0);
+    }
+
+    #[test]
+    fn settings_command_creates_and_opens_config_file() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("menu_settings");
+        let mut app = start(&platform, &config);
+        fs::remove_file(config.path()).unwrap();
+
+        app.handle_menu_command(MenuCommand::OpenSettings);
+
+        assert!(config.path().exists());
+        assert_eq!(
+            platform.state().opened,
+            vec![config.path().to_string_lossy().into_owned()]
+        );
+    }
+
+    #[test]
+    fn menu_commands_reach_shell() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("menu_shell");
+        let mut app = start(&platform, &config);
+
+        app.handle_menu_command(MenuCommand::ToggleMixer);
+        app.handle_menu_command(MenuCommand::OpenSoundSettings);
+        app.handle_menu_command(MenuCommand::About);
+        assert!(!platform.state().quit_posted);
+        app.handle_menu_command(MenuCommand::Exit);
+
+        let state = platform.state();
+        assert!(state.window(app.volume_mixer_process.hwnd).unwrap().visible);
+        assert_eq!(state.opened, vec![SOUND_SETTINGS_URI.to_string()]);
+        assert_eq!(state.messages[0].0, "About");
+        assert!(state.quit_posted);
+    }
+
+    #[test]
+    fn forwarded_commands_show_reload_and_quit() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("instance_commands");
+        let mut app = start(&platform, &config);
+        let hwnd = app.volume_mixer_process.hwnd;
+
+        app.handle_instance_command(InstanceCommand::Show);
+        app.handle_instance_command(InstanceCommand::Show);
+        assert!(platform.state().window(hwnd).unwrap().visible);
+        app.handle_instance_command(InstanceCommand::Toggle);
+        assert!(!platform.state().window(hwnd).unwrap().visible);
+        app.handle_instance_command(InstanceCommand::Show);
+        app.handle_instance_command(InstanceCommand::Hide);
+        assert!(!platform.state().window(hwnd).unwrap().visible);
+
+        fs::write(config.path(), "[tray]\ntooltip = \"Mixer\"\n").unwrap();
+        app.handle_instance_command(InstanceCommand::Reload);
+        assert_eq!(platform.state().tray_tooltip.as_deref(), Some("Mixer"));
+
+        app.handle_instance_command(InstanceCommand::Quit);
+        assert!(platform.state().quit_posted);
+    }
+
+    #[test]
+    fn status_command_replies_with_mixer_state() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("instance_status");
+        let mut app = start(&platform, &config);
+        app.handle_instance_command(InstanceCommand::Show);
+
+        let reply = app.handle_instance_command(InstanceCommand::Status);
+        let status = reply.as_deref().and_then(MixerStatus::decode).unwrap();
+
+        assert_eq!(status.pid, app.volume_mixer_process.pid);
+        assert_eq!(status.hwnd, app.volume_mixer_process.hwnd.0);
+        assert!(status.running && status.visible);
+        assert!(status.uptime.is_some());
+        assert_eq!(app.handle_instance_command(InstanceCommand::Hide), None);
+    }
+
+    #[test]
+    fn state_changes_are_published() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("events");
+        let mut app = start(&platform, &config);
+        let subscription = app.events().subscribe();
+        let events = app.events().clone();
+
+        app.handle_instance_command(InstanceCommand::Show);
+        app.handle_instance_command(InstanceCommand::Status);
+        app.handle_instance_command(InstanceCommand::Reload);
+        let hwnd = app.volume_mixer_process.hwnd;
+        platform.hide(hwnd);
+        app.handle_foreground_changed();
+        app.handle_menu_command(MenuCommand::RestartMixer);
+        let pid = app.volume_mixer_process.pid;
+        drop(app);
+
+        let received: Vec<_> =
+            std::iter::from_fn(|| match subscription.recv_timeout(Duration::ZERO) {
+                Received::Event(event) => Some(event),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            received,
+            vec![
+                MixerEvent::MixerShown,
+                MixerEvent::ConfigReloaded,
+                MixerEvent::MixerHidden,
+                MixerEvent::MixerRestarted { pid },
+                MixerEvent::Exiting,
+            ]
+        );
+        assert_eq!(
+            events.subscribe().recv_timeout(Duration::ZERO),
+            Received::Closed
+        );
+    }
+
+    #[test]
+    fn middle_click_opens_slider_only_when_configured() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("middle_click");
+        let mut app = start(&platform, &config);
+        let pid = app.volume_mixer_process.pid;
+        platform.state_mut().cursor = Point { x: 1800, y: 1050 };
+
+        app.handle_tray_event(TrayEvent::MiddleButtonDown);
+        app.settings.tray.middle_click = ClickAction::OpenSlider;
+        app.handle_tray_event(TrayEvent::MiddleButtonDown);
+
+        let state = platform.state();
+        assert_eq!(state.spawned.len(), 2);
+        assert_eq!(state.spawn_options[1].args, vec!["-f", "68814600"]);
+        assert!(!state.spawn_options[1].hidden);
+        assert_eq!(app.volume_mixer_process.pid, pid);
+        assert!(!state.window(app.volume_mixer_process.hwnd).unwrap().visible);
+    }
+
+    #[test]
+    fn supervisor_respawns_closed_mixer() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("supervise");
+        let mut app = start(&platform, &config);
+        app.supervisor = Supervisor::new(
+            RestartPolicy {
+                initial_delay: Duration::ZERO,
+                ..RestartPolicy::default()
+            },
+            Instant::now(),
+        );
+        let old_pid = app.volume_mixer_process.pid;
+
+        platform.state_mut().exit_process(old_pid, 0);
+        app.handle_tray_event(TrayEvent::LeftButtonDown);
+
+        let new_pid = app.volume_mixer_process.pid;
+        assert_ne!(new_pid, old_pid);
+        {
+            let state = platform.state();
+            assert!(state.is_process_alive(new_pid));
+            assert!(state.window(app.volume_mixer_process.hwnd).unwrap().visible);
+        }
+        assert_eq!(
+            app.supervisor.status(),
+            SupervisorStatus {
+                restart_count: 1,
+                last_exit: Some(ExitReason::Exited { code: 0 }),
+            }
+        );
+    }
+
+    #[test]
+    fn supervisor_does_not_adopt_opened_mixer() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("supervise_opened");
+        let mut app = start(&platform, &config);
+        app.supervisor = Supervisor::new(
+            RestartPolicy {
+                initial_delay: Duration::ZERO,
+                ..RestartPolicy::default()
+            },
+            Instant::now(),
+        );
+        app.settings.tray.middle_click = ClickAction::OpenMixer;
+        app.handle_tray_event(TrayEvent::MiddleButtonDown);
+        let opened_pid = platform.state().processes.last().unwrap().pid;
+
+        platform
+            .state_mut()
+            .exit_process(app.volume_mixer_process.pid, 0);
+        app.handle_timer(Timer::Supervise);
+        app.handle_timer(Timer::Supervise);
+
+        assert_eq!(platform.state().spawned.len(), 3);
+        assert_ne!(app.volume_mixer_process.pid, opened_pid);
+        assert!(!app.volume_mixer_process.is_adopted());
+    }
+
+    #[test]
+    fn supervisor_waits_before_restarting() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("supervise_wait");
+        let mut app = start(&platform, &config);
+        let pid = app.volume_mixer_process.pid;
+
+        platform.state_mut().exit_process(pid, 0xC000_0005);
+        app.handle_timer(Timer::Supervise);
+        app.handle_timer(Timer::Supervise);
+
+        // The first attempt is only due after the initial delay.
+        assert_eq!(platform.state().spawned.len(), 1);
+        assert!(!app.supervisor.is_running());
+    }
+
+    #[test]
+    fn invalid_config_keeps_current_settings() {
+        let platform = FakePlatform::default();
+        let config = TempConfig::new("reload_invalid");
+        let mut app = start(&platform, &config);
+
+        config.write("[tray]\ntooltip = 5\n", Duration::ZERO);
+        app.handle_timer(Timer::ConfigReload);
+
+        assert_eq!(app.settings, Settings::default());
+    }
+}
@@ -0,0 +1,297 @@
+//! Command line of the tray, `volume_mixer [options] [command]`.
+
+use crate::instance::{InstanceCommand, MixerStatus};
+use log::LevelFilter;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: volume_mixer [options] [command]
+
+Commands, sent to the running tray:
+  show            Show the mixer, starting the tray if needed (default)
+  toggle          Show or hide the mixer, starting the tray if needed
+  hide            Hide the mixer
+  reload          Reload the config file
+  restart-mixer   Restart the mixer process
+  status          Print the mixer pid, window, visibility and uptime
+  quit            Exit the tray
+
+Options:
+  --config <path>       Use this config file instead of the default one
+  --log-level <level>   One of off, error, warn, info, debug or trace
+  --no-tray             Run without a tray icon, use hotkeys and commands instead
+  --json                Print the status as JSON
+  -h, --help            Print this help
+";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cli {
+    /// `None` for a plain launch.
+    pub command: Option<InstanceCommand>,
+    pub config_path: Option<PathBuf>,
+    /// Overrides `RUST_LOG`.
+    pub log_level: Option<LevelFilter>,
+    pub no_tray: bool,
+    pub json: bool,
+    pub help: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CliError {
+    UnknownCommand(String),
+    UnknownOption(String),
+    MissingValue(&'static str),
+    InvalidLogLevel(String),
+    /// A second command, or a value given to a flag.
+    UnexpectedArgument(String),
+    /// An option for starting the tray was combined with a command for the running one.
+    StartOptionWithCommand {
+        option: &'static str,
+        command: &'static str,
+    },
+    JsonWithoutStatus,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::UnknownCommand(command) => write!(f, "Unknown command \"{}\"", command),
+            CliError::UnknownOption(option) => write!(f, "Unknown option \"{}\"", option),
+            CliError::MissingValue(option) => write!(f, "Option {} needs a value", option),
+            CliError::InvalidLogLevel(level) => write!(f, "Invalid log level \"{}\"", level),
+            CliError::UnexpectedArgument(arg) => write!(f, "Unexpected argument \"{}\"", arg),
+            CliError::StartOptionWithCommand { option, command } => write!(
+                f,
+                "Option {} only applies when starting the tray, not to \"{}\"",
+                option, command
+            ),
+            CliError::JsonWithoutStatus => write!(f, "Option --json only applies to \"status\""),
+        }
+    }
+}
+
+impl Cli {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, CliError> {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = |option: &'static str| {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(CliError::MissingValue(option))
+            };
+
+            match name {
+                "--config" => cli.config_path = Some(PathBuf::from(value("--config")?)),
+                "--log-level" => {
+                    let level = value("--log-level")?;
+                    cli.log_level = Some(
+                        level
+                            .parse()
+                            .map_err(|_| CliError::InvalidLogLevel(level))?,
+                    );
+                }
+                "--no-tray" | "--json" | "-h" | "--help" if inline_value.is_some() => {
+                    return Err(CliError::UnexpectedArgument(arg));
+                }
+                "--no-tray" => cli.no_tray = true,
+                "--json" => cli.json = true,
+                "-h" | "--help" => cli.help = true,
+                _ if name.starts_with('-') => return Err(CliError::UnknownOption(arg)),
+                _ if cli.command.is_some() => return Err(CliError::UnexpectedArgument(arg)),
+                _ => {
+                    cli.command = Some(
+                        InstanceCommand::from_name(name)
+                            .ok_or_else(|| CliError::UnknownCommand(arg.clone()))?,
+                    );
+                }
+            }
+        }
+
+        cli.validate()?;
+        Ok(cli)
+    }
+
+    /// Whether this launch becomes the tray when none is running yet.
+    pub fn starts_tray(&self) -> bool {
+        self.command.is_none_or(InstanceCommand::starts_tray)
+    }
+
+    fn validate(&self) -> Result<(), CliError> {
+        if let Some(command) = self.command.filter(|_| !self.starts_tray()) {
+            let start_option = if self.config_path.is_some() {
+                Some("--config")
+            } else if self.no_tray {
+                Some("--no-tray")
+            } else {
+                None
+            };
+            if let Some(option) = start_option {
+                return Err(CliError::StartOptionWithCommand {
+                    option,
+                    command: command.name(),
+                });
+            }
+        }
+        if self.json && self.command != Some(InstanceCommand::Status) {
+            return Err(CliError::JsonWithoutStatus);
+        }
+
+        Ok(())
+    }
+}
+
+/// Status as printed by the `status` command.
+pub fn format_status(status: &MixerStatus, json: bool) -> String {
+    if json {
+        serde_json::to_string(status).expect("status is plain data")
+    } else {
+        let uptime = status
+            .uptime
+            .map(format_duration)
+            .unwrap_or_else(|| "not running".to_string());
+        format!(
+            "Mixer pid:      {}\nMixer window:   {:#010X}\nVisible:        {}\nUptime:         {}\nRestarts:       {}",
+            status.pid,
+            status.hwnd,
+            if status.visible { "yes" } else { "no" },
+            uptime,
+            status.restart_count
+        )
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, CliError> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_commands_and_options() {
+        assert_eq!(parse(&[]), Ok(Cli::default()));
+        assert_eq!(
+            parse(&["--config=C:\\mixer.toml", "--no-tray", "toggle"]),
+            Ok(Cli {
+                command: Some(InstanceCommand::Toggle),
+                config_path: Some(PathBuf::from("C:\\mixer.toml")),
+                no_tray: true,
+                ..Cli::default()
+            })
+        );
+        assert_eq!(
+            parse(&["status", "--json", "--log-level", "Debug"]),
+            Ok(Cli {
+                command: Some(InstanceCommand::Status),
+                log_level: Some(LevelFilter::Debug),
+                json: true,
+                ..Cli::default()
+            })
+        );
+        assert_eq!(
+            parse(&["restart-mixer"]).map(|cli| cli.command),
+            Ok(Some(InstanceCommand::RestartMixer))
+        );
+        assert!(parse(&["--help"]).unwrap().help);
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert_eq!(
+            parse(&["mute"]),
+            Err(CliError::UnknownCommand("mute".to_string()))
+        );
+        assert_eq!(
+            parse(&["--verbose"]),
+            Err(CliError::UnknownOption("--verbose".to_string()))
+        );
+        assert_eq!(
+            parse(&["--config"]),
+            Err(CliError::MissingValue("--config"))
+        );
+        assert_eq!(
+            parse(&["--log-level=loud"]),
+            Err(CliError::InvalidLogLevel("loud".to_string()))
+        );
+        assert_eq!(
+            parse(&["show", "hide"]),
+            Err(CliError::UnexpectedArgument("hide".to_string()))
+        );
+        assert_eq!(
+            parse(&["--no-tray=yes"]),
+            Err(CliError::UnexpectedArgument("--no-tray=yes".to_string()))
+        );
+    }
+
+    #[test]
+    fn usage_lists_every_command() {
+        for command in InstanceCommand::ALL {
+            assert!(
+                USAGE.contains(&format!("\n  {} ", command.name())),
+                "{} is missing",
+                command.name()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_options_that_do_not_apply() {
+        assert_eq!(
+            parse(&["quit", "--no-tray"]),
+            Err(CliError::StartOptionWithCommand {
+                option: "--no-tray",
+                command: "quit",
+            })
+        );
+        assert!(parse(&["show", "--config", "mixer.toml"]).is_ok());
+        assert_eq!(parse(&["--json"]), Err(CliError::JsonWithoutStatus));
+    }
+
+    #[test]
+    fn formats_status() {
+        let status = MixerStatus {
+            pid: 4321,
+            hwnd: 0x1A2B4,
+            running: true,
+            visible: true,
+            uptime: Some(Duration::from_secs(3723)),
+            restart_count: 1,
+        };
+
+        assert_eq!(
+            format_status(&status, false),
+            "Mixer pid:      4321\nMixer window:   0x0001A2B4\nVisible:        yes\n\
+             Uptime:         1h 02m 03s\nRestarts:       1"
+        );
+        assert_eq!(
+            format_status(
+                &MixerStatus {
+                    uptime: None,
+                    ..status
+                },
+                true
+            ),
+            "{\"pid\":4321,\"hwnd\":107188,\"running\":true,\"visible\":true,\
+             \"uptime_secs\":null,\"restart_count\":1}"
+        );
+        assert_eq!(format_duration(Duration::from_secs(75)), "1m 15s");
+    }
+}
@@ -0,0 +1,1335 @@
+use crate::hotkey::Hotkey;
+use crate::platform::Point;
+use crate::window_match::{TitleRegex, WindowMatcher};
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::Spanned;
+
+#[cfg(windows)]
+const APP_DIR_NAME: &str = "volume_mixer";
+#[cfg(windows)]
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Written when the user opens the settings from the tray menu and no config file exists yet.
+const DEFAULT_CONFIG: &str = r#"# Volume Mixer tray settings, changes are applied while the tray is running.
+# Uncomment a value to change it.
+
+[mixer]
+# exec_name = "SndVol.exe"
+# How the mixer starts: "mixer" for the volume mixer of all apps, "tray-mixer"
+# for the same mixer opened like from the notification area. The window
+# settings below must match the window of the mode. Views of a single device
+# are not supported yet.
+# mode = "mixer"
+# The mixer window is the first window of the mixer process matching all of
+# the following. Any of the titles may appear in the window title, add the one
+# of your Windows language if it is missing. An empty list matches any title.
+# window_titles = ["Volume Mixer", "Lautstärkemixer", "Mikser głośności", "Mélangeur de volume", "Mezclador de volumen"]
+# Regular expression the window title must also match, an empty string
+# matches any title.
+# window_title_regex = ""
+# Class name of the window, an empty string matches any class.
+# window_class = ""
+# Only match windows that are not owned by another window.
+# window_top_level = true
+# One of "any", "visible" or "hidden". The tray starts the mixer hidden.
+# window_visibility = "any"
+# How long to wait for the mixer window to appear after the mixer started.
+# find_window_timeout_ms = 10000
+# How long the mixer gets to exit after its window is closed before it is
+# terminated, 0 terminates it right away.
+# close_timeout_ms = 2000
+# What to do with a mixer that was already running when the tray started:
+# "borrow" only remembers its pid, "take-over" also opens a handle to it so its
+# exit code is known and it can be terminated.
+# adopt = "borrow"
+# Only adopt a mixer of the session the tray runs in, not one of another user
+# signed in at the same time.
+# adopt_current_session_only = true
+# What to do with the mixer when the tray exits, one of "leave-running",
+# "hide", "close" or "terminate". Separately for a mixer the tray started and
+# one it adopted. A spawned mixer that is closed or terminated on quit also
+# ends if the tray crashes, so changing spawned_on_quit between those and the
+# others restarts a mixer the tray started. A borrowed mixer is also released
+# this way when a reload switches to another mixer, and "Restart mixer" only
+# ends it with "terminate".
+# spawned_on_quit = "terminate"
+# adopted_on_quit = "leave-running"
+
+[tray]
+# tooltip = "Custom Volume Mixer"
+# Hide the mixer when another window is focused, like the Windows flyouts.
+# auto_hide = false
+# auto_hide_grace_ms = 300
+# What a click on the tray icon does: "toggle" shows or hides the mixer,
+# "open-mixer", "open-tray-mixer" and "open-slider" start another mixer in
+# that mode, "open-slider" being the volume slider of the taskbar, "none"
+# does nothing.
+# left_click = "toggle"
+# middle_click = "none"
+
+[placement]
+# One of "corner", "tray-icon", "cursor", "centered" or "last-position".
+# strategy = "corner"
+# One of "top-left", "top-right", "bottom-left" or "bottom-right". Used by the
+# "corner" strategy and whenever another strategy has nothing to anchor to.
+# corner = "bottom-right"
+# Gap between the mixer and the screen edges, in pixels at 100% scaling.
+# margin = 0
+
+[hotkeys]
+# Global hotkeys such as "Ctrl+Alt+F12", none are registered by default.
+# toggle = "Win+Shift+V"
+# hide = ""
+
+[http]
+# REST and WebSocket endpoint on 127.0.0.1 for dashboards and scripts. Every
+# request needs "Authorization: Bearer <token>". Changes apply after restarting
+# the tray.
+# enabled = false
+# port = 8765
+# At least 16 characters, required when enabled.
+# token = ""
+
+[mqtt]
+# Publish the mixer state to an MQTT broker, e.g. for Home Assistant, and take
+# commands from "<topic_prefix>/<node_id>/set". Changes apply after restarting
+# the tray.
+# enabled = false
+# host = "localhost"
+# port = 1883
+# username = ""
+# password = ""
+# Name of this PC in topics, the computer name by default.
+# node_id = ""
+# topic_prefix = "volume_mixer"
+# Home Assistant discovery prefix, an empty string disables discovery.
+# discovery_prefix = "homeassistant"
+"#;
+
+/// `szTip` of `NOTIFYICONDATAA` holds 128 bytes including the terminating NUL.
+const MAX_TOOLTIP_LEN: usize = 127;
+
+const MIN_HTTP_TOKEN_LEN: usize = 16;
+
+/// Title of the mixer window in English, German, Polish, French and Spanish.
+const DEFAULT_WINDOW_TITLES: [&str; 5] = [
+    "Volume Mixer",
+    "Lautstärkemixer",
+    "Mikser głośności",
+    "Mélangeur de volume",
+    "Mezclador de volumen",
+];
+
+/// Class names are limited to 256 characters.
+const MAX_CLASS_NAME_LEN: usize = 256;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub mixer: MixerSettings,
+    pub tray: TraySettings,
+    pub placement: PlacementSettings,
+    pub hotkeys: HotkeySettings,
+    pub http: HttpSettings,
+    pub mqtt: MqttSettings,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MixerSettings {
+    pub exec_name: String,
+    pub mode: MixerMode,
+    /// Parts of the mixer window title, one per language.
+    pub window_titles: Vec<String>,
+    pub window_title_regex: Option<TitleRegex>,
+    pub window_class: String,
+    pub window_top_level: bool,
+    pub window_visibility: WindowVisibility,
+    /// How long a started mixer may take to show its window.
+    pub find_window_timeout: Duration,
+    /// How long the mixer may take to exit after a close request before it is terminated.
+    pub close_timeout: Duration,
+    pub adopt: AdoptMode,
+    pub adopt_current_session_only: bool,
+    pub spawned_on_quit: QuitAction,
+    pub adopted_on_quit: QuitAction,
+}
+
+/// Which window of the mixer executable to start with.
+///
+/// Device-specific views of `SndVol.exe` are deferred: they are selected by
+/// an audio endpoint id, which the tray does not enumerate, through switches
+/// that are not documented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MixerMode {
+    /// The volume mixer of all apps, where Windows places it.
+    Mixer,
+    /// The volume mixer of all apps, opened at a point like from the
+    /// notification area.
+    TrayMixer,
+    /// The volume slider of the default device, as opened from the taskbar.
+    /// It closes itself as soon as it loses focus.
+    Slider,
+}
+
+impl MixerMode {
+    /// Command line arguments of `SndVol.exe` that open the mode at `anchor`.
+    pub fn args(self, anchor: Point) -> Vec<String> {
+        match self {
+            MixerMode::Mixer => Vec::new(),
+            MixerMode::TrayMixer => vec!["-m".to_string(), packed_point(anchor)],
+            MixerMode::Slider => vec!["-f".to_string(), packed_point(anchor)],
+        }
+    }
+}
+
+/// `anchor` as `SndVol.exe` expects it, y in the high and x in the low word.
+fn packed_point(anchor: Point) -> String {
+    (((anchor.y as u16 as u32) << 16) | anchor.x as u16 as u32).to_string()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WindowVisibility {
+    Any,
+    Visible,
+    Hidden,
+}
+
+/// How to hold on to a mixer the user started before the tray.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdoptMode {
+    /// Only track the mixer by pid.
+    Borrow,
+    /// Open a handle to the mixer, as if the tray had started it.
+    TakeOver,
+}
+
+/// What happens to the mixer when the tray exits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuitAction {
+    LeaveRunning,
+    Hide,
+    /// Close the mixer window and let the mixer exit by itself.
+    Close,
+    Terminate,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraySettings {
+    pub tooltip: String,
+    pub auto_hide: AutoHideSettings,
+    pub left_click: ClickAction,
+    pub middle_click: ClickAction,
+}
+
+/// What a click on the tray icon does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClickAction {
+    /// Show or hide the mixer of the tray.
+    Toggle,
+    /// Start another mixer in a mode, shown and left to the user.
+    OpenMixer,
+    OpenTrayMixer,
+    OpenSlider,
+    #[serde(rename = "none")]
+    Nothing,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AutoHideSettings {
+    /// Hide the mixer as soon as another window is focused.
+    pub enabled: bool,
+    /// How long after showing or auto-hiding the mixer focus changes and tray
+    /// clicks are ignored.
+    pub grace_period: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlacementSettings {
+    pub strategy: PlacementStrategy,
+    pub corner: Corner,
+    /// Logical pixels, scaled with the DPI of the monitor the mixer opens on.
+    pub margin: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlacementStrategy {
+    /// Next to the tray icon, against the taskbar.
+    TrayIcon,
+    Cursor,
+    Corner,
+    Centered,
+    /// Where the user left the mixer the last time it was hidden.
+    LastPosition,
+}
+
+/// No hotkeys by default, a system-wide key combination is only taken when configured.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HotkeySettings {
+    pub toggle: Option<Hotkey>,
+    pub hide: Option<Hotkey>,
+}
+
+/// Loopback HTTP endpoint, read once at startup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpSettings {
+    pub enabled: bool,
+    pub port: u16,
+    /// Shared secret clients send as bearer token.
+    pub token: String,
+}
+
+/// MQTT bridge, read once at startup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MqttSettings {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    /// Empty to connect without credentials.
+    pub username: String,
+    pub password: String,
+    /// Empty for the computer name.
+    pub node_id: String,
+    pub topic_prefix: String,
+    /// Empty to publish no discovery configs.
+    pub discovery_prefix: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Default for MixerSettings {
+    fn default() -> Self {
+        MixerSettings {
+            exec_name: "SndVol.exe".to_string(),
+            mode: MixerMode::Mixer,
+            window_titles: DEFAULT_WINDOW_TITLES.map(str::to_string).to_vec(),
+            window_title_regex: None,
+            window_class: String::new(),
+            window_top_level: true,
+            window_visibility: WindowVisibility::Any,
+            find_window_timeout: Duration::from_millis(10_000),
+            close_timeout: Duration::from_millis(2000),
+            adopt: AdoptMode::Borrow,
+            adopt_current_session_only: true,
+            spawned_on_quit: QuitAction::Terminate,
+            adopted_on_quit: QuitAction::LeaveRunning,
+        }
+    }
+}
+
+impl MixerSettings {
+    /// Whether a spawned mixer ends with the tray even if the tray crashes.
+    /// Decided when the mixer starts, as it cannot leave the job object later.
+    pub fn spawned_ends_with_tray(&self) -> bool {
+        !matches!(
+            self.spawned_on_quit,
+            QuitAction::LeaveRunning | QuitAction::Hide
+        )
+    }
+
+    /// Matches the mixer window among the windows `owner` matches, like
+    /// those of a mixer process.
+    pub fn window_matcher(&self, owner: WindowMatcher) -> WindowMatcher {
+        let mut matchers = vec![owner];
+        if !self.window_titles.is_empty() {
+            matchers.push(WindowMatcher::any(
+                self.window_titles
+                    .iter()
+                    .map(|title| WindowMatcher::TitleContains(title.clone())),
+            ));
+        }
+        if let Some(regex) = &self.window_title_regex {
+            matchers.push(WindowMatcher::TitleRegex(regex.clone()));
+        }
+        if !self.window_class.is_empty() {
+            matchers.push(WindowMatcher::ClassName(self.window_class.clone()));
+        }
+        if self.window_top_level {
+            matchers.push(WindowMatcher::TopLevel);
+        }
+        match self.window_visibility {
+            WindowVisibility::Any => {}
+            WindowVisibility::Visible => matchers.push(WindowMatcher::Visible),
+            WindowVisibility::Hidden => matchers.push(!WindowMatcher::Visible),
+        }
+
+        WindowMatcher::all(matchers)
+    }
+}
+
+impl Default for TraySettings {
+    fn default() -> Self {
+        TraySettings {
+            tooltip: "Custom Volume Mixer".to_string(),
+            auto_hide: AutoHideSettings::default(),
+            left_click: ClickAction::Toggle,
+            middle_click: ClickAction::Nothing,
+        }
+    }
+}
+
+impl Default for AutoHideSettings {
+    fn default() -> Self {
+        AutoHideSettings {
+            enabled: false,
+            grace_period: Duration::from_millis(300),
+        }
+    }
+}
+
+impl Default for PlacementSettings {
+    fn default() -> Self {
+        PlacementSettings {
+            strategy: PlacementStrategy::Corner,
+            corner: Corner::BottomRight,
+            margin: 0,
+        }
+    }
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            enabled: false,
+            port: 8765,
+            token: String::new(),
+        }
+    }
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        MqttSettings {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            username: String::new(),
+            password: String::new(),
+            node_id: String::new(),
+            topic_prefix: "volume_mixer".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub path: Option<PathBuf>,
+    /// 1-based position of the offending value, if known.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Dotted key of the offending field, e.g. `tray.tooltip`.
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn at(source: &str, span: std::ops::Range<usize>, field: Option<&str>, message: &str) -> Self {
+        let (line, column) = line_and_column(source, span.start);
+
+        ConfigError {
+            path: None,
+            line: Some(line),
+            column: Some(column),
+            field: field.map(str::to_string),
+            message: message.to_string(),
+        }
+    }
+
+    fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}", path.display())?;
+        } else {
+            write!(f, "<config>")?;
+        }
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, ":{}:{}", line, column)?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, ": {}", field)?;
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+
+    (line, column)
+}
+
+/// `%APPDATA%\volume_mixer\config.toml`.
+#[cfg(windows)]
+pub fn default_config_path() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join(APP_DIR_NAME).join(CONFIG_FILE_NAME))
+}
+
+/// Load settings from `path`. A missing file is not an error and yields the defaults.
+pub fn load_settings(path: &Path) -> Result<Settings, ConfigError> {
+    match fs::read_to_string(path) {
+        Ok(source) => parse_settings(&source).map_err(|err| err.with_path(path)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+        Err(err) => Err(ConfigError {
+            path: Some(path.to_path_buf()),
+            line: None,
+            column: None,
+            field: None,
+            message: err.to_string(),
+        }),
+    }
+}
+
+/// Create `path` with a commented template of the default settings, unless it already exists.
+pub fn ensure_config_file(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, DEFAULT_CONFIG)
+}
+
+pub fn parse_settings(source: &str) -> Result<Settings, ConfigError> {
+    let raw: RawSettings = toml::from_str(source).map_err(|err| {
+        let span = err.span().unwrap_or(0..0);
+        ConfigError::at(source, span, None, err.message())
+    })?;
+
+    raw.validate(source)
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawSettings {
+    mixer: RawMixerSettings,
+    tray: RawTraySettings,
+    placement: RawPlacementSettings,
+    hotkeys: RawHotkeySettings,
+    http: RawHttpSettings,
+    mqtt: RawMqttSettings,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawMixerSettings {
+    exec_name: Option<Spanned<String>>,
+    mode: Option<Spanned<MixerMode>>,
+    window_titles: Option<Spanned<Vec<String>>>,
+    window_title_regex: Option<Spanned<String>>,
+    window_class: Option<Spanned<String>>,
+    window_top_level: Option<Spanned<bool>>,
+    window_visibility: Option<Spanned<WindowVisibility>>,
+    find_window_timeout_ms: Option<Spanned<u64>>,
+    close_timeout_ms: Option<Spanned<u64>>,
+    adopt: Option<Spanned<AdoptMode>>,
+    adopt_current_session_only: Option<Spanned<bool>>,
+    spawned_on_quit: Option<Spanned<QuitAction>>,
+    adopted_on_quit: Option<Spanned<QuitAction>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawTraySettings {
+    tooltip: Option<Spanned<String>>,
+    auto_hide: Option<Spanned<bool>>,
+    auto_hide_grace_ms: Option<Spanned<u64>>,
+    left_click: Option<Spanned<ClickAction>>,
+    middle_click: Option<Spanned<ClickAction>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawPlacementSettings {
+    strategy: Option<Spanned<PlacementStrategy>>,
+    corner: Option<Spanned<Corner>>,
+    margin: Option<Spanned<u32>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawHotkeySettings {
+    toggle: Option<Spanned<String>>,
+    hide: Option<Spanned<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawHttpSettings {
+    enabled: Option<Spanned<bool>>,
+    port: Option<Spanned<u16>>,
+    token: Option<Spanned<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawMqttSettings {
+    enabled: Option<Spanned<bool>>,
+    host: Option<Spanned<String>>,
+    port: Option<Spanned<u16>>,
+    username: Option<Spanned<String>>,
+    password: Option<Spanned<String>>,
+    node_id: Option<Spanned<String>>,
+    topic_prefix: Option<Spanned<String>>,
+    discovery_prefix: Option<Spanned<String>>,
+}
+
+/// Take the value of an optional field, checking it with `check`, or fall back to `default`.
+fn validated<T>(
+    source: &str,
+    field: &str,
+    value: Option<Spanned<T>>,
+    default: T,
+    check: impl FnOnce(&T) -> Result<(), String>,
+) -> Result<T, ConfigError> {
+    match value {
+        Some(value) => {
+            let span = value.span();
+            let value = value.into_inner();
+            check(&value)
+                .map_err(|message| ConfigError::at(source, span, Some(field), &message))?;
+
+            Ok(value)
+        }
+        None => Ok(default),
+    }
+}
+
+/// Parse an optional hotkey field, an empty string disabling the hotkey.
+fn hotkey(
+    source: &str,
+    field: &str,
+    value: Option<Spanned<String>>,
+    default: Option<Hotkey>,
+) -> Result<Option<Hotkey>, ConfigError> {
+    match value {
+        Some(value) if value.get_ref().trim().is_empty() => Ok(None),
+        Some(value) => value
+            .get_ref()
+            .parse::<Hotkey>()
+            .map(Some)
+            .map_err(|err| ConfigError::at(source, value.span(), Some(field), &err.to_string())),
+        None => Ok(default),
+    }
+}
+
+/// Parse an optional regular expression field, an empty string matching anything.
+fn title_regex(
+    source: &str,
+    field: &str,
+    value: Option<Spanned<String>>,
+    default: Option<TitleRegex>,
+) -> Result<Option<TitleRegex>, ConfigError> {
+    match value {
+        Some(value) if value.get_ref().is_empty() => Ok(None),
+        Some(value) => TitleRegex::new(value.get_ref())
+            .map(Some)
+            .map_err(|err| ConfigError::at(source, value.span(), Some(field), &err.to_string())),
+        None => Ok(default),
+    }
+}
+
+fn not_empty(value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        Err("must not be empty".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn port(value: &u16) -> Result<(), String> {
+    if *value == 0 {
+        Err("must be between 1 and 65535".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Topic levels to prepend, e.g. `home/volume_mixer`.
+fn topic_prefix(value: &str) -> Result<(), String> {
+    if value.contains(['+', '#']) {
+        Err("must not contain the wildcards + or #".to_string())
+    } else if value.split('/').any(str::is_empty) {
+        Err("must not have empty levels or start or end with /".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+impl RawSettings {
+    fn validate(self, source: &str) -> Result<Settings, ConfigError> {
+        let defaults = Settings::default();
+
+        let mixer = MixerSettings {
+            exec_name: validated(
+                source,
+                "mixer.exec_name",
+                self.mixer.exec_name,
+                defaults.mixer.exec_name,
+                |value| {
+                    not_empty(value)?;
+                    if value.contains(['/', '\\']) {
+                        Err("must be a file name, not a path".to_string())
+                    } else {
+                        Ok(())
+                    }
+                },
+            )?,
+            mode: validated(
+                source,
+                "mixer.mode",
+                self.mixer.mode,
+                defaults.mixer.mode,
+                |value| {
+                    // The tray would take every close of the slider for a crash.
+                    if *value == MixerMode::Slider {
+                        Err(
+                            "must not be \"slider\", which closes itself when it loses focus, \
+                             use tray.left_click = \"open-slider\" instead"
+                                .to_string(),
+                        )
+                    } else {
+                        Ok(())
+                    }
+                },
+            )?,
+            window_titles: validated(
+                source,
+                "mixer.window_titles",
+                self.mixer.window_titles,
+                defaults.mixer.window_titles,
+                |titles| {
+                    if titles.iter().any(|title| title.trim().is_empty()) {
+                        Err("must not contain empty titles".to_string())
+                    } else {
+                        Ok(())
+                    }
+                },
+            )?,
+            window_title_regex: title_regex(
+                source,
+                "mixer.window_title_regex",
+                self.mixer.window_title_regex,
+                defaults.mixer.window_title_regex,
+            )?,
+            window_class: validated(
+                source,
+                "mixer.window_class",
+                self.mixer.window_class,
+                defaults.mixer.window_class,
+                |value| {
+                    if value.chars().count() > MAX_CLASS_NAME_LEN {
+                        Err(format!("must be at most {} characters", MAX_CLASS_NAME_LEN))
+                    } else {
+                        Ok(())
+                    }
+                },
+            )?,
+            window_top_level: validated(
+                source,
+                "mixer.window_top_level",
+                self.mixer.window_top_level,
+                defaults.mixer.window_top_level,
+                |_| Ok(()),
+            )?,
+            window_visibility: validated(
+                source,
+                "mixer.window_visibility",
+                self.mixer.window_visibility,
+                defaults.mixer.window_visibility,
+                |_| Ok(()),
+            )?,
+            find_window_timeout: validated(
+                source,
+                "mixer.find_window_timeout_ms",
+                self.mixer.find_window_timeout_ms,
+                defaults.mixer.find_window_timeout.as_millis() as u64,
+                |value| {
+                    if *value > 120_000 {
+                        Err("must be at most 120000".to_string())
+                    } else {
+                        Ok(())
+                    }
+                },
+            )
+            .map(Duration::from_millis)?,
+            close_timeout: validated(
+                source,
+                "mixer.close_timeout_ms",
+                self.mixer.close_timeout_ms,
+                defaults.mixer.close_timeout.as_millis() as u64,
+                |value| {
+                    if *value > 30_000 {
+                        Err("must be at most 30000".to_string())
+                    } else {
+                        Ok(())
+                    }
+                },
+            )
+            .map(Duration::from_millis)?,
+            adopt: validated(
+                source,
+                "mixer.adopt",
+                self.mixer.adopt,
+                defaults.mixer.adopt,
+                |_| Ok(()),
+            )?,
+            adopt_current_session_only: validated(
+                source,
+                "mixer.adopt_current_session_only",
+                self.mixer.adopt_current_session_only,
+                defaults.mixer.adopt_current_session_only,
+                |_| Ok(()),
+            )?,
+            spawned_on_quit: validated(
+                source,
+                "mixer.spawned_on_quit",
+                self.mixer.spawned_on_quit,
+                defaults.mixer.spawned_on_quit,
+                |_| Ok(()),
+            )?,
+            adopted_on_quit: validated(
+                source,
+                "mixer.adopted_on_quit",
+                self.mixer.adopted_on_quit,
+                defaults.mixer.adopted_on_quit,
+                |_| Ok(()),
+            )?,
+        };
+
+        let tray = TraySettings {
+            tooltip: validated(
+                source,
+                "tray.tooltip",
+                self.tray.tooltip,
+                defaults.tray.tooltip,
+                |value| {
+                    if !value.is_ascii() {
+                        Err("must contain only ASCII characters".to_string())
+                    } else if value.len() > MAX_TOOLTIP_LEN {
+                        Err(format!("must be at most {} characters", MAX_TOOLTIP_LEN))
+                    } else {
+                        Ok(())
+                    }
+                },
+            )?,
+            auto_hide: AutoHideSettings {
+                enabled: validated(
+                    source,
+                    "tray.auto_hide",
+                    self.tray.auto_hide,
+                    defaults.tray.auto_hide.enabled,
+                    |_| Ok(()),
+                )?,
+                grace_period: validated(
+                    source,
+                    "tray.auto_hide_grace_ms",
+                    self.tray.auto_hide_grace_ms,
+                    defaults.tray.auto_hide.grace_period.as_millis() as u64,
+                    |value| {
+                        if *value > 5_000 {
+                            Err("must be at most 5000".to_string())
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .map(Duration::from_millis)?,
+            },
+            left_click: validated(
+                source,
+                "tray.left_click",
+                self.tray.left_click,
+                defaults.tray.left_click,
+                |_| Ok(()),
+            )?,
+            middle_click: validated(
+                source,
+                "tray.middle_click",
+                self.tray.middle_click,
+                defaults.tray.middle_click,
+                |_| Ok(()),
+            )?,
+        };
+
+        let placement = PlacementSettings {
+            strategy: validated(
+                source,
+                "placement.strategy",
+                self.placement.strategy,
+                defaults.placement.strategy,
+                |_| Ok(()),
+            )?,
+            corner: validated(
+                source,
+                "placement.corner",
+                self.placement.corner,
+                defaults.placement.corner,
+                |_| Ok(()),
+            )?,
+            margin: validated(
+                source,
+                "placement.margin",
+                self.placement.margin,
+                defaults.placement.margin,
+                |value| {
+                    if *value > 200 {
+                        Err("must be at most 200".to_string())
+                    } else {
+                        Ok(())
+                    }
+                },
+            )?,
+        };
+
+        let hide_span = self.hotkeys.hide.as_ref().map(Spanned::span);
+        let hotkeys = HotkeySettings {
+            toggle: hotkey(
+                source,
+                "hotkeys.toggle",
+                self.hotkeys.toggle,
+                defaults.hotkeys.toggle,
+            )?,
+            hide: hotkey(
+                source,
+                "hotkeys.hide",
+                self.hotkeys.hide,
+                defaults.hotkeys.hide,
+            )?,
+        };
+        if hotkeys.hide.is_some() && hotkeys.hide == hotkeys.toggle {
+            return Err(ConfigError::at(
+                source,
+                hide_span.unwrap_or(0..0),
+                Some("hotkeys.hide"),
+                "must differ from hotkeys.toggle",
+            ));
+        }
+
+        let enabled_span = self.http.enabled.as_ref().map(Spanned::span);
+        let http = HttpSettings {
+            enabled: validated(
+                source,
+                "http.enabled",
+                self.http.enabled,
+                defaults.http.enabled,
+                |_| Ok(()),
+            )?,
+            port: validated(
+                source,
+                "http.port",
+                self.http.port,
+                defaults.http.port,
+                port,
+            )?,
+            token: validated(
+                source,
+                "http.token",
+                self.http.token,
+                defaults.http.token,
+                |value| {
+                    if value.chars().any(|c| !c.is_ascii_graphic()) {
+                        Err(
+                            "must contain only printable ASCII characters without spaces"
+                                .to_string(),
+                        )
+                    } else {
+                        Ok(())
+                    }
+                },
+            )?,
+        };
+        if http.enabled && http.token.len() < MIN_HTTP_TOKEN_LEN {
+            return Err(ConfigError::at(
+                source,
+                enabled_span.unwrap_or(0..0),
+                Some("http.token"),
+                &format!(
+                    "must be at least {} characters when http.enabled is set",
+                    MIN_HTTP_TOKEN_LEN
+                ),
+            ));
+        }
+
+        let mqtt = MqttSettings {
+            enabled: validated(
+                source,
+                "mqtt.enabled",
+                self.mqtt.enabled,
+                defaults.mqtt.enabled,
+                |_| Ok(()),
+            )?,
+            host: validated(
+                source,
+                "mqtt.host",
+                self.mqtt.host,
+                defaults.mqtt.host,
+                |value| not_empty(value),
+            )?,
+            port: validated(
+                source,
+                "mqtt.port",
+                self.mqtt.port,
+                defaults.mqtt.port,
+                port,
+            )?,
+            username: validated(
+                source,
+                "mqtt.username",
+                self.mqtt.username,
+                defaults.mqtt.username,
+                |_| Ok(()),
+            )?,
+            password: validated(
+                source,
+                "mqtt.password",
+                self.mqtt.password,
+                defaults.mqtt.password,
+                |_| Ok(()),
+            )?,
+            node_id: validated(
+                source,
+                "mqtt.node_id",
+                self.mqtt.node_id,
+                defaults.mqtt.node_id,
+                |value| {
+                    // Home Assistant allows only these in discovery topics.
+                    if value
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    {
+                        Ok(())
+                    } else {
+                        Err("must contain only letters, digits, _ and -".to_string())
+                    }
+                },
+            )?,
+            topic_prefix: validated(
+                source,
+                "mqtt.topic_prefix",
+                self.mqtt.topic_prefix,
+                defaults.mqtt.topic_prefix,
+                |value| topic_prefix(value),
+            )?,
+            discovery_prefix: validated(
+                source,
+                "mqtt.discovery_prefix",
+                self.mqtt.discovery_prefix,
+                defaults.mqtt.discovery_prefix,
+                |value| {
+                    if value.is_empty() {
+                        Ok(())
+                    } else {
+                        topic_prefix(value)
+                    }
+                },
+            )?,
+        };
+
+        Ok(Settings {
+            mixer,
+            tray,
+            placement,
+            hotkeys,
+            http,
+            mqtt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_yields_defaults() {
+        assert_eq!(parse_settings("").unwrap(), Settings::default());
+    }
+
+    #[test]
+    fn default_config_template_yields_defaults() {
+        assert_eq!(parse_settings(DEFAULT_CONFIG).unwrap(), Settings::default());
+    }
+
+    #[test]
+    fn parses_all_fields() {
+        let settings = parse_settings(
+            r##"
+            [mixer]
+            exec_name = "Mixer.exe"
+            mode = "tray-mixer"
+            window_titles = ["Mikser", "Mixer"]
+            window_title_regex = "^(Mikser|Mixer)$"
+            window_class = "#32770"
+            window_top_level = false
+            window_visibility = "hidden"
+            find_window_timeout_ms = 30000
+            close_timeout_ms = 0
+            adopt = "take-over"
+            adopt_current_session_only = false
+            spawned_on_quit = "hide"
+            adopted_on_quit = "close"
+
+            [tray]
+            tooltip = "Mixer"
+            auto_hide = true
+            auto_hide_grace_ms = 500
+            left_click = "open-mixer"
+            middle_click = "none"
+
+            [placement]
+            strategy = "centered"
+            corner = "top-left"
+            margin = 8
+
+            [hotkeys]
+            toggle = ""
+            hide = "Ctrl+Alt+F12"
+
+            [http]
+            enabled = true
+            port = 9000
+            token = "0123456789abcdef"
+
+            [mqtt]
+            enabled = true
+            host = "broker.lan"
+            port = 8883
+            username = "mixer"
+            password = "secret"
+            node_id = "office-pc"
+            topic_prefix = "home/mixer"
+            discovery_prefix = ""
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(settings.mixer.exec_name, "Mixer.exe");
+        assert_eq!(settings.mixer.mode, MixerMode::TrayMixer);
+        assert_eq!(settings.mixer.window_titles, vec!["Mikser", "Mixer"]);
+        assert_eq!(
+            settings.mixer.window_title_regex,
+            Some(TitleRegex::new("^(Mikser|Mixer)$").unwrap())
+        );
+        assert_eq!(settings.mixer.window_class, "#32770");
+        assert!(!settings.mixer.window_top_level);
+        assert_eq!(settings.mixer.window_visibility, WindowVisibility::Hidden);
+        assert_eq!(
+            settings.mixer.find_window_timeout,
+            Duration::from_millis(30_000)
+        );
+        assert_eq!(settings.mixer.close_timeout, Duration::ZERO);
+        assert_eq!(settings.mixer.adopt, AdoptMode::TakeOver);
+        assert!(!settings.mixer.adopt_current_session_only);
+        assert_eq!(settings.mixer.spawned_on_quit, QuitAction::Hide);
+        assert_eq!(settings.mixer.adopted_on_quit, QuitAction::Close);
+        assert_eq!(settings.tray.tooltip, "Mixer");
+        assert!(settings.tray.auto_hide.enabled);
+        assert_eq!(
+            settings.tray.auto_hide.grace_period,
+            Duration::from_millis(500)
+        );
+        assert_eq!(settings.tray.left_click, ClickAction::OpenMixer);
+        assert_eq!(settings.tray.middle_click, ClickAction::Nothing);
+        assert_eq!(settings.placement.strategy, PlacementStrategy::Centered);
+        assert_eq!(settings.placement.corner, Corner::TopLeft);
+        assert_eq!(settings.placement.margin, 8);
+        assert_eq!(settings.hotkeys.toggle, None);
+        assert_eq!(
+            settings.hotkeys.hide.map(|hotkey| hotkey.to_string()),
+            Some("Ctrl+Alt+F12".to_string())
+        );
+        assert_eq!(
+            settings.http,
+            HttpSettings {
+                enabled: true,
+                port: 9000,
+                token: "0123456789abcdef".to_string(),
+            }
+        );
+        assert_eq!(
+            settings.mqtt,
+            MqttSettings {
+                enabled: true,
+                host: "broker.lan".to_string(),
+                port: 8883,
+                username: "mixer".to_string(),
+                password: "secret".to_string(),
+                node_id: "office-pc".to_string(),
+                topic_prefix: "home/mixer".to_string(),
+                discovery_prefix: String::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_empty_window_titles() {
+        let err = parse_settings("[mixer]\nwindow_titles = [\"Mixer\", \" \"]\n").unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("mixer.window_titles"));
+    }
+
+    #[test]
+    fn modes_open_at_packed_anchor() {
+        let anchor = Point { x: 1800, y: 1050 };
+
+        assert!(MixerMode::Mixer.args(anchor).is_empty());
+        assert_eq!(MixerMode::TrayMixer.args(anchor), vec!["-m", "68814600"]);
+        assert_eq!(
+            MixerMode::Slider.args(Point { x: -10, y: 2 }),
+            vec!["-f", "196598"]
+        );
+    }
+
+    #[test]
+    fn rejects_slider_as_managed_mode() {
+        let err = parse_settings("[mixer]\nmode = \"slider\"\n").unwrap_err();
+        assert_eq!(err.line, Some(2));
+        assert_eq!(err.field.as_deref(), Some("mixer.mode"));
+
+        let settings = parse_settings("[tray]\nmiddle_click = \"open-slider\"\n").unwrap();
+        assert_eq!(settings.tray.middle_click, ClickAction::OpenSlider);
+    }
+
+    #[test]
+    fn window_matcher_skips_unset_criteria() {
+        let mut settings = MixerSettings {
+            window_titles: vec!["Mikser".to_string()],
+            window_top_level: false,
+            ..MixerSettings::default()
+        };
+        assert_eq!(
+            settings
+                .window_matcher(WindowMatcher::OwnerPid(42))
+                .to_string(),
+            "pid 42 and title containing \"Mikser\""
+        );
+
+        settings.window_titles.clear();
+        settings.window_class = "#32770".to_string();
+        settings.window_top_level = true;
+        settings.window_title_regex = Some(TitleRegex::new("^Mixer").unwrap());
+        settings.window_visibility = WindowVisibility::Hidden;
+        assert_eq!(
+            settings
+                .window_matcher(WindowMatcher::ExeName("SndVol.exe".to_string()))
+                .to_string(),
+            "exe \"SndVol.exe\" and title matching /^Mixer/ and class \"#32770\" and top-level \
+             and not visible"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_mqtt_topics() {
+        for (source, field) in [
+            ("[mqtt]\nnode_id = \"my pc\"\n", "mqtt.node_id"),
+            ("[mqtt]\ntopic_prefix = \"\"\n", "mqtt.topic_prefix"),
+            ("[mqtt]\ntopic_prefix = \"home/\"\n", "mqtt.topic_prefix"),
+            (
+                "[mqtt]\ndiscovery_prefix = \"ha/#\"\n",
+                "mqtt.discovery_prefix",
+            ),
+        ] {
+            let err = parse_settings(source).unwrap_err();
+            assert_eq!(err.line, Some(2), "{}", source);
+            assert_eq!(err.field.as_deref(), Some(field), "{}", source);
+        }
+    }
+
+    #[test]
+    fn enabled_http_needs_token() {
+        let err = parse_settings(
+            "[http]
+enabled = true
+token = \"short\"\n",
+        )
+        .unwrap_err();
+        assert_eq!(err.line, Some(2));
+        assert_eq!(err.field.as_deref(), Some("http.token"));
+
+        let err = parse_settings(
+            "[http]
+port = 0
+",
+        )
+        .unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("http.port"));
+        assert!(parse_settings(
+            "[http]
+token = \"short\"\n"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn reports_line_and_field_of_invalid_value() {
+        let err = parse_settings("[tray]\n\ntooltip = \"\u{e9}t\u{e9}\"\n").unwrap_err();
+
+        assert_eq!(err.line, Some(3));
+        assert_eq!(err.column, Some(11));
+        assert_eq!(err.field.as_deref(), Some("tray.tooltip"));
+    }
+
+    #[test]
+    fn reports_line_of_syntax_and_type_errors() {
+        let err = parse_settings("[mixer]\nfind_window_timeout_ms = \"four\"\n").unwrap_err();
+        assert_eq!(err.line, Some(2));
+
+        let err = parse_settings("[placement]\ncorner = \"middle\"\n").unwrap_err();
+        assert_eq!(err.line, Some(2));
+
+        let err = parse_settings("[tray]\ncolour = 1\n").unwrap_err();
+        assert_eq!(err.line, Some(2));
+        assert!(err.message.contains("colour"));
+
+        let err = parse_settings("[mixer]\nwindow_title_regex = \"Mixer (\"\n").unwrap_err();
+        assert_eq!(err.line, Some(2));
+        assert_eq!(err.field.as_deref(), Some("mixer.window_title_regex"));
+    }
+
+    #[test]
+    fn reports_invalid_and_conflicting_hotkeys() {
+        let err = parse_settings("[hotkeys]\ntoggle = \"Ctrl+Q+W\"\n").unwrap_err();
+        assert_eq!(err.line, Some(2));
+        assert_eq!(err.field.as_deref(), Some("hotkeys.toggle"));
+
+        let err = parse_settings("[hotkeys]\ntoggle = \"Win+Shift+V\"\nhide = \"Shift+Win+V\"\n")
+            .unwrap_err();
+        assert_eq!(err.line, Some(3));
+        assert_eq!(err.field.as_deref(), Some("hotkeys.hide"));
+    }
+
+    #[test]
+    fn missing_file_yields_defaults() {
+        let path = env::temp_dir().join("volume_mixer_missing_config.toml");
+
+        assert_eq!(load_settings(&path).unwrap(), Settings::default());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::config::{
+    load_settings, AutoHideSettings, ConfigError, HotkeySettings, PlacementSettings, Settings,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What has to be done to move the running tray from one set of settings to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReloadAction {
+    UpdateTooltip(String),
+    UpdateAutoHide(AutoHideSettings),
+    UpdatePlacement(PlacementSettings),
+    UpdateHotkeys(HotkeySettings),
+    /// The mixer executable changed, so the running mixer cannot be kept.
+    RestartMixer,
+    /// Whether a spawned mixer ends with the tray changed, which only a
+    /// mixer started with the new settings follows.
+    RespawnMixer,
+}
+
+/// Settings that only matter when the mixer is (re)started or its window is
+/// looked up again need no action, the new values are simply used next time.
+pub fn diff_settings(old: &Settings, new: &Settings) -> Vec<ReloadAction> {
+    let mut actions = Vec::new();
+
+    if old.tray.tooltip != new.tray.tooltip {
+        actions.push(ReloadAction::UpdateTooltip(new.tray.tooltip.clone()));
+    }
+    if old.tray.auto_hide != new.tray.auto_hide {
+        actions.push(ReloadAction::UpdateAutoHide(new.tray.auto_hide.clone()));
+    }
+    if old.placement != new.placement {
+        actions.push(ReloadAction::UpdatePlacement(new.placement.clone()));
+    }
+    if old.hotkeys != new.hotkeys {
+        actions.push(ReloadAction::UpdateHotkeys(new.hotkeys.clone()));
+    }
+    if old.mixer.exec_name != new.mixer.exec_name || old.mixer.mode != new.mixer.mode {
+        actions.push(ReloadAction::RestartMixer);
+    } else if old.mixer.spawned_ends_with_tray() != new.mixer.spawned_ends_with_tray() {
+        actions.push(ReloadAction::RespawnMixer);
+    }
+
+    actions
+}
+
+/// Detects changes of the config file by polling its modification time.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> ConfigWatcher {
+        ConfigWatcher {
+            path: path.to_path_buf(),
+            last_modified: Self::modified(path),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reload the settings if the file was modified, created or removed since the last poll.
+    pub fn poll(&mut self) -> Option<Result<Settings, ConfigError>> {
+        let modified = Self::modified(&self.path);
+        if modified == self.last_modified {
+            return None;
+        }
+
+        self.last_modified = modified;
+        Some(load_settings(&self.path))
+    }
+
+    /// Reload the settings whether or not the file changed.
+    pub fn reload(&mut self) -> Result<Settings, ConfigError> {
+        self.last_modified = Self::modified(&self.path);
+        load_settings(&self.path)
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Corner, MixerMode, QuitAction};
+    use crate::fake_platform::TempConfig;
+    use std::time::Duration;
+
+    #[test]
+    fn no_actions_for_equal_settings() {
+        let settings = Settings::default();
+
+        assert!(diff_settings(&settings, &settings.clone()).is_empty());
+    }
+
+    #[test]
+    fn tray_changes_keep_mixer() {
+        let old = Settings::default();
+        let mut new = old.clone();
+        new.tray.tooltip = "Mixer".to_string();
+        new.placement.corner = Corner::TopLeft;
+        new.mixer.window_titles = vec!["Mikser".to_string()];
+        new.hotkeys.toggle = Some("Win+Shift+V".parse().unwrap());
+
+        assert_eq!(
+            diff_settings(&old, &new),
+            vec![
+                ReloadAction::UpdateTooltip("Mixer".to_string()),
+                ReloadAction::UpdatePlacement(new.placement.clone()),
+                ReloadAction::UpdateHotkeys(new.hotkeys.clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn exec_change_restarts_mixer() {
+        let old = Settings::default();
+        let mut new = old.clone();
+        new.mixer.exec_name = "Mixer.exe".to_string();
+
+        assert_eq!(diff_settings(&old, &new), vec![ReloadAction::RestartMixer]);
+
+        let mut new = old.clone();
+        new.mixer.mode = MixerMode::TrayMixer;
+        assert_eq!(diff_settings(&old, &new), vec![ReloadAction::RestartMixer]);
+    }
+
+    #[test]
+    fn leaving_mixer_behind_respawns_it() {
+        let old = Settings::default();
+        let mut new = old.clone();
+        new.mixer.spawned_on_quit = QuitAction::Close;
+        assert!(diff_settings(&old, &new).is_empty());
+
+        new.mixer.spawned_on_quit = QuitAction::LeaveRunning;
+        assert_eq!(diff_settings(&old, &new), vec![ReloadAction::RespawnMixer]);
+
+        new.mixer.exec_name = "Mixer.exe".to_string();
+        assert_eq!(diff_settings(&old, &new), vec![ReloadAction::RestartMixer]);
+    }
+
+    #[test]
+    fn poll_reports_only_changes() {
+        let config = TempConfig::new("watch");
+        config.write("", Duration::from_secs(60));
+        let mut watcher = ConfigWatcher::new(config.path());
+
+        assert!(watcher.poll().is_none());
+
+        config.write("[tray]\ntooltip = \"Mixer\"\n", Duration::ZERO);
+        let settings = watcher.poll().unwrap().unwrap();
+        assert_eq!(settings.tray.tooltip, "Mixer");
+        assert!(watcher.poll().is_none());
+
+        fs::remove_file(config.path()).unwrap();
+        assert_eq!(watcher.poll().unwrap().unwrap(), Settings::default());
+    }
+}
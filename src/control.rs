@@ -0,0 +1,539 @@
+//! Control API for external tools such as Stream Deck or AutoHotkey scripts.
+//! Requests are JSON lines like `{"v":1,"id":7,"cmd":"toggle"}` sent over a
+//! named pipe (a Unix domain socket off Windows), each answered by one line.
+//!
+//! Every signed-in user runs a tray of their own, so the pipe is named after
+//! the session of the tray, e.g. `\\.\pipe\VolumeMixerTray-1` in session 1.
+//! A script of the same user finds the session id of its own process, e.g.
+//! with `(Get-Process -Id $PID).SessionId` in PowerShell.
+//!
+//! `{"cmd":"subscribe"}` turns the connection into a stream of `MixerEvent`s
+//! like `{"v":1,"event":"mixer-shown"}`, in the order they happened. A client
+//! that reads too slowly gets `{"v":1,"event":"lagged","missed":3}` in place
+//! of the events it missed. Requests sent after subscribing are ignored.
+
+use crate::error::Error;
+use crate::events::{EventBus, MixerEvent, Received, Subscription};
+use crate::instance::{InstanceChannel, InstanceCommand, MixerStatus};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Version of the request and response schema, `v` in both.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Name of the control pipe, followed by the session id of the tray.
+const PIPE_NAME_PREFIX: &str = r"\\.\pipe\VolumeMixerTray-";
+
+/// Longer requests are rejected instead of being buffered.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// How long an event stream waits for an event before waiting again.
+const EVENT_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    InvalidJson,
+    /// Valid JSON, but not an object with a string `cmd`.
+    InvalidRequest,
+    UnsupportedVersion,
+    UnknownCommand,
+    RequestTooLong,
+    /// The tray did not take the command or sent no valid reply.
+    TrayUnavailable,
+    /// The HTTP request lacked the token.
+    Unauthorized,
+    /// The HTTP method does not apply to the path.
+    MethodNotAllowed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ResponseError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Response {
+    pub v: u32,
+    /// `id` of the request, left out when the request had none.
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub id: Value,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ResponseError>,
+}
+
+impl Response {
+    pub fn ok(id: Value, result: Option<Value>) -> Response {
+        Response {
+            v: PROTOCOL_VERSION,
+            id,
+            ok: true,
+            result,
+            error: None,
+        }
+    }
+
+    pub fn error(id: Value, code: ErrorCode, message: impl Into<String>) -> Response {
+        Response {
+            v: PROTOCOL_VERSION,
+            id,
+            ok: false,
+            result: None,
+            error: Some(ResponseError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Request {
+    Command(InstanceCommand),
+    Subscribe,
+}
+
+/// One line of an event stream.
+#[derive(Serialize)]
+pub struct EventLine {
+    v: u32,
+    #[serde(flatten)]
+    event: StreamedEvent,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum StreamedEvent {
+    Mixer(MixerEvent),
+    Lagged { event: &'static str, missed: u64 },
+}
+
+/// Named pipe of the tray running in session `session_id`.
+pub fn pipe_name(session_id: u32) -> String {
+    format!("{}{}", PIPE_NAME_PREFIX, session_id)
+}
+
+/// Accepts the connections of external tools.
+pub trait ControlListener {
+    /// Served on a thread of its own.
+    type Stream: Read + Write + Send + 'static;
+
+    /// Wait for the next client.
+    fn accept(&mut self) -> io::Result<Self::Stream>;
+}
+
+#[cfg(unix)]
+impl ControlListener for std::os::unix::net::UnixListener {
+    type Stream = std::os::unix::net::UnixStream;
+
+    fn accept(&mut self) -> io::Result<Self::Stream> {
+        std::os::unix::net::UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+/// The `id` and what a request asks for, or the response rejecting it.
+fn parse_request(request: &[u8]) -> Result<(Value, Request), Response> {
+    let request: Value = match serde_json::from_slice(request) {
+        Ok(request) => request,
+        Err(err) => {
+            return Err(Response::error(
+                Value::Null,
+                ErrorCode::InvalidJson,
+                err.to_string(),
+            ))
+        }
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+
+    let Some(name) = request.get("cmd").and_then(Value::as_str) else {
+        return Err(Response::error(
+            id,
+            ErrorCode::InvalidRequest,
+            "Expected an object with a \"cmd\"",
+        ));
+    };
+    match request.get("v").map(Value::as_u64) {
+        None => {}
+        Some(Some(version)) if version == PROTOCOL_VERSION as u64 => {}
+        Some(_) => {
+            return Err(Response::error(
+                id,
+                ErrorCode::UnsupportedVersion,
+                format!("Only version {} is supported", PROTOCOL_VERSION),
+            ))
+        }
+    }
+
+    if name == "subscribe" {
+        return Ok((id, Request::Subscribe));
+    }
+    match InstanceCommand::from_name(name) {
+        Some(command) => Ok((id, Request::Command(command))),
+        None => Err(Response::error(
+            id,
+            ErrorCode::UnknownCommand,
+            format!("Unknown command \"{}\"", name),
+        )),
+    }
+}
+
+/// Run `command` with `dispatch` and answer the request `id`.
+pub fn run_command(
+    id: Value,
+    command: InstanceCommand,
+    dispatch: impl FnOnce(InstanceCommand) -> Result<Option<String>, Error>,
+) -> Response {
+    match dispatch(command) {
+        Ok(reply) if command == InstanceCommand::Status => {
+            match reply.as_deref().and_then(MixerStatus::decode) {
+                Some(status) => Response::ok(id, serde_json::to_value(status).ok()),
+                None => Response::error(
+                    id,
+                    ErrorCode::TrayUnavailable,
+                    Error::InvalidReply.to_string(),
+                ),
+            }
+        }
+        Ok(_) => Response::ok(id, None),
+        Err(err) => Response::error(id, ErrorCode::TrayUnavailable, err.to_string()),
+    }
+}
+
+/// Serve every client of `listener` on a thread of its own until accepting
+/// fails, so an idle client does not hold up the others. Each thread
+/// forwards commands through a channel returned by `channel`.
+pub fn serve<L, C>(
+    listener: &mut L,
+    events: &EventBus,
+    channel: impl Fn() -> C + Send + Sync + 'static,
+) -> io::Error
+where
+    L: ControlListener,
+    C: InstanceChannel,
+{
+    let channel = Arc::new(channel);
+    loop {
+        let stream = match listener.accept() {
+            Ok(stream) => stream,
+            Err(err) => return err,
+        };
+        let events = events.clone();
+        let channel = Arc::clone(&channel);
+        let spawned = std::thread::Builder::new()
+            .name("control-client".to_string())
+            .spawn(move || serve_client(stream, &events, channel()));
+        if let Err(err) = spawned {
+            error!("Could not serve control client: {}", err);
+        }
+    }
+}
+
+/// Serve `listener` on a worker thread, forwarding commands through the
+/// channels returned by `channel`, which are created on the thread of each
+/// client so their replies are received there.
+pub fn spawn_server<L, C>(
+    mut listener: L,
+    events: EventBus,
+    channel: impl Fn() -> C + Send + Sync + 'static,
+) -> io::Result<JoinHandle<()>>
+where
+    L: ControlListener + Send + 'static,
+    C: InstanceChannel,
+{
+    std::thread::Builder::new()
+        .name("control".to_string())
+        .spawn(move || {
+            let err = serve(&mut listener, &events, channel);
+            error!("Control server stopped: {}", err);
+        })
+}
+
+/// Answer the requests of a client, then stream events to it if it subscribes.
+fn serve_client<S: Read + Write, C: InstanceChannel>(
+    mut stream: S,
+    events: &EventBus,
+    mut channel: C,
+) {
+    match serve_connection(&mut stream, events, &mut |command| channel.forward(command)) {
+        Ok(Some(subscription)) => stream_events(stream, subscription),
+        Ok(None) => {}
+        Err(err) => debug!("Control connection closed: {}", err),
+    }
+}
+
+/// Answer the requests of a client until it disconnects, or until it
+/// subscribes and the subscription is returned.
+fn serve_connection<S: Read + Write>(
+    stream: &mut S,
+    events: &EventBus,
+    dispatch: &mut impl FnMut(InstanceCommand) -> Result<Option<String>, Error>,
+) -> io::Result<Option<Subscription>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read = reader
+            .by_ref()
+            .take(MAX_REQUEST_LEN as u64 + 1)
+            .read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let too_long = line.len() > MAX_REQUEST_LEN && line.last() != Some(&b'\n');
+        let response = if too_long {
+            Response::error(
+                Value::Null,
+                ErrorCode::RequestTooLong,
+                format!("Requests are limited to {} bytes", MAX_REQUEST_LEN),
+            )
+        } else if line.trim_ascii().is_empty() {
+            continue;
+        } else {
+            match parse_request(line.trim_ascii()) {
+                Ok((id, Request::Command(command))) => run_command(id, command, &mut *dispatch),
+                Ok((id, Request::Subscribe)) => {
+                    // Subscribe first, so no event is lost after the answer.
+                    let subscription = events.subscribe();
+                    write_line(reader.get_mut(), &Response::ok(id, None))?;
+                    return Ok(Some(subscription));
+                }
+                Err(response) => response,
+            }
+        };
+        write_line(reader.get_mut(), &response)?;
+
+        // The rest of the line cannot be told apart from the next request.
+        if too_long {
+            return Ok(None);
+        }
+    }
+}
+
+/// Write the events of `subscription` to `stream` until either ends.
+fn stream_events<S: Write>(mut stream: S, subscription: Subscription) {
+    while let Some(line) = next_event_line(&subscription) {
+        if let Err(err) = write_line(&mut stream, &line) {
+            debug!("Event subscriber disconnected: {}", err);
+            return;
+        }
+    }
+}
+
+/// Wait for the next event of `subscription`, `None` once the bus closed.
+pub fn next_event_line(subscription: &Subscription) -> Option<EventLine> {
+    loop {
+        let event = match subscription.recv_timeout(EVENT_WAIT) {
+            Received::Event(event) => StreamedEvent::Mixer(event),
+            Received::Lagged { missed } => StreamedEvent::Lagged {
+                event: "lagged",
+                missed,
+            },
+            Received::Timeout => continue,
+            Received::Closed => return None,
+        };
+
+        return Some(EventLine {
+            v: PROTOCOL_VERSION,
+            event,
+        });
+    }
+}
+
+fn write_line<W: Write>(writer: &mut W, value: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn respond(request: &str) -> Value {
+        let (id, request) = match parse_request(request.as_bytes()) {
+            Ok(request) => request,
+            Err(response) => return serde_json::to_value(response).unwrap(),
+        };
+        let Request::Command(command) = request else {
+            panic!("not a command: {:?}", request);
+        };
+        let response = run_command(id, command, |command| match command {
+            InstanceCommand::Status => Ok(Some(
+                MixerStatus {
+                    pid: 42,
+                    hwnd: 0x1234,
+                    running: true,
+                    visible: false,
+                    uptime: Some(Duration::from_secs(90)),
+                    restart_count: 0,
+                }
+                .encode(),
+            )),
+            InstanceCommand::Quit => Err(Error::InvalidReply),
+            _ => Ok(None),
+        });
+        serde_json::to_value(response).unwrap()
+    }
+
+    #[test]
+    fn answers_commands() {
+        assert_eq!(
+            respond(r#"{"v":1,"id":"a","cmd":"toggle"}"#),
+            serde_json::json!({"v": 1, "id": "a", "ok": true})
+        );
+        assert_eq!(
+            respond(r#"{"cmd":"status","id":3}"#),
+            serde_json::json!({
+                "v": 1,
+                "id": 3,
+                "ok": true,
+                "result": {
+                    "pid": 42,
+                    "hwnd": 4660,
+                    "running": true,
+                    "visible": false,
+                    "uptime_secs": 90,
+                    "restart_count": 0,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn pipe_is_named_after_session() {
+        assert_eq!(pipe_name(1), r"\\.\pipe\VolumeMixerTray-1");
+    }
+
+    #[test]
+    fn reports_error_codes() {
+        let code = |request: &str| respond(request)["error"]["code"].clone();
+
+        assert_eq!(code("{\"cmd\":"), "invalid-json");
+        assert_eq!(code(r#"["toggle"]"#), "invalid-request");
+        assert_eq!(code(r#"{"v":2,"cmd":"toggle"}"#), "unsupported-version");
+        assert_eq!(code(r#"{"cmd":"mute"}"#), "unknown-command");
+        assert_eq!(code(r#"{"cmd":"quit"}"#), "tray-unavailable");
+        assert_eq!(
+            respond(r#"{"id":[1],"cmd":"mute"}"#)["id"],
+            serde_json::json!([1])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serves_json_lines_over_socket() {
+        use crate::fake_platform::RecordingChannel;
+        use std::os::unix::net::{UnixListener, UnixStream};
+        use std::sync::mpsc;
+
+        let path = std::env::temp_dir().join(format!("volume_mixer_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let (sender, commands) = mpsc::channel();
+        let events = EventBus::default();
+        spawn_server(listener, events.clone(), move || {
+            RecordingChannel(sender.clone())
+        })
+        .unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"{\"id\":1,\"cmd\":\"hide\"}\n\n{\"id\":2,\"cmd\":\"nope\"}\n")
+            .unwrap();
+        let mut lines = BufReader::new(client.try_clone().unwrap()).lines();
+
+        let first: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        let second: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(first, serde_json::json!({"v": 1, "id": 1, "ok": true}));
+        assert_eq!(second["error"]["code"], "unknown-command");
+        assert_eq!(
+            commands.try_iter().collect::<Vec<_>>(),
+            vec![InstanceCommand::Hide]
+        );
+
+        client.write_all(&[b'x'; MAX_REQUEST_LEN + 1]).unwrap();
+        let third: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(third["error"]["code"], "request-too-long");
+        assert!(lines.next().is_none());
+
+        let mut subscriber = UnixStream::connect(&path).unwrap();
+        subscriber
+            .write_all(b"{\"id\":4,\"cmd\":\"subscribe\"}\n")
+            .unwrap();
+        let mut lines = BufReader::new(subscriber.try_clone().unwrap()).lines();
+        let mut next =
+            || -> Value { serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap() };
+        assert_eq!(next(), serde_json::json!({"v": 1, "id": 4, "ok": true}));
+
+        // The subscriber does not hold up other clients.
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"{\"cmd\":\"show\"}\n").unwrap();
+        let mut answer = String::new();
+        BufReader::new(client).read_line(&mut answer).unwrap();
+        assert_eq!(answer, "{\"v\":1,\"ok\":true}\n");
+
+        events.publish(MixerEvent::MixerRestarted { pid: 12 });
+        events.publish(MixerEvent::Exiting);
+        events.close();
+        assert_eq!(
+            next(),
+            serde_json::json!({"v": 1, "event": "mixer-restarted", "pid": 12})
+        );
+        assert_eq!(next(), serde_json::json!({"v": 1, "event": "exiting"}));
+        assert!(lines.next().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn idle_client_does_not_block_others() {
+        use crate::fake_platform::RecordingChannel;
+        use std::os::unix::net::{UnixListener, UnixStream};
+        use std::sync::mpsc;
+
+        let path =
+            std::env::temp_dir().join(format!("volume_mixer_idle_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let (sender, commands) = mpsc::channel();
+        spawn_server(listener, EventBus::default(), move || {
+            RecordingChannel(sender.clone())
+        })
+        .unwrap();
+
+        // Connected first and never sends a request.
+        let idle = UnixStream::connect(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .write_all(b"{\"id\":1,\"cmd\":\"toggle\"}\n")
+            .unwrap();
+        let mut answer = String::new();
+        BufReader::new(&client).read_line(&mut answer).unwrap();
+
+        assert_eq!(answer, "{\"v\":1,\"id\":1,\"ok\":true}\n");
+        assert_eq!(
+            commands.try_iter().collect::<Vec<_>>(),
+            vec![InstanceCommand::Toggle]
+        );
+        drop(idle);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
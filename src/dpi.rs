@@ -0,0 +1,113 @@
+//! Conversion of pixel lengths between logical (96 DPI) and physical coordinates.
+
+use crate::platform::Rect;
+
+/// Dots per inch of a monitor, 96 being 100% scaling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dpi(pub u32);
+
+impl Dpi {
+    pub const DEFAULT: Dpi = Dpi(96);
+
+    pub fn to_physical(self, logical: i32) -> i32 {
+        mul_div(logical, self.0, Self::DEFAULT.0)
+    }
+
+    pub fn to_logical(self, physical: i32) -> i32 {
+        mul_div(physical, Self::DEFAULT.0, self.0)
+    }
+}
+
+impl Default for Dpi {
+    fn default() -> Self {
+        Dpi::DEFAULT
+    }
+}
+
+/// Physical length on a monitor with DPI `to` of something that is `length`
+/// pixels long on a monitor with DPI `from`.
+pub fn rescale(length: i32, from: Dpi, to: Dpi) -> i32 {
+    mul_div(length, to.0, from.0)
+}
+
+/// Size of `rect` after moving it from a monitor with DPI `from` to one with
+/// DPI `to`, keeping its top-left corner.
+pub fn rescale_rect(rect: Rect, from: Dpi, to: Dpi) -> Rect {
+    Rect::new(
+        rect.left,
+        rect.top,
+        rect.left + rescale(rect.width(), from, to),
+        rect.top + rescale(rect.height(), from, to),
+    )
+}
+
+/// `value * numerator / denominator` rounded half away from zero, like Win32 `MulDiv`.
+fn mul_div(value: i32, numerator: u32, denominator: u32) -> i32 {
+    if denominator == 0 {
+        return value;
+    }
+
+    let product = value as i64 * numerator as i64;
+    let half = denominator as i64 / 2;
+    let rounded = if product < 0 {
+        (product - half) / denominator as i64
+    } else {
+        (product + half) / denominator as i64
+    };
+
+    rounded as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_logical_to_physical() {
+        let dpi = Dpi(144);
+
+        assert_eq!(dpi.to_physical(300), 450);
+        assert_eq!(dpi.to_physical(-7), -11);
+        assert_eq!(Dpi(120).to_physical(5), 6);
+        assert_eq!(Dpi::DEFAULT.to_physical(123), 123);
+    }
+
+    #[test]
+    fn converts_physical_to_logical() {
+        assert_eq!(Dpi(144).to_logical(450), 300);
+        assert_eq!(Dpi(144).to_logical(-11), -7);
+        assert_eq!(Dpi(120).to_logical(6), 5);
+        assert_eq!(Dpi::DEFAULT.to_logical(123), 123);
+        assert_eq!(Dpi(0).to_logical(123), 123);
+    }
+
+    #[test]
+    fn logical_lengths_round_trip() {
+        // 125% and 150%, where physical lengths are rounded.
+        for dpi in [Dpi(120), Dpi(144)] {
+            for logical in -500..=500 {
+                assert_eq!(
+                    dpi.to_logical(dpi.to_physical(logical)),
+                    logical,
+                    "{:?}",
+                    dpi
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rescales_between_monitors() {
+        let rect = Rect::new(-1280, 10, -980, 210);
+
+        assert_eq!(
+            rescale_rect(rect, Dpi(96), Dpi(144)),
+            Rect::new(-1280, 10, -830, 310)
+        );
+        assert_eq!(
+            rescale_rect(Rect::new(0, 0, 450, 300), Dpi(144), Dpi(96)),
+            Rect::new(0, 0, 300, 200)
+        );
+        assert_eq!(rescale(100, Dpi(0), Dpi(96)), 100);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::cli::CliError;
+use crate::config::ConfigError;
+use crate::window_match::WindowMatcher;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Failure of a single OS API call, keeping the code reported by the OS
+/// (an `HRESULT` on Windows) so callers can react to it programmatically.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OsError {
+    pub function: &'static str,
+    pub code: i32,
+    pub message: String,
+}
+
+impl OsError {
+    #[cfg(windows)]
+    pub fn from_io(function: &'static str, err: &std::io::Error) -> OsError {
+        OsError {
+            function,
+            code: err.raw_os_error().unwrap_or_default(),
+            message: err.to_string(),
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn from_win32(function: &'static str, err: windows::core::Error) -> OsError {
+        OsError {
+            function,
+            code: err.code().0,
+            message: err.message().to_string(),
+        }
+    }
+
+    /// Build the error from the calling thread's last Win32 error.
+    #[cfg(windows)]
+    pub fn last_win32(function: &'static str) -> OsError {
+        Self::from_win32(function, windows::core::Error::from_win32())
+    }
+}
+
+impl fmt::Display for OsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed: {} ({:#010X})",
+            self.function, self.message, self.code
+        )
+    }
+}
+
+// Several variants are only raised by the Win32 backend.
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    Config(ConfigError),
+    /// A required environment variable is not set.
+    MissingEnvVar {
+        name: String,
+    },
+    /// The executable to run does not exist.
+    ExecNotFound {
+        exec_path: PathBuf,
+    },
+    Spawn {
+        exec_path: PathBuf,
+        source: OsError,
+    },
+    ProcessNotFound {
+        exec_name: String,
+    },
+    ProcessQuery(OsError),
+    ProcessTerminate {
+        pid: u32,
+        source: OsError,
+    },
+    WindowNotFound {
+        matcher: WindowMatcher,
+    },
+    /// The window could not appear anymore, e.g. its process exited.
+    WindowWaitCancelled {
+        matcher: WindowMatcher,
+    },
+    /// Querying or manipulating an existing window failed.
+    Window(OsError),
+    WindowClassRegistration(OsError),
+    WindowCreation(OsError),
+    PropertyStore {
+        property: String,
+        source: OsError,
+    },
+    TrayRegistration(OsError),
+    Menu(OsError),
+    ShellOpen {
+        target: String,
+        source: OsError,
+    },
+    /// Another application already registered the hotkey.
+    HotkeyConflict {
+        hotkey: String,
+    },
+    HotkeyRegistration {
+        hotkey: String,
+        source: OsError,
+    },
+    /// Creating or assigning the job object that ends children with the tray failed.
+    Job(OsError),
+    Cli(CliError),
+    InstanceLock(OsError),
+    /// Another tray is running but could not be reached.
+    InstanceForward(OsError),
+    /// The running tray was reached but did not take the command, e.g.
+    /// while its menu or a message box is open.
+    TrayBusy {
+        command: String,
+    },
+    /// The running tray did not answer a command that expects a reply.
+    InvalidReply,
+    /// The command needs a running tray.
+    TrayNotRunning {
+        command: String,
+    },
+    /// The pipe for external tools could not be created.
+    ControlServer(OsError),
+    HttpServer {
+        port: u16,
+        message: String,
+    },
+}
+
+impl Error {
+    /// Process exit code reported when the tray stops because of this error,
+    /// so wrapper scripts can tell failures apart without parsing the log.
+    #[cfg(windows)]
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::MissingEnvVar { .. } | Error::ExecNotFound { .. } => 2,
+            Error::Spawn { .. } => 3,
+            Error::ProcessNotFound { .. } | Error::ProcessQuery(_) => 4,
+            Error::ProcessTerminate { .. } => 5,
+            Error::WindowNotFound { .. } | Error::WindowWaitCancelled { .. } => 6,
+            Error::Window(_) => 7,
+            Error::WindowClassRegistration(_) | Error::WindowCreation(_) => 8,
+            Error::PropertyStore { .. } => 9,
+            Error::TrayRegistration(_) => 10,
+            Error::Config(_) => 11,
+            Error::Menu(_) => 12,
+            Error::ShellOpen { .. } => 13,
+            Error::HotkeyConflict { .. } | Error::HotkeyRegistration { .. } => 14,
+            Error::Job(_) => 15,
+            Error::InstanceLock(_)
+            | Error::InstanceForward(_)
+            | Error::TrayBusy { .. }
+            | Error::InvalidReply => 16,
+            Error::Cli(_) => 17,
+            Error::TrayNotRunning { .. } => 18,
+            Error::ControlServer(_) | Error::HttpServer { .. } => 19,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(err) => write!(f, "Invalid configuration: {}", err),
+            Error::MissingEnvVar { name } => {
+                write!(f, "Environment variable {} is not set", name)
+            }
+            Error::ExecNotFound { exec_path } => {
+                write!(f, "Executable \"{}\" does not exist", exec_path.display())
+            }
+            Error::Spawn { exec_path, source } => {
+                write!(f, "Could not run \"{}\": {}", exec_path.display(), source)
+            }
+            Error::ProcessNotFound { exec_name } => {
+                write!(f, "Could not find pid related to exec {}", exec_name)
+            }
+            Error::ProcessQuery(source) => write!(f, "Could not query processes: {}", source),
+            Error::ProcessTerminate { pid, source } => {
+                write!(f, "Could not terminate process {}: {}", pid, source)
+            }
+            Error::WindowNotFound { matcher } => {
+                write!(f, "Could not find a window matching {}", matcher)
+            }
+            Error::WindowWaitCancelled { matcher } => {
+                write!(f, "Stopped waiting for a window matching {}", matcher)
+            }
+            Error::Window(source) => write!(f, "Window operation failed: {}", source),
+            Error::WindowClassRegistration(source) => {
+                write!(f, "Could not register window class: {}", source)
+            }
+            Error::WindowCreation(source) => write!(f, "Could not create window: {}", source),
+            Error::PropertyStore { property, source } => {
+                write!(f, "Could not store property \"{}\": {}", property, source)
+            }
+            Error::TrayRegistration(source) => {
+                write!(f, "Could not register tray icon: {}", source)
+            }
+            Error::Menu(source) => write!(f, "Could not show menu: {}", source),
+            Error::ShellOpen { target, source } => {
+                write!(f, "Could not open \"{}\": {}", target, source)
+            }
+            Error::HotkeyConflict { hotkey } => {
+                write!(
+                    f,
+                    "Hotkey {} is already used by another application",
+                    hotkey
+                )
+            }
+            Error::HotkeyRegistration { hotkey, source } => {
+                write!(f, "Could not register hotkey {}: {}", hotkey, source)
+            }
+            Error::Job(source) => write!(f, "Job object operation failed: {}", source),
+            Error::Cli(err) => write!(f, "{}", err),
+            Error::InstanceLock(source) => {
+                write!(f, "Could not check for a running tray: {}", source)
+            }
+            Error::InstanceForward(source) => {
+                write!(f, "Could not reach the running tray: {}", source)
+            }
+            Error::TrayBusy { command } => {
+                write!(f, "The running tray is busy and did not {}", command)
+            }
+            Error::InvalidReply => write!(f, "The running tray sent no valid reply"),
+            Error::TrayNotRunning { command } => {
+                write!(f, "No tray is running to {}", command)
+            }
+            Error::ControlServer(source) => {
+                write!(f, "Could not start the control server: {}", source)
+            }
+            Error::HttpServer { port, message } => {
+                write!(f, "Could not serve HTTP on port {}: {}", port, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
@@ -0,0 +1,302 @@
+//! Events about the mixer that other threads, like control API clients, can
+//! subscribe to.
+//!
+//! Every subscriber gets the events published after it subscribed, in the
+//! order they were published. Publishing never blocks: a subscriber whose
+//! queue is full misses events until it has taken the queued ones, and then
+//! gets `Received::Lagged` with the number it missed before newer events.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+/// Events a subscriber can fall behind by before it misses events.
+pub const DEFAULT_CAPACITY: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum MixerEvent {
+    MixerShown,
+    MixerHidden,
+    /// A new mixer process replaced the previous one.
+    MixerRestarted {
+        pid: u32,
+    },
+    ConfigReloaded,
+    /// The tray is about to exit, no events follow.
+    Exiting,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Received {
+    Event(MixerEvent),
+    /// `missed` events were dropped because the subscriber fell behind.
+    Lagged {
+        missed: u64,
+    },
+    Timeout,
+    /// The bus was closed and all events before were taken.
+    Closed,
+}
+
+/// Fans out published events to all subscribers. Clones share the bus.
+#[derive(Clone)]
+pub struct EventBus {
+    shared: Arc<Mutex<BusState>>,
+    capacity: usize,
+}
+
+struct BusState {
+    subscribers: Vec<Weak<Queue>>,
+    closed: bool,
+}
+
+struct Queue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    events: VecDeque<MixerEvent>,
+    missed: u64,
+    closed: bool,
+}
+
+pub struct Subscription {
+    queue: Arc<Queue>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> EventBus {
+        EventBus {
+            shared: Arc::new(Mutex::new(BusState {
+                subscribers: Vec::new(),
+                closed: false,
+            })),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState::default()),
+            changed: Condvar::new(),
+        });
+        let mut shared = lock(&self.shared);
+        if shared.closed {
+            lock(&queue.state).closed = true;
+        } else {
+            shared.subscribers.push(Arc::downgrade(&queue));
+        }
+
+        Subscription { queue }
+    }
+
+    pub fn publish(&self, event: MixerEvent) {
+        let mut shared = lock(&self.shared);
+        if shared.closed {
+            return;
+        }
+
+        // Dropped subscriptions are forgotten here.
+        shared.subscribers.retain(|subscriber| {
+            let Some(queue) = subscriber.upgrade() else {
+                return false;
+            };
+            let mut state = lock(&queue.state);
+            // Keep missing events until the queued ones are taken, so the
+            // gap is reported where it happened.
+            if state.missed > 0 || state.events.len() >= self.capacity {
+                state.missed += 1;
+            } else {
+                state.events.push_back(event);
+                queue.changed.notify_all();
+            }
+            true
+        });
+    }
+
+    /// End all subscriptions once they took the events published so far.
+    pub fn close(&self) {
+        let mut shared = lock(&self.shared);
+        shared.closed = true;
+        for queue in shared
+            .subscribers
+            .drain(..)
+            .filter_map(|queue| queue.upgrade())
+        {
+            lock(&queue.state).closed = true;
+            queue.changed.notify_all();
+        }
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> EventBus {
+        EventBus::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Subscription {
+    /// Take the next event, waiting up to `timeout` for one.
+    pub fn recv_timeout(&self, timeout: Duration) -> Received {
+        let deadline = Instant::now() + timeout;
+        let mut state = lock(&self.queue.state);
+
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                return Received::Event(event);
+            }
+            if state.missed > 0 {
+                let missed = std::mem::take(&mut state.missed);
+                return Received::Lagged { missed };
+            }
+            if state.closed {
+                return Received::Closed;
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Received::Timeout;
+            }
+            state = match self.queue.changed.wait_timeout(state, remaining) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+}
+
+/// Events are plain data, a panicking holder cannot leave them inconsistent.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(subscription: &Subscription) -> Vec<Received> {
+        let mut received = Vec::new();
+        loop {
+            match subscription.recv_timeout(Duration::ZERO) {
+                Received::Timeout => return received,
+                Received::Closed => {
+                    received.push(Received::Closed);
+                    return received;
+                }
+                event => received.push(event),
+            }
+        }
+    }
+
+    #[test]
+    fn delivers_events_in_order_to_every_subscriber() {
+        let bus = EventBus::default();
+        bus.publish(MixerEvent::MixerShown);
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+
+        bus.publish(MixerEvent::MixerHidden);
+        bus.publish(MixerEvent::MixerRestarted { pid: 7 });
+
+        for subscription in [&first, &second] {
+            assert_eq!(
+                drain(subscription),
+                vec![
+                    Received::Event(MixerEvent::MixerHidden),
+                    Received::Event(MixerEvent::MixerRestarted { pid: 7 }),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn slow_subscriber_lags_without_blocking_publisher() {
+        let bus = EventBus::new(2);
+        let slow = bus.subscribe();
+        let fast = bus.subscribe();
+
+        bus.publish(MixerEvent::MixerShown);
+        bus.publish(MixerEvent::MixerHidden);
+        assert_eq!(drain(&fast).len(), 2);
+        bus.publish(MixerEvent::ConfigReloaded);
+        bus.publish(MixerEvent::MixerShown);
+        assert_eq!(drain(&fast).len(), 2);
+        assert_eq!(
+            slow.recv_timeout(Duration::ZERO),
+            Received::Event(MixerEvent::MixerShown)
+        );
+        // Still missed although there is room again.
+        bus.publish(MixerEvent::MixerHidden);
+        assert_eq!(
+            drain(&slow),
+            vec![
+                Received::Event(MixerEvent::MixerHidden),
+                Received::Lagged { missed: 3 },
+            ]
+        );
+
+        bus.publish(MixerEvent::Exiting);
+        assert_eq!(drain(&slow), vec![Received::Event(MixerEvent::Exiting)]);
+        assert_eq!(
+            drain(&fast),
+            vec![
+                Received::Event(MixerEvent::MixerHidden),
+                Received::Event(MixerEvent::Exiting),
+            ]
+        );
+    }
+
+    #[test]
+    fn close_ends_subscriptions_after_pending_events() {
+        let bus = EventBus::default();
+        let subscription = bus.subscribe();
+        let waiting = std::thread::spawn({
+            let subscription = bus.subscribe();
+            move || subscription.recv_timeout(Duration::from_secs(10))
+        });
+
+        bus.publish(MixerEvent::Exiting);
+        bus.close();
+        bus.publish(MixerEvent::MixerShown);
+
+        assert_eq!(
+            waiting.join().unwrap(),
+            Received::Event(MixerEvent::Exiting)
+        );
+        assert_eq!(
+            drain(&subscription),
+            vec![Received::Event(MixerEvent::Exiting), Received::Closed]
+        );
+        assert_eq!(
+            bus.subscribe().recv_timeout(Duration::ZERO),
+            Received::Closed
+        );
+    }
+
+    #[test]
+    fn dropped_subscriptions_are_forgotten() {
+        let bus = EventBus::default();
+        let subscription = bus.subscribe();
+        drop(bus.subscribe());
+
+        bus.publish(MixerEvent::MixerShown);
+        assert_eq!(lock(&bus.shared).subscribers.len(), 1);
+        drop(subscription);
+        bus.publish(MixerEvent::MixerShown);
+        assert!(lock(&bus.shared).subscribers.is_empty());
+    }
+
+    #[test]
+    fn events_serialize_with_tag() {
+        assert_eq!(
+            serde_json::to_value(MixerEvent::MixerRestarted { pid: 9 }).unwrap(),
+            serde_json::json!({"event": "mixer-restarted", "pid": 9})
+        );
+    }
+}
@@ -0,0 +1,607 @@
+//! In-memory implementation of the platform traits used by unit tests.
+
+use crate::dpi::Dpi;
+use crate::error::{Error, OsError};
+use crate::hotkey::{Hotkey, HotkeyAction};
+use crate::instance::{InstanceChannel, InstanceCommand};
+use crate::menu::Menu;
+use crate::platform::{
+    ChildProcess, Monitor, Point, ProcessSpawner, Rect, SpawnOptions, TrayShell, WindowFinder,
+    WindowHandle, WindowManager,
+};
+use crate::process_query::{ProcessInfo, ProcessQuery};
+use crate::window_match::{WindowInfo, WindowMatcher};
+use crate::window_wait::{WindowSignal, WindowWatch};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+
+/// Where `system_exec_path` finds executables.
+const SYSTEM_DIR: &str = r"C:\Windows\System32";
+
+pub struct FakeProcessEntry {
+    pub pid: u32,
+    pub exec_name: String,
+    pub exec_path: PathBuf,
+    pub session_id: u32,
+}
+
+impl FakeProcessEntry {
+    fn info(&self) -> ProcessInfo {
+        ProcessInfo {
+            pid: self.pid,
+            exe_name: self.exec_name.clone(),
+            exe_path: Some(self.exec_path.clone()),
+            thread_count: 1,
+            session_id: Some(self.session_id),
+            ..ProcessInfo::default()
+        }
+    }
+}
+
+pub struct FakeWindow {
+    pub hwnd: WindowHandle,
+    pub pid: u32,
+    pub title: String,
+    pub class_name: String,
+    pub visible: bool,
+    pub owner: Option<WindowHandle>,
+    pub rect: Rect,
+}
+
+impl FakeWindow {
+    pub fn info(&self, state: &FakeState) -> WindowInfo {
+        let exe_name = state
+            .processes
+            .iter()
+            .find(|process| process.pid == self.pid)
+            .map(|process| process.exec_name.clone())
+            .unwrap_or_default();
+        WindowInfo {
+            hwnd: self.hwnd,
+            pid: self.pid,
+            exe_name,
+            class_name: self.class_name.clone(),
+            title: self.title.clone(),
+            visible: self.visible,
+            rect: self.rect,
+            owner: self.owner,
+            ..WindowInfo::default()
+        }
+    }
+}
+
+pub struct FakeState {
+    pub processes: Vec<FakeProcessEntry>,
+    /// Session of the tray and of every process added.
+    pub session_id: u32,
+    pub windows: Vec<FakeWindow>,
+    pub monitors: Vec<Monitor>,
+    pub foreground: Option<WindowHandle>,
+    /// Between `watch_foreground` and `unwatch_foreground`.
+    pub watching_foreground: bool,
+    pub tray_tooltip: Option<String>,
+    /// Where the tray icon is, `None` to make the lookup fail.
+    pub tray_icon_rect: Option<Rect>,
+    pub cursor: Point,
+    pub quit_posted: bool,
+    /// Last menu passed to `show_context_menu`.
+    pub shown_menu: Option<Menu>,
+    /// `(title, text)` of every message box shown.
+    pub messages: Vec<(String, String)>,
+    pub hotkeys: BTreeMap<HotkeyAction, Hotkey>,
+    /// Hotkeys "registered by other applications", registering them fails.
+    pub taken_hotkeys: Vec<Hotkey>,
+    /// Targets passed to `shell_open`.
+    pub opened: Vec<String>,
+    pub spawned: Vec<PathBuf>,
+    /// Exit codes of processes that ended, by pid.
+    pub exit_codes: HashMap<u32, u32>,
+    /// Title of the window created for every spawned process, `None` to create no window.
+    pub spawned_window_title: Option<String>,
+    /// Exit code of every spawned process, which ends right after it started,
+    /// `None` to keep them running.
+    pub spawned_exit_code: Option<u32>,
+    /// Options of every spawned process, in the order of `spawned`.
+    pub spawn_options: Vec<SpawnOptions>,
+    /// Whether another tray holds the single-instance lock.
+    pub instance_running: bool,
+    /// How many of the next forwarded commands fail, as if the running tray was still starting.
+    pub forward_failures: u32,
+    /// Whether the running tray is reached but does not take commands.
+    pub instance_busy: bool,
+    /// Commands forwarded to the running tray, in order.
+    pub forwarded: Vec<InstanceCommand>,
+    /// Reply of the running tray to every forwarded command.
+    pub instance_reply: Option<String>,
+    /// Windows asked to close, in order.
+    pub close_requests: Vec<WindowHandle>,
+    /// Whether processes keep running when their window is asked to close.
+    pub ignore_close: bool,
+    next_id: u32,
+}
+
+impl Default for FakeState {
+    fn default() -> Self {
+        FakeState {
+            processes: Vec::new(),
+            session_id: 1,
+            windows: Vec::new(),
+            monitors: vec![Monitor {
+                bounds: Rect::new(0, 0, 1920, 1080),
+                work_area: Rect::new(0, 0, 1920, 1040),
+                primary: true,
+                dpi: Dpi::DEFAULT,
+            }],
+            foreground: None,
+            watching_foreground: false,
+            tray_tooltip: None,
+            tray_icon_rect: None,
+            cursor: Point::default(),
+            quit_posted: false,
+            shown_menu: None,
+            messages: Vec::new(),
+            opened: Vec::new(),
+            hotkeys: BTreeMap::new(),
+            taken_hotkeys: Vec::new(),
+            spawned: Vec::new(),
+            exit_codes: HashMap::new(),
+            spawned_window_title: Some("Volume Mixer - Speakers".to_string()),
+            spawned_exit_code: None,
+            spawn_options: Vec::new(),
+            instance_running: false,
+            forward_failures: 0,
+            instance_busy: false,
+            forwarded: Vec::new(),
+            instance_reply: None,
+            close_requests: Vec::new(),
+            ignore_close: false,
+            next_id: 100,
+        }
+    }
+}
+
+impl FakeState {
+    pub fn window(&self, hwnd: WindowHandle) -> Option<&FakeWindow> {
+        self.windows.iter().find(|window| window.hwnd == hwnd)
+    }
+
+    pub fn window_mut(&mut self, hwnd: WindowHandle) -> Option<&mut FakeWindow> {
+        self.windows.iter_mut().find(|window| window.hwnd == hwnd)
+    }
+
+    /// Replace the monitors by a single primary one with the given work area.
+    pub fn set_work_area(&mut self, work_area: Rect) {
+        self.monitors = vec![Monitor {
+            bounds: Rect::new(0, 0, 1920, 1080),
+            work_area,
+            primary: true,
+            dpi: Dpi::DEFAULT,
+        }];
+    }
+
+    pub fn is_process_alive(&self, pid: u32) -> bool {
+        self.processes.iter().any(|process| process.pid == pid)
+    }
+
+    /// Let the process end by itself, as if closed by the user or crashed.
+    pub fn exit_process(&mut self, pid: u32, exit_code: u32) {
+        self.kill_process(pid);
+        self.exit_codes.insert(pid, exit_code);
+    }
+
+    fn next_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn add_process(&mut self, exec_path: &Path) -> u32 {
+        let pid = self.next_id();
+        self.processes.push(FakeProcessEntry {
+            pid,
+            exec_name: exec_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            exec_path: exec_path.to_path_buf(),
+            session_id: self.session_id,
+        });
+
+        pid
+    }
+
+    pub fn add_window(&mut self, pid: u32, title: &str) -> WindowHandle {
+        let hwnd = WindowHandle(self.next_id() as isize);
+        self.windows.push(FakeWindow {
+            hwnd,
+            pid,
+            title: title.to_string(),
+            class_name: "#32770".to_string(),
+            visible: false,
+            owner: None,
+            rect: Rect::new(0, 0, 300, 200),
+        });
+
+        hwnd
+    }
+
+    fn kill_process(&mut self, pid: u32) {
+        self.processes.retain(|process| process.pid != pid);
+        self.windows.retain(|window| window.pid != pid);
+    }
+}
+
+/// Cheaply clonable handle to shared fake state, so the same fake can play
+/// every platform role at once.
+#[derive(Clone, Default)]
+pub struct FakePlatform {
+    state: Rc<RefCell<FakeState>>,
+}
+
+impl FakePlatform {
+    pub fn state(&self) -> Ref<'_, FakeState> {
+        self.state.borrow()
+    }
+
+    pub fn state_mut(&self) -> RefMut<'_, FakeState> {
+        self.state.borrow_mut()
+    }
+
+    /// Error returned by the fake for failed "OS" calls.
+    pub fn os_error(function: &'static str) -> OsError {
+        OsError {
+            function,
+            code: -1,
+            message: "fake failure".to_string(),
+        }
+    }
+
+    pub fn add_process_with_window(&self, exec_name: &str, title: &str) -> (u32, WindowHandle) {
+        let mut state = self.state_mut();
+        let pid = state.add_process(&Path::new(SYSTEM_DIR).join(exec_name));
+        let hwnd = state.add_window(pid, title);
+
+        (pid, hwnd)
+    }
+}
+
+pub struct FakeProcess {
+    pid: u32,
+    state: Rc<RefCell<FakeState>>,
+}
+
+impl ChildProcess for FakeProcess {
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn terminate(&mut self) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        if state.is_process_alive(self.pid) {
+            state.exit_process(self.pid, 1);
+            Ok(())
+        } else {
+            Err(Error::ProcessTerminate {
+                pid: self.pid,
+                source: FakePlatform::os_error("TerminateProcess"),
+            })
+        }
+    }
+
+    fn try_wait(&mut self) -> Result<Option<u32>, Error> {
+        let state = self.state.borrow();
+        if state.is_process_alive(self.pid) {
+            Ok(None)
+        } else {
+            Ok(Some(state.exit_codes.get(&self.pid).copied().unwrap_or(0)))
+        }
+    }
+
+    /// The fake never blocks, a process either exited already or does not exit.
+    fn wait_timeout(&mut self, _timeout: Duration) -> Result<Option<u32>, Error> {
+        self.try_wait()
+    }
+}
+
+impl ProcessSpawner for FakePlatform {
+    type Process = FakeProcess;
+
+    fn find_processes(&self, query: &ProcessQuery) -> Result<Vec<ProcessInfo>, Error> {
+        Ok(query.filter(self.state().processes.iter().map(FakeProcessEntry::info)))
+    }
+
+    fn current_session_id(&self) -> Result<u32, Error> {
+        Ok(self.state().session_id)
+    }
+
+    fn is_process_running(&self, pid: u32) -> Result<bool, Error> {
+        Ok(self.state().is_process_alive(pid))
+    }
+
+    fn open_process(&self, pid: u32) -> Result<FakeProcess, Error> {
+        if self.state().is_process_alive(pid) {
+            Ok(FakeProcess {
+                pid,
+                state: self.state.clone(),
+            })
+        } else {
+            Err(Error::ProcessQuery(Self::os_error("OpenProcess")))
+        }
+    }
+
+    fn system_exec_path(&self, exec_name: &str) -> Result<PathBuf, Error> {
+        Ok(Path::new(SYSTEM_DIR).join(exec_name))
+    }
+
+    fn spawn(&self, exec_path: &Path, options: &SpawnOptions) -> Result<FakeProcess, Error> {
+        let mut state = self.state_mut();
+        state.spawned.push(exec_path.to_path_buf());
+        state.spawn_options.push(options.clone());
+        let pid = state.add_process(exec_path);
+        if let Some(title) = state.spawned_window_title.clone() {
+            let hwnd = state.add_window(pid, &title);
+            state.window_mut(hwnd).unwrap().visible = !options.hidden;
+        }
+        if let Some(exit_code) = state.spawned_exit_code {
+            state.exit_process(pid, exit_code);
+        }
+
+        Ok(FakeProcess {
+            pid,
+            state: self.state.clone(),
+        })
+    }
+
+    fn shell_open(&self, target: &str) -> Result<(), Error> {
+        self.state_mut().opened.push(target.to_string());
+        Ok(())
+    }
+}
+
+impl InstanceChannel for FakePlatform {
+    fn try_lock(&mut self) -> Result<bool, Error> {
+        let mut state = self.state_mut();
+        let locked = !state.instance_running;
+        state.instance_running = true;
+
+        Ok(locked)
+    }
+
+    fn forward(&mut self, command: InstanceCommand) -> Result<Option<String>, Error> {
+        let mut state = self.state_mut();
+        if state.forward_failures > 0 {
+            state.forward_failures -= 1;
+            return Err(Error::InstanceForward(Self::os_error("FindWindowExW")));
+        }
+        if state.instance_busy {
+            return Err(Error::TrayBusy {
+                command: command.name().to_string(),
+            });
+        }
+        state.forwarded.push(command);
+
+        Ok(state.instance_reply.clone())
+    }
+}
+
+/// Channel that can be moved to another thread, recording forwarded commands.
+pub struct RecordingChannel(pub mpsc::Sender<InstanceCommand>);
+
+impl InstanceChannel for RecordingChannel {
+    fn try_lock(&mut self) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn forward(&mut self, command: InstanceCommand) -> Result<Option<String>, Error> {
+        // The test may be done with the commands already.
+        let _ = self.0.send(command);
+        Ok(None)
+    }
+}
+
+/// Config file in the temp directory, removed again when dropped.
+pub struct TempConfig {
+    path: PathBuf,
+}
+
+impl TempConfig {
+    /// Not created yet, `name` keeps concurrently running tests apart.
+    pub fn new(name: &str) -> TempConfig {
+        TempConfig {
+            path: std::env::temp_dir().join(format!(
+                "volume_mixer_{}_{}.toml",
+                name,
+                std::process::id()
+            )),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the content, as if last modified `age` ago.
+    pub fn write(&self, content: &str, age: Duration) {
+        fs::write(&self.path, content).unwrap();
+        File::options()
+            .write(true)
+            .open(&self.path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+}
+
+impl Drop for TempConfig {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl WindowFinder for FakePlatform {
+    fn find_window(&self, matcher: &WindowMatcher) -> Result<WindowInfo, Error> {
+        let state = self.state();
+        matcher
+            .find(state.windows.iter().map(|window| window.info(&state)))
+            .ok_or_else(|| Error::WindowNotFound {
+                matcher: matcher.clone(),
+            })
+    }
+
+    fn is_window(&self, hwnd: WindowHandle) -> bool {
+        self.state().window(hwnd).is_some()
+    }
+
+    /// Only ever signals that a process already exited, no window appears
+    /// while the test waits.
+    fn watch_windows(&self, pid: Option<u32>) -> Result<WindowWatch, Error> {
+        let exited = pid.is_some_and(|pid| !self.state().is_process_alive(pid));
+        WindowWatch::start(|sender| {
+            if exited {
+                let _ = sender.send(WindowSignal::Cancelled);
+            }
+            Ok(())
+        })
+    }
+}
+
+impl WindowManager for FakePlatform {
+    fn is_visible(&self, hwnd: WindowHandle) -> bool {
+        self.state()
+            .window(hwnd)
+            .is_some_and(|window| window.visible)
+    }
+
+    fn show(&self, hwnd: WindowHandle) {
+        if let Some(window) = self.state_mut().window_mut(hwnd) {
+            window.visible = true;
+        }
+    }
+
+    fn hide(&self, hwnd: WindowHandle) {
+        if let Some(window) = self.state_mut().window_mut(hwnd) {
+            window.visible = false;
+        }
+    }
+
+    /// The fake mixer quits as soon as its window is closed, unless it ignores close requests.
+    fn close(&self, hwnd: WindowHandle) -> Result<(), Error> {
+        let mut state = self.state_mut();
+        match state.window(hwnd).map(|window| window.pid) {
+            Some(pid) => {
+                state.close_requests.push(hwnd);
+                if !state.ignore_close {
+                    state.exit_process(pid, 0);
+                }
+                Ok(())
+            }
+            None => Err(Error::Window(Self::os_error("PostMessageW"))),
+        }
+    }
+
+    fn set_foreground(&self, hwnd: WindowHandle) {
+        self.state_mut().foreground = Some(hwnd);
+    }
+
+    fn foreground_window(&self) -> Option<WindowHandle> {
+        self.state().foreground
+    }
+
+    fn window_rect(&self, hwnd: WindowHandle) -> Result<Rect, Error> {
+        self.state()
+            .window(hwnd)
+            .map(|window| window.rect)
+            .ok_or_else(|| Error::Window(Self::os_error("GetWindowRect")))
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, Error> {
+        Ok(self.state().monitors.clone())
+    }
+
+    fn move_window(&self, hwnd: WindowHandle, rect: Rect) -> Result<(), Error> {
+        self.state_mut()
+            .window_mut(hwnd)
+            .map(|window| window.rect = rect)
+            .ok_or_else(|| Error::Window(Self::os_error("MoveWindow")))
+    }
+
+    fn cursor_pos(&self) -> Result<Point, Error> {
+        Ok(self.state().cursor)
+    }
+}
+
+impl TrayShell for FakePlatform {
+    fn add_icon(&mut self, tooltip: &str) -> Result<(), Error> {
+        self.state_mut().tray_tooltip = Some(tooltip.to_string());
+        Ok(())
+    }
+
+    fn remove_icon(&mut self) -> Result<(), Error> {
+        self.state_mut()
+            .tray_tooltip
+            .take()
+            .map(|_| ())
+            .ok_or_else(|| Error::TrayRegistration(Self::os_error("Shell_NotifyIconA")))
+    }
+
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
+        let mut state = self.state_mut();
+        match state.tray_tooltip.as_mut() {
+            Some(current) => {
+                *current = tooltip.to_string();
+                Ok(())
+            }
+            None => Err(Error::TrayRegistration(Self::os_error("Shell_NotifyIconA"))),
+        }
+    }
+
+    fn icon_rect(&self) -> Result<Rect, Error> {
+        self.state()
+            .tray_icon_rect
+            .ok_or_else(|| Error::Window(Self::os_error("Shell_NotifyIconGetRect")))
+    }
+
+    fn show_context_menu(&self, menu: &Menu) -> Result<(), Error> {
+        self.state_mut().shown_menu = Some(menu.clone());
+        Ok(())
+    }
+
+    fn show_message(&self, title: &str, text: &str) {
+        self.state_mut()
+            .messages
+            .push((title.to_string(), text.to_string()));
+    }
+
+    fn register_hotkey(&mut self, action: HotkeyAction, hotkey: Hotkey) -> Result<(), Error> {
+        let mut state = self.state_mut();
+        if state.taken_hotkeys.contains(&hotkey) || state.hotkeys.values().any(|h| *h == hotkey) {
+            return Err(Error::HotkeyConflict {
+                hotkey: hotkey.to_string(),
+            });
+        }
+
+        state.hotkeys.insert(action, hotkey);
+        Ok(())
+    }
+
+    fn unregister_hotkey(&mut self, action: HotkeyAction) {
+        self.state_mut().hotkeys.remove(&action);
+    }
+
+    fn watch_foreground(&mut self) -> Result<(), Error> {
+        self.state_mut().watching_foreground = true;
+        Ok(())
+    }
+
+    fn unwatch_foreground(&mut self) {
+        self.state_mut().watching_foreground = false;
+    }
+
+    fn post_quit(&self) {
+        self.state_mut().quit_posted = true;
+    }
+}
@@ -0,0 +1,279 @@
+//! Global hotkeys written as "Ctrl+Alt+F12", parsed into the modifier and
+//! virtual-key values `RegisterHotKey` expects.
+
+use std::fmt;
+use std::str::FromStr;
+
+pub const MOD_ALT: u32 = 0x0001;
+pub const MOD_CONTROL: u32 = 0x0002;
+pub const MOD_SHIFT: u32 = 0x0004;
+pub const MOD_WIN: u32 = 0x0008;
+
+/// Modifiers in the order they are written back.
+const MODIFIERS: [(&str, u32); 4] = [
+    ("Ctrl", MOD_CONTROL),
+    ("Alt", MOD_ALT),
+    ("Shift", MOD_SHIFT),
+    ("Win", MOD_WIN),
+];
+
+/// Named keys and their virtual-key codes. Letters, digits and F1-F24 are
+/// handled separately.
+const NAMED_KEYS: [(&str, u32); 21] = [
+    ("Backspace", 0x08),
+    ("Tab", 0x09),
+    ("Enter", 0x0D),
+    ("Pause", 0x13),
+    ("Escape", 0x1B),
+    ("Space", 0x20),
+    ("PageUp", 0x21),
+    ("PageDown", 0x22),
+    ("End", 0x23),
+    ("Home", 0x24),
+    ("Left", 0x25),
+    ("Up", 0x26),
+    ("Right", 0x27),
+    ("Down", 0x28),
+    ("Insert", 0x2D),
+    ("Delete", 0x2E),
+    ("VolumeMute", 0xAD),
+    ("VolumeDown", 0xAE),
+    ("VolumeUp", 0xAF),
+    ("MediaPlayPause", 0xB3),
+    ("ScrollLock", 0x91),
+];
+
+/// What a hotkey does when pressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HotkeyAction {
+    ToggleMixer,
+    HideMixer,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 2] = [HotkeyAction::ToggleMixer, HotkeyAction::HideMixer];
+
+    /// Identifier the hotkey is registered with.
+    pub fn id(self) -> i32 {
+        match self {
+            HotkeyAction::ToggleMixer => 1,
+            HotkeyAction::HideMixer => 2,
+        }
+    }
+
+    pub fn from_id(id: i32) -> Option<HotkeyAction> {
+        Self::ALL.into_iter().find(|action| action.id() == id)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Hotkey {
+    /// Combination of the `MOD_*` flags.
+    pub modifiers: u32,
+    pub virtual_key: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HotkeyParseError {
+    Empty,
+    UnknownKey(String),
+    DuplicateModifier(String),
+    MissingKey,
+    MultipleKeys(String, String),
+}
+
+impl fmt::Display for HotkeyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HotkeyParseError::Empty => write!(f, "hotkey is empty"),
+            HotkeyParseError::UnknownKey(key) => write!(f, "unknown key \"{}\"", key),
+            HotkeyParseError::DuplicateModifier(modifier) => {
+                write!(f, "modifier \"{}\" is given more than once", modifier)
+            }
+            HotkeyParseError::MissingKey => write!(f, "hotkey has only modifiers, no key"),
+            HotkeyParseError::MultipleKeys(first, second) => {
+                write!(f, "hotkey has two keys, \"{}\" and \"{}\"", first, second)
+            }
+        }
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = HotkeyParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.trim().is_empty() {
+            return Err(HotkeyParseError::Empty);
+        }
+
+        let mut modifiers = 0;
+        let mut key: Option<(&str, u32)> = None;
+        for part in text.split('+').map(str::trim) {
+            if let Some(modifier) = parse_modifier(part) {
+                if modifiers & modifier != 0 {
+                    return Err(HotkeyParseError::DuplicateModifier(part.to_string()));
+                }
+                modifiers |= modifier;
+            } else {
+                let virtual_key = parse_key(part)
+                    .ok_or_else(|| HotkeyParseError::UnknownKey(part.to_string()))?;
+                if let Some((first, _)) = key {
+                    return Err(HotkeyParseError::MultipleKeys(
+                        first.to_string(),
+                        part.to_string(),
+                    ));
+                }
+                key = Some((part, virtual_key));
+            }
+        }
+
+        match key {
+            Some((_, virtual_key)) => Ok(Hotkey {
+                modifiers,
+                virtual_key,
+            }),
+            None => Err(HotkeyParseError::MissingKey),
+        }
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, modifier) in MODIFIERS {
+            if self.modifiers & modifier != 0 {
+                write!(f, "{}+", name)?;
+            }
+        }
+
+        match key_name(self.virtual_key) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{:#04X}", self.virtual_key),
+        }
+    }
+}
+
+fn parse_modifier(part: &str) -> Option<u32> {
+    match part.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" => Some(MOD_CONTROL),
+        "alt" => Some(MOD_ALT),
+        "shift" => Some(MOD_SHIFT),
+        "win" | "super" => Some(MOD_WIN),
+        _ => None,
+    }
+}
+
+fn parse_key(part: &str) -> Option<u32> {
+    let upper = part.to_ascii_uppercase();
+    let mut chars = upper.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_uppercase() || c.is_ascii_digit() {
+            return Some(c as u32);
+        }
+    }
+
+    if let Some(number) = upper.strip_prefix('F').and_then(|n| n.parse::<u32>().ok()) {
+        if (1..=24).contains(&number) {
+            return Some(0x70 + number - 1);
+        }
+    }
+
+    NAMED_KEYS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(part))
+        .map(|(_, virtual_key)| *virtual_key)
+}
+
+fn key_name(virtual_key: u32) -> Option<String> {
+    match virtual_key {
+        0x30..=0x39 | 0x41..=0x5A => char::from_u32(virtual_key).map(String::from),
+        0x70..=0x87 => Some(format!("F{}", virtual_key - 0x70 + 1)),
+        _ => NAMED_KEYS
+            .iter()
+            .find(|(_, key)| *key == virtual_key)
+            .map(|(name, _)| name.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modifiers_and_keys() {
+        assert_eq!(
+            "Ctrl+Alt+F12".parse(),
+            Ok(Hotkey {
+                modifiers: MOD_CONTROL | MOD_ALT,
+                virtual_key: 0x7B,
+            })
+        );
+        assert_eq!(
+            "win + shift + v".parse(),
+            Ok(Hotkey {
+                modifiers: MOD_WIN | MOD_SHIFT,
+                virtual_key: 0x56,
+            })
+        );
+        assert_eq!(
+            "VolumeMute".parse(),
+            Ok(Hotkey {
+                modifiers: 0,
+                virtual_key: 0xAD,
+            })
+        );
+        assert_eq!(
+            "Control+7"
+                .parse::<Hotkey>()
+                .map(|hotkey| hotkey.virtual_key),
+            Ok(0x37)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_hotkeys() {
+        assert_eq!("".parse::<Hotkey>(), Err(HotkeyParseError::Empty));
+        assert_eq!(
+            "Ctrl+Shift".parse::<Hotkey>(),
+            Err(HotkeyParseError::MissingKey)
+        );
+        assert_eq!(
+            "Ctrl+Ctrl+A".parse::<Hotkey>(),
+            Err(HotkeyParseError::DuplicateModifier("Ctrl".to_string()))
+        );
+        assert_eq!(
+            "Ctrl+A+B".parse::<Hotkey>(),
+            Err(HotkeyParseError::MultipleKeys(
+                "A".to_string(),
+                "B".to_string()
+            ))
+        );
+        assert_eq!(
+            "Ctrl+F25".parse::<Hotkey>(),
+            Err(HotkeyParseError::UnknownKey("F25".to_string()))
+        );
+        assert_eq!(
+            "Ctrl+".parse::<Hotkey>(),
+            Err(HotkeyParseError::UnknownKey("".to_string()))
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for text in ["Ctrl+Alt+F12", "Shift+Win+V", "Alt+PageDown", "Ctrl+0"] {
+            let hotkey: Hotkey = text.parse().unwrap();
+            assert_eq!(hotkey.to_string().parse(), Ok(hotkey));
+        }
+        assert_eq!(
+            "shift+win+v".parse::<Hotkey>().unwrap().to_string(),
+            "Shift+Win+V"
+        );
+    }
+
+    #[test]
+    fn action_ids_round_trip() {
+        for action in HotkeyAction::ALL {
+            assert_eq!(HotkeyAction::from_id(action.id()), Some(action));
+        }
+        assert_eq!(HotkeyAction::from_id(0), None);
+    }
+}
@@ -0,0 +1,342 @@
+//! Optional REST and WebSocket endpoint on 127.0.0.1 for browser dashboards
+//! and home-automation scripts. Requests carry the token from the settings as
+//! `Authorization: Bearer <token>`.
+//!
+//! - `GET /v1/status` answers like `status` of the control API.
+//! - `POST /v1/<command>` runs any other command, e.g. `POST /v1/toggle`.
+//! - `GET /v1/events` upgrades to a WebSocket that sends the event lines of
+//!   the control API as text messages. Browsers cannot set headers on a
+//!   WebSocket, so this one also takes the token as `?token=`.
+//!
+//! Bodies are the responses of the control API.
+
+use crate::control::{self, ErrorCode};
+use crate::error::Error;
+use crate::events::{EventBus, Subscription};
+use crate::instance::{InstanceChannel, InstanceCommand};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde_json::Value;
+use std::io::{self, Cursor, Read, Write};
+use std::net::Ipv4Addr;
+use std::thread::JoinHandle;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+const API_PREFIX: &str = "/v1/";
+const EVENTS_PATH: &str = "events";
+
+pub struct HttpServer {
+    server: Server,
+    token: String,
+}
+
+impl HttpServer {
+    /// Listen on `port` of the loopback interface only, 0 picking a free port.
+    pub fn bind(port: u16, token: &str) -> Result<HttpServer, Error> {
+        let server =
+            Server::http((Ipv4Addr::LOCALHOST, port)).map_err(|err| Error::HttpServer {
+                port,
+                message: err.to_string(),
+            })?;
+
+        Ok(HttpServer {
+            server,
+            token: token.to_string(),
+        })
+    }
+
+    /// Serve requests on a worker thread, running commands through the
+    /// channel returned by `channel`, which is created on that thread.
+    pub fn spawn<C: InstanceChannel>(
+        self,
+        events: EventBus,
+        channel: impl FnOnce() -> C + Send + 'static,
+    ) -> io::Result<JoinHandle<()>> {
+        if let Some(addr) = self.server.server_addr().to_ip() {
+            info!("Serve HTTP on http://{}", addr);
+        }
+
+        std::thread::Builder::new()
+            .name("http".to_string())
+            .spawn(move || {
+                let mut channel = channel();
+                for request in self.server.incoming_requests() {
+                    handle_request(request, &self.token, &events, |command| {
+                        channel.forward(command)
+                    });
+                }
+            })
+    }
+}
+
+fn handle_request(
+    request: Request,
+    token: &str,
+    events: &EventBus,
+    dispatch: impl FnOnce(InstanceCommand) -> Result<Option<String>, Error>,
+) {
+    // CORS preflights of browsers carry no credentials.
+    if *request.method() == Method::Options {
+        let response = Response::empty(204)
+            .with_header(header("Access-Control-Allow-Methods", "GET, POST"))
+            .with_header(header("Access-Control-Allow-Headers", "Authorization"));
+        return respond(request, response);
+    }
+
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let route = path.strip_prefix(API_PREFIX).unwrap_or_default();
+    let query_token = Some(query)
+        .filter(|_| route == EVENTS_PATH)
+        .and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("token="))
+        });
+
+    let given_token = header_value(&request, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer ").map(str::to_string))
+        .or(query_token.map(str::to_string));
+    if !given_token.is_some_and(|given| tokens_match(&given, token)) {
+        let response = json_response(&control::Response::error(
+            Value::Null,
+            ErrorCode::Unauthorized,
+            "Expected \"Authorization: Bearer <token>\"",
+        ))
+        .with_header(header("WWW-Authenticate", "Bearer"));
+        return respond(request, response);
+    }
+
+    let method = request.method().clone();
+    let response = match (&method, InstanceCommand::from_name(route)) {
+        (Method::Get, _) if route == EVENTS_PATH => return upgrade_to_events(request, events),
+        (Method::Get, Some(InstanceCommand::Status)) => {
+            control::run_command(Value::Null, InstanceCommand::Status, dispatch)
+        }
+        (Method::Post, Some(command)) if command != InstanceCommand::Status => {
+            control::run_command(Value::Null, command, dispatch)
+        }
+        (_, Some(_)) => method_not_allowed(&method, path),
+        _ if route == EVENTS_PATH => method_not_allowed(&method, path),
+        _ => control::Response::error(
+            Value::Null,
+            ErrorCode::UnknownCommand,
+            format!("Unknown path \"{}\"", path),
+        ),
+    };
+
+    respond(request, json_response(&response));
+}
+
+fn method_not_allowed(method: &Method, path: &str) -> control::Response {
+    control::Response::error(
+        Value::Null,
+        ErrorCode::MethodNotAllowed,
+        format!("{} does not apply to {}", method, path),
+    )
+}
+
+/// Answer the handshake and stream events from another thread.
+fn upgrade_to_events(request: Request, events: &EventBus) {
+    let is_websocket = header_value(&request, "Upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let Some(key) = header_value(&request, "Sec-WebSocket-Key").filter(|_| is_websocket) else {
+        let response = control::Response::error(
+            Value::Null,
+            ErrorCode::InvalidRequest,
+            "Expected a WebSocket upgrade",
+        );
+        return respond(request, json_response(&response));
+    };
+
+    // Subscribe first, so no event is lost after the handshake.
+    let subscription = events.subscribe();
+    let response = Response::empty(101).with_header(header(
+        "Sec-WebSocket-Accept",
+        &derive_accept_key(key.as_bytes()),
+    ));
+    let stream = request.upgrade("websocket", response);
+
+    let spawned = std::thread::Builder::new()
+        .name("http-events".to_string())
+        .spawn(move || {
+            stream_events(
+                WebSocket::from_raw_socket(stream, Role::Server, None),
+                subscription,
+            )
+        });
+    if let Err(err) = spawned {
+        error!("Could not stream events: {}", err);
+    }
+}
+
+fn stream_events<S: Read + Write>(mut socket: WebSocket<S>, subscription: Subscription) {
+    while let Some(line) = control::next_event_line(&subscription) {
+        let text = serde_json::to_string(&line).expect("events are plain data");
+        if let Err(err) = socket.send(Message::text(text)) {
+            debug!("WebSocket subscriber disconnected: {}", err);
+            return;
+        }
+    }
+
+    let _ = socket.close(None);
+    let _ = socket.flush();
+}
+
+/// Compare tokens in a time that does not depend on where they differ.
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn header_value(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str().trim().to_string())
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn json_response(response: &control::Response) -> Response<Cursor<Vec<u8>>> {
+    let status = match response.error.as_ref().map(|error| error.code) {
+        None => 200,
+        Some(ErrorCode::Unauthorized) => 401,
+        Some(ErrorCode::UnknownCommand) => 404,
+        Some(ErrorCode::MethodNotAllowed) => 405,
+        Some(ErrorCode::TrayUnavailable) => 503,
+        Some(_) => 400,
+    };
+
+    Response::from_data(serde_json::to_vec(response).expect("responses are plain data"))
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn respond<R: Read>(request: Request, response: Response<R>) {
+    let response = response.with_header(header("Access-Control-Allow-Origin", "*"));
+    if let Err(err) = request.respond(response) {
+        debug!("Could not answer HTTP request: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::MixerEvent;
+    use crate::fake_platform::RecordingChannel;
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::mpsc;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn start() -> (SocketAddr, EventBus, mpsc::Receiver<InstanceCommand>) {
+        let server = HttpServer::bind(0, TOKEN).unwrap();
+        let addr = server.server.server_addr().to_ip().unwrap();
+        let events = EventBus::default();
+        let (sender, commands) = mpsc::channel();
+        server
+            .spawn(events.clone(), move || RecordingChannel(sender))
+            .unwrap();
+
+        (addr, events, commands)
+    }
+
+    fn request(addr: SocketAddr, method: &str, path: &str, token: Option<&str>) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: 0\r\n{}\r\n",
+            method, path, addr, authorization
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn runs_commands_with_token() {
+        let (addr, _events, commands) = start();
+
+        let (status, body) = request(addr, "POST", "/v1/toggle", Some(TOKEN));
+        assert_eq!(status, 200);
+        assert_eq!(body, serde_json::json!({"v": 1, "ok": true}));
+        assert_eq!(
+            request(addr, "POST", "/v1/restart-mixer", Some(TOKEN)).0,
+            200
+        );
+        assert_eq!(
+            commands.try_iter().collect::<Vec<_>>(),
+            vec![InstanceCommand::Toggle, InstanceCommand::RestartMixer]
+        );
+
+        // No status reply from the recording channel.
+        let (status, body) = request(addr, "GET", "/v1/status", Some(TOKEN));
+        assert_eq!(status, 503);
+        assert_eq!(body["error"]["code"], "tray-unavailable");
+    }
+
+    #[test]
+    fn rejects_missing_token_and_unknown_routes() {
+        let (addr, _events, commands) = start();
+
+        let (status, body) = request(addr, "POST", "/v1/toggle", None);
+        assert_eq!(status, 401);
+        assert_eq!(body["error"]["code"], "unauthorized");
+        assert_eq!(
+            request(addr, "POST", "/v1/toggle", Some("0123456789abcdeF")).0,
+            401
+        );
+        assert_eq!(
+            request(addr, "POST", "/v1/toggle?token=0123456789abcdef", None).0,
+            401
+        );
+        assert_eq!(request(addr, "GET", "/v1/toggle", Some(TOKEN)).0, 405);
+        assert_eq!(request(addr, "POST", "/v1/status", Some(TOKEN)).0, 405);
+        assert_eq!(request(addr, "POST", "/v1/mute", Some(TOKEN)).0, 404);
+        assert_eq!(request(addr, "GET", "/v1/events", Some(TOKEN)).0, 400);
+        assert_eq!(request(addr, "OPTIONS", "/v1/toggle", None).0, 204);
+        assert_eq!(commands.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn streams_events_over_websocket() {
+        let (addr, events, _commands) = start();
+        let url = format!("ws://{}/v1/events?token={}", addr, TOKEN);
+        let (mut socket, _) = tungstenite::client(url, TcpStream::connect(addr).unwrap()).unwrap();
+
+        events.publish(MixerEvent::MixerHidden);
+        events.publish(MixerEvent::MixerRestarted { pid: 5 });
+        events.close();
+
+        let mut next = || -> Value {
+            match socket.read().unwrap() {
+                Message::Text(text) => serde_json::from_str(&text).unwrap(),
+                message => panic!("unexpected message {:?}", message),
+            }
+        };
+        assert_eq!(next(), serde_json::json!({"v": 1, "event": "mixer-hidden"}));
+        assert_eq!(
+            next(),
+            serde_json::json!({"v": 1, "event": "mixer-restarted", "pid": 5})
+        );
+        assert!(matches!(socket.read(), Ok(Message::Close(_))));
+    }
+}
@@ -0,0 +1,275 @@
+//! Single-instance guard. A second launch hands its command to the tray that
+//! is already running and exits.
+
+use crate::error::Error;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::{Serialize, Serializer};
+use std::time::Duration;
+
+/// Prefix of forwarded commands, versioned so an older tray ignores a newer protocol.
+const MESSAGE_PREFIX: &str = "volume_mixer/1 ";
+
+/// How often to look for the running tray, which may still be starting up.
+const FORWARD_RETRIES: u32 = 10;
+const FORWARD_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// What a launch asks the running tray to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstanceCommand {
+    Show,
+    Toggle,
+    Hide,
+    /// Reload the config file even if it did not change.
+    Reload,
+    RestartMixer,
+    /// Answered with an encoded `MixerStatus`.
+    Status,
+    Quit,
+}
+
+impl InstanceCommand {
+    pub const ALL: [InstanceCommand; 7] = [
+        InstanceCommand::Show,
+        InstanceCommand::Toggle,
+        InstanceCommand::Hide,
+        InstanceCommand::Reload,
+        InstanceCommand::RestartMixer,
+        InstanceCommand::Status,
+        InstanceCommand::Quit,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InstanceCommand::Show => "show",
+            InstanceCommand::Toggle => "toggle",
+            InstanceCommand::Hide => "hide",
+            InstanceCommand::Reload => "reload",
+            InstanceCommand::RestartMixer => "restart-mixer",
+            InstanceCommand::Status => "status",
+            InstanceCommand::Quit => "quit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<InstanceCommand> {
+        Self::ALL
+            .into_iter()
+            .find(|command| command.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the command starts the tray when none is running yet.
+    pub fn starts_tray(self) -> bool {
+        matches!(self, InstanceCommand::Show | InstanceCommand::Toggle)
+    }
+
+    /// Message sent to the running tray.
+    pub fn encode(self) -> Vec<u8> {
+        format!("{}{}", MESSAGE_PREFIX, self.name()).into_bytes()
+    }
+
+    pub fn decode(message: &[u8]) -> Option<InstanceCommand> {
+        std::str::from_utf8(message)
+            .ok()?
+            .strip_prefix(MESSAGE_PREFIX)
+            .and_then(Self::from_name)
+    }
+}
+
+/// State of the mixer as reported to `status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct MixerStatus {
+    pub pid: u32,
+    pub hwnd: isize,
+    /// `false` while the supervisor waits to restart the mixer.
+    pub running: bool,
+    pub visible: bool,
+    #[serde(rename = "uptime_secs", serialize_with = "serialize_secs")]
+    pub uptime: Option<Duration>,
+    pub restart_count: u32,
+}
+
+fn serialize_secs<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_secs())
+        .serialize(serializer)
+}
+
+impl MixerStatus {
+    /// Reply sent back to the launch that asked for the status.
+    pub fn encode(&self) -> String {
+        let mut reply = format!(
+            "pid={}\nhwnd={}\nrunning={}\nvisible={}\nrestart_count={}\n",
+            self.pid, self.hwnd, self.running, self.visible, self.restart_count
+        );
+        if let Some(uptime) = self.uptime {
+            reply.push_str(&format!("uptime_ms={}\n", uptime.as_millis()));
+        }
+
+        reply
+    }
+
+    pub fn decode(reply: &str) -> Option<MixerStatus> {
+        let field = |name: &str| {
+            reply
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+        };
+
+        Some(MixerStatus {
+            pid: field("pid")?.parse().ok()?,
+            hwnd: field("hwnd")?.parse().ok()?,
+            running: field("running")?.parse().ok()?,
+            visible: field("visible")?.parse().ok()?,
+            uptime: match field("uptime_ms") {
+                Some(millis) => Some(Duration::from_millis(millis.parse().ok()?)),
+                None => None,
+            },
+            restart_count: field("restart_count")?.parse().ok()?,
+        })
+    }
+}
+
+/// Lock that only one tray can hold, and the way to reach the tray holding it.
+pub trait InstanceChannel {
+    /// Take the lock for the lifetime of the channel, `false` if another tray holds it.
+    fn try_lock(&mut self) -> Result<bool, Error>;
+
+    /// Send `command` to the tray holding the lock, returning its reply if any.
+    fn forward(&mut self, command: InstanceCommand) -> Result<Option<String>, Error>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Startup {
+    /// No other tray is running, this one continues.
+    Primary,
+    /// The command was handed to the running tray, this one exits.
+    Forwarded {
+        command: InstanceCommand,
+        reply: Option<String>,
+    },
+}
+
+/// Become the only running tray, or forward `command` to the one that is. A
+/// plain second launch shows the mixer.
+pub fn claim_instance<C: InstanceChannel>(
+    channel: &mut C,
+    command: Option<InstanceCommand>,
+) -> Result<Startup, Error> {
+    if channel.try_lock()? {
+        return Ok(Startup::Primary);
+    }
+
+    let command = command.unwrap_or(InstanceCommand::Show);
+    let mut result = channel.forward(command);
+    for _ in 0..FORWARD_RETRIES {
+        // Only a tray that could not be reached may still be starting up.
+        if !matches!(result, Err(Error::InstanceForward(_))) {
+            break;
+        }
+        std::thread::sleep(FORWARD_RETRY_DELAY);
+        result = channel.forward(command);
+    }
+
+    result.map(|reply| Startup::Forwarded { command, reply })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_platform::FakePlatform;
+
+    #[test]
+    fn messages_round_trip() {
+        for command in InstanceCommand::ALL {
+            assert_eq!(InstanceCommand::decode(&command.encode()), Some(command));
+        }
+        assert_eq!(InstanceCommand::decode(b"toggle"), None);
+        assert_eq!(InstanceCommand::decode(b"volume_mixer/2 toggle"), None);
+        assert_eq!(InstanceCommand::decode(&[0xFF, 0xFE]), None);
+    }
+
+    #[test]
+    fn status_round_trips() {
+        let mut status = MixerStatus {
+            pid: 42,
+            hwnd: -7,
+            running: true,
+            visible: false,
+            uptime: Some(Duration::from_millis(61_500)),
+            restart_count: 2,
+        };
+        assert_eq!(MixerStatus::decode(&status.encode()), Some(status));
+
+        status.uptime = None;
+        assert_eq!(MixerStatus::decode(&status.encode()), Some(status));
+        assert_eq!(MixerStatus::decode("pid=42\n"), None);
+    }
+
+    #[test]
+    fn first_launch_becomes_primary() {
+        let mut platform = FakePlatform::default();
+
+        assert_eq!(
+            claim_instance(&mut platform, Some(InstanceCommand::Toggle)),
+            Ok(Startup::Primary)
+        );
+        assert!(platform.state().forwarded.is_empty());
+    }
+
+    #[test]
+    fn second_launch_forwards_command() {
+        let mut platform = FakePlatform::default();
+        platform.state_mut().instance_running = true;
+
+        assert_eq!(
+            claim_instance(&mut platform, None),
+            Ok(Startup::Forwarded {
+                command: InstanceCommand::Show,
+                reply: None,
+            })
+        );
+        platform.state_mut().instance_reply = Some("pid=1".to_string());
+        assert_eq!(
+            claim_instance(&mut platform, Some(InstanceCommand::Status)),
+            Ok(Startup::Forwarded {
+                command: InstanceCommand::Status,
+                reply: Some("pid=1".to_string()),
+            })
+        );
+        assert_eq!(
+            platform.state().forwarded,
+            vec![InstanceCommand::Show, InstanceCommand::Status]
+        );
+    }
+
+    #[test]
+    fn busy_tray_reports_command_not_taken() {
+        let mut platform = FakePlatform::default();
+        platform.state_mut().instance_running = true;
+        platform.state_mut().instance_busy = true;
+
+        assert_eq!(
+            claim_instance(&mut platform, Some(InstanceCommand::Quit)),
+            Err(Error::TrayBusy {
+                command: "quit".to_string(),
+            })
+        );
+        assert!(platform.state().forwarded.is_empty());
+    }
+
+    #[test]
+    fn forwarding_waits_for_starting_tray() {
+        let mut platform = FakePlatform::default();
+        platform.state_mut().instance_running = true;
+        platform.state_mut().forward_failures = 2;
+
+        assert!(matches!(
+            claim_instance(&mut platform, Some(InstanceCommand::Reload)),
+            Ok(Startup::Forwarded { .. })
+        ));
+        assert_eq!(platform.state().forwarded, vec![InstanceCommand::Reload]);
+    }
+}
@@ -1,52 +1,263 @@
 #![windows_subsystem = "windows"]
 
+// Off Windows `main` is a stub, so the platform-neutral core is only
+// compiled there for its unit tests.
+#[cfg(any(windows, test))]
+mod app;
+#[cfg(any(windows, test))]
+mod cli;
+#[cfg(any(windows, test))]
+mod config;
+#[cfg(any(windows, test))]
+mod config_watcher;
+#[cfg(any(windows, test))]
+mod control;
+#[cfg(any(windows, test))]
+mod dpi;
+#[cfg(any(windows, test))]
+mod error;
+#[cfg(any(windows, test))]
+mod events;
+#[cfg(test)]
+mod fake_platform;
+#[cfg(any(windows, test))]
+mod hotkey;
+#[cfg(any(windows, test))]
+mod http_api;
+#[cfg(any(windows, test))]
+mod instance;
+#[cfg(any(windows, test))]
+mod menu;
+#[cfg(windows)]
 mod message_only_window;
+#[cfg(any(windows, test))]
+mod mqtt;
+#[cfg(any(windows, test))]
+mod mqtt_bridge;
+#[cfg(any(windows, test))]
+mod placement;
+#[cfg(any(windows, test))]
+mod platform;
+#[cfg(any(windows, test))]
+mod process_query;
+#[cfg(any(windows, test))]
+mod shutdown;
+#[cfg(any(windows, test))]
+mod supervisor;
+#[cfg(any(windows, test))]
 mod volume_mixer_process;
+#[cfg(any(windows, test))]
 mod volume_mixer_tray_icon;
+#[cfg(windows)]
+mod win32_platform;
+#[cfg(any(windows, test))]
+mod window_match;
+#[cfg(any(windows, test))]
+mod window_wait;
+#[cfg(windows)]
 mod windows_utils;
 
-use crate::volume_mixer_tray_icon::VolumeMixerTrayIcon;
-use crate::volume_mixer_tray_icon::PROP_VOLUME_MIXER_HWND;
-use crate::windows_utils::ExtendPCWSTR;
+#[cfg(windows)]
+use crate::cli::{Cli, USAGE};
 use env_logger::Builder;
 #[allow(unused_imports)]
-use log::{debug, error, info, trace, warn};
-use message_only_window::MessageOnlyWindow;
+use log::{debug, error, info, trace, warn, LevelFilter};
 use std::io::Write;
-use volume_mixer_process::VolumeMixerProcess;
-use windows::core::{Error, PCWSTR};
-use windows::Win32::Foundation::HANDLE;
-use windows::Win32::UI::WindowsAndMessaging::{
-    DispatchMessageW, GetMessageW, RemovePropW, SetPropW, TranslateMessage, MSG, WNDPROC,
-};
-
-fn main() -> Result<(), String> {
-    init_logger();
-
-    let volume_mixer_process = VolumeMixerProcess::new()?;
-    info!("Run Volue Mixer with pid {}", volume_mixer_process.pid);
-
-    let msg_only_window = MessageOnlyWindow::new(
-        "VolumeMixerWindowClass",
-        &WNDPROC::Some(VolumeMixerTrayIcon::wnd_proc),
-    )?;
+#[cfg(windows)]
+use std::path::Path;
+use std::process::ExitCode;
+
+#[cfg(windows)]
+const WINDOW_CLASS_NAME: &str = "VolumeMixerWindowClass";
+#[cfg(windows)]
+const CONFIG_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+#[cfg(windows)]
+const SUPERVISE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[cfg(windows)]
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        // Commands print their result, or at least their errors.
+        windows_utils::attach_parent_console();
+    }
+
+    let cli = match Cli::parse(args) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(crate::error::Error::Cli(err).exit_code());
+        }
+    };
+    if cli.help {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    init_logger(cli.log_level);
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{}", err);
+            eprintln!("{}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn main() -> ExitCode {
+    init_logger(None);
+
+    error!("Volume Mixer tray is only supported on Windows");
+    ExitCode::FAILURE
+}
+
+#[cfg(windows)]
+fn run(cli: Cli) -> Result<(), crate::error::Error> {
+    use crate::app::App;
+    use crate::cli::format_status;
+    use crate::config_watcher::ConfigWatcher;
+    use crate::error::{Error, OsError};
+    use crate::http_api::HttpServer;
+    use crate::instance::{claim_instance, InstanceCommand, MixerStatus, Startup};
+    use crate::mqtt_bridge::MqttBridge;
+    use crate::platform::{EventHandler, Timer};
+    use crate::win32_platform::{
+        enable_per_monitor_dpi_awareness, set_timer, wnd_proc, Win32ControlListener,
+        Win32InstanceChannel, Win32Platform, Win32TrayShell, PROP_EVENT_HANDLER,
+    };
+    use message_only_window::MessageOnlyWindow;
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::UI::WindowsAndMessaging::{
+        DispatchMessageW, GetMessageW, RemovePropW, SetPropW, TranslateMessage, MSG, WNDPROC,
+    };
+
+    type Win32App = App<Win32Platform, Win32TrayShell>;
+
+    // Held until the tray exits.
+    let mut instance_channel = Win32InstanceChannel::new(WINDOW_CLASS_NAME);
+    match claim_instance(&mut instance_channel, cli.command)? {
+        Startup::Primary => {}
+        Startup::Forwarded {
+            command: InstanceCommand::Status,
+            reply,
+        } => {
+            let status = reply
+                .as_deref()
+                .and_then(MixerStatus::decode)
+                .ok_or(Error::InvalidReply)?;
+            println!("{}", format_status(&status, cli.json));
+            return Ok(());
+        }
+        Startup::Forwarded { command, .. } => {
+            info!("Tray is already running, forwarded \"{}\"", command.name());
+            return Ok(());
+        }
+    }
+    if let Some(command) = cli.command.filter(|_| !cli.starts_tray()) {
+        return match command {
+            InstanceCommand::Quit | InstanceCommand::Hide => {
+                info!("No tray is running, nothing to {}", command.name());
+                Ok(())
+            }
+            _ => Err(Error::TrayNotRunning {
+                command: command.name().to_string(),
+            }),
+        };
+    }
+
+    if let Err(err) = enable_per_monitor_dpi_awareness() {
+        warn!("Mixer may be misplaced on scaled monitors: {}", err);
+    }
+
+    let config_path = cli.config_path.clone().or_else(config::default_config_path);
+    let settings = load_settings(config_path.as_deref())?;
+    let http_settings = settings.http.clone();
+    let mqtt_settings = settings.mqtt.clone();
+    let config_watcher = config_path.as_deref().map(ConfigWatcher::new);
+
+    let msg_only_window =
+        MessageOnlyWindow::new(WINDOW_CLASS_NAME, &WNDPROC::Some(wnd_proc::<Win32App>))?;
     info!("Create hidden message-only window");
 
+    let mut app = App::new(
+        Win32Platform,
+        Win32TrayShell::new(msg_only_window.hwnd),
+        settings,
+        config_watcher,
+        !cli.no_tray,
+    )?;
+
     let set_prop_result = unsafe {
         SetPropW(
             msg_only_window.hwnd,
-            PCWSTR::from_str(PROP_VOLUME_MIXER_HWND),
-            HANDLE(volume_mixer_process.hwnd.0),
+            &HSTRING::from(PROP_EVENT_HANDLER),
+            HANDLE(&mut app as *mut Win32App as isize),
         )
     };
     if let Err(err) = set_prop_result.ok() {
-        return Err(format!(
-            "SetPropW failed for \"{}\": {}",
-            PROP_VOLUME_MIXER_HWND, err
-        ));
+        return Err(Error::PropertyStore {
+            property: PROP_EVENT_HANDLER.to_string(),
+            source: OsError::from_win32("SetPropW", err),
+        });
+    }
+
+    if let Some(command) = cli.command {
+        app.handle_instance_command(command);
+    }
+
+    // Commands of external tools reach the window like those of later launches.
+    let control_server = windows_utils::current_session_id()
+        .and_then(|session_id| Win32ControlListener::bind(control::pipe_name(session_id)))
+        .and_then(|listener| {
+            control::spawn_server(listener, app.events().clone(), || {
+                Win32InstanceChannel::new(WINDOW_CLASS_NAME)
+            })
+            .map_err(|err| Error::ControlServer(OsError::from_io("CreateThread", &err)))
+        });
+    if let Err(err) = control_server {
+        warn!("External tools cannot control the tray: {}", err);
+    }
+    if http_settings.enabled {
+        let http_server =
+            HttpServer::bind(http_settings.port, &http_settings.token).and_then(|server| {
+                server
+                    .spawn(app.events().clone(), || {
+                        Win32InstanceChannel::new(WINDOW_CLASS_NAME)
+                    })
+                    .map_err(|err| Error::HttpServer {
+                        port: http_settings.port,
+                        message: err.to_string(),
+                    })
+            });
+        if let Err(err) = http_server {
+            warn!("Dashboards cannot control the tray: {}", err);
+        }
+    }
+    if mqtt_settings.enabled {
+        let mqtt_bridge = MqttBridge::new(mqtt_settings).spawn(app.events().clone(), || {
+            Win32InstanceChannel::new(WINDOW_CLASS_NAME)
+        });
+        if let Err(err) = mqtt_bridge {
+            warn!("Home automation cannot reach the tray over MQTT: {}", err);
+        }
     }
 
-    let _volume_mixer_tray_icon = VolumeMixerTrayIcon::new(msg_only_window.hwnd);
+    if config_path.is_some() {
+        if let Err(err) = set_timer(
+            msg_only_window.hwnd,
+            Timer::ConfigReload,
+            CONFIG_POLL_INTERVAL,
+        ) {
+            warn!("Config file changes will not be applied: {}", err);
+        }
+    }
+    if let Err(err) = set_timer(msg_only_window.hwnd, Timer::Supervise, SUPERVISE_INTERVAL) {
+        warn!("Volume mixer will not be restarted when it exits: {}", err);
+    }
 
     let mut msg = MSG::default();
     loop {
@@ -61,25 +272,39 @@ fn main() -> Result<(), String> {
         }
     }
 
-    let remove_prop_result = unsafe {
-        RemovePropW(
-            msg_only_window.hwnd,
-            PCWSTR::from_str(PROP_VOLUME_MIXER_HWND),
-        )
-    };
+    let remove_prop_result =
+        unsafe { RemovePropW(msg_only_window.hwnd, &HSTRING::from(PROP_EVENT_HANDLER)) };
     if remove_prop_result.is_err() {
         error!(
-            "RemovePropW failed for \"{}\": {}",
-            PROP_VOLUME_MIXER_HWND,
-            Error::from_win32()
+            "{}",
+            Error::PropertyStore {
+                property: PROP_EVENT_HANDLER.to_string(),
+                source: OsError::last_win32("RemovePropW"),
+            }
         );
     }
 
     Ok(())
 }
 
-fn init_logger() {
-    Builder::from_default_env()
+#[cfg(windows)]
+fn load_settings(config_path: Option<&Path>) -> Result<config::Settings, config::ConfigError> {
+    if let Some(config_path) = config_path {
+        info!("Load settings from \"{}\"", config_path.display());
+        config::load_settings(config_path)
+    } else {
+        warn!("Could not determine config directory, use default settings");
+        Ok(config::Settings::default())
+    }
+}
+
+/// `level` overrides the filter from `RUST_LOG`.
+fn init_logger(level: Option<LevelFilter>) {
+    let mut builder = Builder::from_default_env();
+    if let Some(level) = level {
+        builder.filter_level(level);
+    }
+    builder
         .format(|buf, record| {
             let style = buf.default_level_style(record.level());
 
@@ -0,0 +1,165 @@
+/// Commands of the tray context menu, independent of how the menu is rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuCommand {
+    ToggleMixer,
+    OpenSoundSettings,
+    RestartMixer,
+    OpenSettings,
+    About,
+    Exit,
+}
+
+impl MenuCommand {
+    const ALL: [MenuCommand; 6] = [
+        MenuCommand::ToggleMixer,
+        MenuCommand::OpenSoundSettings,
+        MenuCommand::RestartMixer,
+        MenuCommand::OpenSettings,
+        MenuCommand::About,
+        MenuCommand::Exit,
+    ];
+
+    /// Identifier passed through the native menu, never 0 as that means "no selection".
+    pub fn id(self) -> u16 {
+        match self {
+            MenuCommand::ToggleMixer => 1,
+            MenuCommand::OpenSoundSettings => 2,
+            MenuCommand::RestartMixer => 3,
+            MenuCommand::OpenSettings => 4,
+            MenuCommand::About => 5,
+            MenuCommand::Exit => 6,
+        }
+    }
+
+    pub fn from_id(id: u16) -> Option<MenuCommand> {
+        Self::ALL.into_iter().find(|command| command.id() == id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MenuItem {
+    Command {
+        command: MenuCommand,
+        label: String,
+        enabled: bool,
+        /// Rendered in bold, the action of a left click on the icon.
+        default: bool,
+    },
+    Separator,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Menu {
+    pub items: Vec<MenuItem>,
+}
+
+impl Menu {
+    pub fn new() -> Menu {
+        Menu::default()
+    }
+
+    pub fn command(mut self, command: MenuCommand, label: &str) -> Menu {
+        self.items.push(MenuItem::Command {
+            command,
+            label: label.to_string(),
+            enabled: true,
+            default: false,
+        });
+        self
+    }
+
+    /// Mark the last added command as the default one.
+    pub fn default_item(mut self) -> Menu {
+        if let Some(MenuItem::Command { default, .. }) = self.items.last_mut() {
+            *default = true;
+        }
+        self
+    }
+
+    pub fn separator(mut self) -> Menu {
+        self.items.push(MenuItem::Separator);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn commands(&self) -> impl Iterator<Item = MenuCommand> + '_ {
+        self.items.iter().filter_map(|item| match item {
+            MenuItem::Command { command, .. } => Some(*command),
+            MenuItem::Separator => None,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn label(&self, command: MenuCommand) -> Option<&str> {
+        self.items.iter().find_map(|item| match item {
+            MenuItem::Command {
+                command: item_command,
+                label,
+                ..
+            } if *item_command == command => Some(label.as_str()),
+            _ => None,
+        })
+    }
+}
+
+pub fn tray_menu(mixer_visible: bool) -> Menu {
+    let toggle_label = if mixer_visible {
+        "Hide mixer"
+    } else {
+        "Show mixer"
+    };
+
+    Menu::new()
+        .command(MenuCommand::ToggleMixer, toggle_label)
+        .default_item()
+        .command(MenuCommand::OpenSoundSettings, "Open Sound settings")
+        .command(MenuCommand::RestartMixer, "Restart mixer")
+        .separator()
+        .command(MenuCommand::OpenSettings, "Settings")
+        .command(MenuCommand::About, "About")
+        .separator()
+        .command(MenuCommand::Exit, "Exit")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_ids_round_trip() {
+        for command in MenuCommand::ALL {
+            assert_ne!(command.id(), 0);
+            assert_eq!(MenuCommand::from_id(command.id()), Some(command));
+        }
+        assert_eq!(MenuCommand::from_id(0), None);
+    }
+
+    #[test]
+    fn tray_menu_contains_every_command_once() {
+        let menu = tray_menu(false);
+        let commands: Vec<MenuCommand> = menu.commands().collect();
+
+        assert_eq!(commands, MenuCommand::ALL);
+        assert_eq!(
+            menu.items[0],
+            MenuItem::Command {
+                command: MenuCommand::ToggleMixer,
+                label: "Show mixer".to_string(),
+                enabled: true,
+                default: true,
+            }
+        );
+    }
+
+    #[test]
+    fn toggle_label_follows_mixer_visibility() {
+        assert_eq!(
+            tray_menu(true).label(MenuCommand::ToggleMixer),
+            Some("Hide mixer")
+        );
+        assert_eq!(
+            tray_menu(false).label(MenuCommand::ToggleMixer),
+            Some("Show mixer")
+        );
+    }
+}
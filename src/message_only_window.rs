@@ -1,8 +1,8 @@
-use crate::windows_utils::ExtendPCWSTR;
+use crate::error::{Error, OsError};
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
-use windows::core::{Error, PCWSTR};
-use windows::Win32::Foundation::HWND;
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{GetLastError, ERROR_CLASS_ALREADY_EXISTS, HWND};
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
 use windows::Win32::UI::WindowsAndMessaging::{
     CreateWindowExW, DestroyWindow, RegisterClassW, CS_VREDRAW, CW_USEDEFAULT, HMENU, HWND_MESSAGE,
@@ -15,31 +15,38 @@ pub struct MessageOnlyWindow {
 }
 
 impl MessageOnlyWindow {
-    pub fn new(window_class_name: &str, wnd_proc: &WNDPROC) -> Result<MessageOnlyWindow, String> {
-        let hinstance = { unsafe { GetModuleHandleW(PCWSTR::null()).unwrap() } };
-        let utf16_class_name = PCWSTR::from_str(window_class_name);
+    pub fn new(window_class_name: &str, wnd_proc: &WNDPROC) -> Result<MessageOnlyWindow, Error> {
+        let hinstance = { unsafe { GetModuleHandleW(PCWSTR::null()) } }
+            .map_err(|err| Error::WindowCreation(OsError::from_win32("GetModuleHandleW", err)))?;
+        // Outlives the registration and the window creation below.
+        let class_name = HSTRING::from(window_class_name);
 
         let window_class = WNDCLASSW {
             style: CS_VREDRAW,
             lpfnWndProc: wnd_proc.to_owned(),
             hInstance: hinstance,
-            lpszClassName: utf16_class_name,
+            lpszClassName: PCWSTR(class_name.as_ptr()),
 
             ..Default::default()
         };
 
+        // Windows of the same class on several threads share the registration.
         let register_result = { unsafe { RegisterClassW(&window_class) } };
         if register_result != 0 {
             debug!("Register window class");
+        } else if unsafe { GetLastError() } == ERROR_CLASS_ALREADY_EXISTS {
+            debug!("Window class is already registered");
         } else {
-            return Err(format!("RegisterClassW failed: {}", Error::from_win32()));
+            return Err(Error::WindowClassRegistration(OsError::last_win32(
+                "RegisterClassW",
+            )));
         }
 
         let hwnd = {
             unsafe {
                 CreateWindowExW(
                     WINDOW_EX_STYLE::default(),
-                    utf16_class_name,
+                    &class_name,
                     PCWSTR::null(),
                     WS_MINIMIZE,
                     CW_USEDEFAULT,
@@ -55,7 +62,9 @@ impl MessageOnlyWindow {
         };
 
         if hwnd == HWND::default() {
-            return Err(format!("CreateWindowExW failed: {}", Error::from_win32()));
+            return Err(Error::WindowCreation(OsError::last_win32(
+                "CreateWindowExW",
+            )));
         }
 
         Ok(MessageOnlyWindow { hwnd })
@@ -0,0 +1,387 @@
+//! The part of MQTT 3.1.1 the bridge speaks: QoS 0 messages, retained
+//! messages, a last will and keep-alive pings. Both directions are encoded and
+//! decoded, so tests can play the broker.
+
+use std::io;
+
+const PROTOCOL_NAME: &str = "MQTT";
+const PROTOCOL_LEVEL: u8 = 4;
+
+/// Largest value of the variable-length "remaining length" field.
+const MAX_REMAINING_LEN: usize = 268_435_455;
+
+/// Return code of a `ConnAck` that accepted the connection.
+pub const CONNECTION_ACCEPTED: u8 = 0;
+/// Return code in a `SubAck` for a rejected topic filter.
+pub const SUBSCRIPTION_FAILED: u8 = 0x80;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// Kept by the broker for clients that subscribe later.
+    pub retain: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Connect {
+    pub client_id: String,
+    pub keep_alive_secs: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Published by the broker when the client disappears without `Disconnect`.
+    pub will: Option<Message>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Packet {
+    Connect(Connect),
+    ConnAck { session_present: bool, code: u8 },
+    Publish(Message),
+    Subscribe { packet_id: u16, topics: Vec<String> },
+    SubAck { packet_id: u16, codes: Vec<u8> },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+impl Message {
+    pub fn new(topic: impl Into<String>, payload: impl Into<Vec<u8>>, retain: bool) -> Message {
+        Message {
+            topic: topic.into(),
+            payload: payload.into(),
+            retain,
+        }
+    }
+}
+
+impl Packet {
+    /// Fails if a string or the will payload does not fit its 16-bit length
+    /// prefix, or the packet is too long for the "remaining length" field.
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        let header = match self {
+            Packet::Connect(connect) => {
+                put_str(&mut body, PROTOCOL_NAME)?;
+                body.push(PROTOCOL_LEVEL);
+                let mut flags = 0x02; // Clean session.
+                if let Some(will) = &connect.will {
+                    flags |= 0x04 | if will.retain { 0x20 } else { 0 };
+                }
+                if connect.password.is_some() {
+                    flags |= 0x40;
+                }
+                if connect.username.is_some() {
+                    flags |= 0x80;
+                }
+                body.push(flags);
+                body.extend_from_slice(&connect.keep_alive_secs.to_be_bytes());
+                put_str(&mut body, &connect.client_id)?;
+                if let Some(will) = &connect.will {
+                    put_str(&mut body, &will.topic)?;
+                    put_bytes(&mut body, &will.payload)?;
+                }
+                for field in [&connect.username, &connect.password].into_iter().flatten() {
+                    put_str(&mut body, field)?;
+                }
+                0x10
+            }
+            Packet::ConnAck {
+                session_present,
+                code,
+            } => {
+                body.extend_from_slice(&[*session_present as u8, *code]);
+                0x20
+            }
+            Packet::Publish(message) => {
+                put_str(&mut body, &message.topic)?;
+                body.extend_from_slice(&message.payload);
+                0x30 | message.retain as u8
+            }
+            Packet::Subscribe { packet_id, topics } => {
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                for topic in topics {
+                    put_str(&mut body, topic)?;
+                    body.push(0); // QoS 0.
+                }
+                0x82
+            }
+            Packet::SubAck { packet_id, codes } => {
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                body.extend_from_slice(codes);
+                0x90
+            }
+            Packet::PingReq => 0xC0,
+            Packet::PingResp => 0xD0,
+            Packet::Disconnect => 0xE0,
+        };
+        if body.len() > MAX_REMAINING_LEN {
+            return Err(invalid("packet is too long"));
+        }
+
+        let mut packet = vec![header];
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            if len == 0 {
+                packet.push(byte);
+                break;
+            }
+            packet.push(byte | 0x80);
+        }
+        packet.extend_from_slice(&body);
+        Ok(packet)
+    }
+
+    /// Decode the packet at the start of `buf` and how many bytes it took,
+    /// `None` while `buf` holds only part of it.
+    pub fn decode(buf: &[u8]) -> io::Result<Option<(Packet, usize)>> {
+        let Some(&header) = buf.first() else {
+            return Ok(None);
+        };
+
+        let mut len = 0;
+        let mut offset = 1;
+        loop {
+            let Some(&byte) = buf.get(offset) else {
+                return Ok(None);
+            };
+            len += ((byte & 0x7F) as usize) << (7 * (offset - 1));
+            offset += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if offset > 4 {
+                return Err(invalid("remaining length is longer than 4 bytes"));
+            }
+        }
+        if len > MAX_REMAINING_LEN {
+            return Err(invalid("remaining length is too large"));
+        }
+        let Some(body) = buf.get(offset..offset + len) else {
+            return Ok(None);
+        };
+
+        let mut reader = Reader { body };
+        let packet = match header >> 4 {
+            1 => {
+                if reader.str()? != PROTOCOL_NAME || reader.u8()? != PROTOCOL_LEVEL {
+                    return Err(invalid("only MQTT 3.1.1 is supported"));
+                }
+                let flags = reader.u8()?;
+                let keep_alive_secs = reader.u16()?;
+                let client_id = reader.str()?;
+                let will = if flags & 0x04 != 0 {
+                    Some(Message {
+                        topic: reader.str()?,
+                        payload: reader.bytes()?.to_vec(),
+                        retain: flags & 0x20 != 0,
+                    })
+                } else {
+                    None
+                };
+                let username = (flags & 0x80 != 0).then(|| reader.str()).transpose()?;
+                let password = (flags & 0x40 != 0).then(|| reader.str()).transpose()?;
+                Packet::Connect(Connect {
+                    client_id,
+                    keep_alive_secs,
+                    username,
+                    password,
+                    will,
+                })
+            }
+            2 => {
+                let session_present = reader.u8()? & 0x01 != 0;
+                Packet::ConnAck {
+                    session_present,
+                    code: reader.u8()?,
+                }
+            }
+            3 => {
+                let topic = reader.str()?;
+                // Messages above QoS 0 carry a packet id.
+                if header & 0x06 != 0 {
+                    reader.u16()?;
+                }
+                Packet::Publish(Message {
+                    topic,
+                    payload: reader.body.to_vec(),
+                    retain: header & 0x01 != 0,
+                })
+            }
+            8 => {
+                let packet_id = reader.u16()?;
+                let mut topics = Vec::new();
+                while !reader.body.is_empty() {
+                    topics.push(reader.str()?);
+                    reader.u8()?;
+                }
+                Packet::Subscribe { packet_id, topics }
+            }
+            9 => Packet::SubAck {
+                packet_id: reader.u16()?,
+                codes: reader.body.to_vec(),
+            },
+            12 => Packet::PingReq,
+            13 => Packet::PingResp,
+            14 => Packet::Disconnect,
+            kind => return Err(invalid(&format!("unsupported packet type {}", kind))),
+        };
+
+        Ok(Some((packet, offset + len)))
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(bytes.len()).map_err(|_| invalid("string is too long"))?;
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn put_str(buf: &mut Vec<u8>, value: &str) -> io::Result<()> {
+    put_bytes(buf, value.as_bytes())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct Reader<'a> {
+    body: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.body.len() < len {
+            return Err(invalid("packet is truncated"));
+        }
+        let (taken, rest) = self.body.split_at(len);
+        self.body = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| invalid("string is not UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(packet: Packet) {
+        let encoded = packet.encode().unwrap();
+        assert_eq!(
+            Packet::decode(&encoded).unwrap(),
+            Some((packet, encoded.len()))
+        );
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        round_trip(Packet::Connect(Connect {
+            client_id: "volume_mixer-pc".to_string(),
+            keep_alive_secs: 30,
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+            will: Some(Message::new("a/availability", "offline", true)),
+        }));
+        round_trip(Packet::Connect(Connect {
+            client_id: String::new(),
+            keep_alive_secs: 0,
+            username: None,
+            password: None,
+            will: None,
+        }));
+        round_trip(Packet::ConnAck {
+            session_present: false,
+            code: 5,
+        });
+        round_trip(Packet::Publish(Message::new("a/state", "ON", true)));
+        round_trip(Packet::Subscribe {
+            packet_id: 1,
+            topics: vec!["a/set".to_string(), "b/#".to_string()],
+        });
+        round_trip(Packet::SubAck {
+            packet_id: 1,
+            codes: vec![0, SUBSCRIPTION_FAILED],
+        });
+        round_trip(Packet::PingReq);
+        round_trip(Packet::PingResp);
+        round_trip(Packet::Disconnect);
+    }
+
+    #[test]
+    fn long_payloads_use_multi_byte_length() {
+        let packet = Packet::Publish(Message::new("t", vec![7; 300], false));
+        let encoded = packet.encode().unwrap();
+        // 2 + 1 + 300 bytes after the header, in two length bytes.
+        assert_eq!(&encoded[..3], &[0x30, 0xAF, 0x02]);
+        round_trip(packet);
+    }
+
+    #[test]
+    fn waits_for_complete_packets() {
+        let mut buf = Packet::Publish(Message::new("t", "payload", false))
+            .encode()
+            .unwrap();
+        buf.extend_from_slice(&Packet::PingResp.encode().unwrap());
+        let first_len = buf.len() - 2;
+
+        for len in 0..first_len {
+            assert_eq!(Packet::decode(&buf[..len]).unwrap(), None);
+        }
+        let (_, used) = Packet::decode(&buf).unwrap().unwrap();
+        assert_eq!(used, first_len);
+        assert_eq!(
+            Packet::decode(&buf[used..]).unwrap(),
+            Some((Packet::PingResp, 2))
+        );
+    }
+
+    #[test]
+    fn rejects_strings_longer_than_length_prefix() {
+        let topic = "t".repeat(usize::from(u16::MAX) + 1);
+        assert!(Packet::Publish(Message::new(topic.clone(), "ON", false))
+            .encode()
+            .is_err());
+        assert!(Packet::Connect(Connect {
+            client_id: "volume_mixer-pc".to_string(),
+            keep_alive_secs: 30,
+            username: None,
+            password: None,
+            will: Some(Message::new("a/availability", vec![0; 70_000], true)),
+        })
+        .encode()
+        .is_err());
+        assert!(Packet::Subscribe {
+            packet_id: 1,
+            topics: vec![topic],
+        }
+        .encode()
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        assert!(Packet::decode(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]).is_err());
+        assert!(Packet::decode(&[0x30, 0x02, 0x00, 0x05]).is_err());
+        assert!(Packet::decode(&[0x40, 0x02, 0x00, 0x01]).is_err());
+    }
+}
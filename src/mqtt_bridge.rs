@@ -0,0 +1,794 @@
+//! Optional bridge to an MQTT broker for home automation such as Home
+//! Assistant. Topics under `<topic_prefix>/<node_id>/`:
+//!
+//! - `availability` is `online`, or `offline` once the tray exits or the
+//!   broker loses the connection, the latter sent as last will.
+//! - `state` is `ON` while the mixer is visible and `OFF` otherwise.
+//! - `attributes` holds the `status` of the control API as JSON.
+//! - `event` gets the `MixerEvent`s of the control API, not retained.
+//! - `set` takes `ON`, `OFF` or a command name such as `toggle`. Retained
+//!   messages are ignored, so a stale command is not run on every reconnect.
+//!
+//! With a discovery prefix, Home Assistant finds a switch showing the mixer
+//! and a button restarting it. Lost connections are retried with a growing
+//! delay.
+
+use crate::config::MqttSettings;
+use crate::error::Error;
+use crate::events::{EventBus, MixerEvent, Received, Subscription};
+#[cfg(windows)]
+use crate::instance::InstanceChannel;
+use crate::instance::{InstanceCommand, MixerStatus};
+use crate::mqtt::{Connect, Message, Packet, CONNECTION_ACCEPTED, SUBSCRIPTION_FAILED};
+use crate::supervisor::RestartPolicy;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde_json::json;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(windows)]
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// A broker that stops reading drops the connection after this long instead
+/// of blocking the bridge.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for the broker before looking for events again.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Larger packets from the broker drop the connection instead of being buffered.
+const MAX_PACKET_LEN: usize = 64 * 1024;
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+pub struct MqttBridge {
+    settings: MqttSettings,
+    node_id: String,
+    reconnect: RestartPolicy,
+}
+
+struct Connection {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    last_received: Instant,
+    last_ping: Instant,
+}
+
+impl MqttBridge {
+    pub fn new(settings: MqttSettings) -> MqttBridge {
+        let node_id = if settings.node_id.is_empty() {
+            let computer_name = std::env::var("COMPUTERNAME")
+                .or_else(|_| std::env::var("HOSTNAME"))
+                .unwrap_or_default();
+            sanitize_node_id(&computer_name)
+        } else {
+            settings.node_id.clone()
+        };
+
+        MqttBridge {
+            settings,
+            node_id,
+            reconnect: RestartPolicy {
+                initial_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(60),
+                stable_after: Duration::from_secs(60),
+            },
+        }
+    }
+
+    /// Connect on a worker thread, running commands through the channel
+    /// returned by `channel`, which is created on that thread.
+    #[cfg(windows)]
+    pub fn spawn<C: InstanceChannel>(
+        self,
+        events: EventBus,
+        channel: impl FnOnce() -> C + Send + 'static,
+    ) -> io::Result<JoinHandle<()>> {
+        info!(
+            "Bridge to MQTT broker {}:{} as {}",
+            self.settings.host,
+            self.settings.port,
+            self.topic("")
+        );
+
+        std::thread::Builder::new()
+            .name("mqtt".to_string())
+            .spawn(move || {
+                let mut channel = channel();
+                self.run(&events, |command| channel.forward(command));
+            })
+    }
+
+    /// Stay connected until the tray exits.
+    fn run(
+        &self,
+        events: &EventBus,
+        mut dispatch: impl FnMut(InstanceCommand) -> Result<Option<String>, Error>,
+    ) {
+        let subscription = events.subscribe();
+        let mut failures = 0;
+
+        loop {
+            let started = Instant::now();
+            let err = match self.session(&subscription, &mut dispatch) {
+                Ok(()) => return,
+                Err(err) => err,
+            };
+
+            if started.elapsed() >= self.reconnect.stable_after {
+                failures = 0;
+            }
+            let delay = self.reconnect.delay(failures);
+            failures = failures.saturating_add(1);
+            warn!(
+                "MQTT connection to {}:{} failed: {}, retrying in {:?}",
+                self.settings.host, self.settings.port, err, delay
+            );
+            if !wait_for_retry(&subscription, delay) {
+                return;
+            }
+        }
+    }
+
+    /// Publish state and take commands until the tray exits or the
+    /// connection fails.
+    fn session(
+        &self,
+        subscription: &Subscription,
+        dispatch: &mut impl FnMut(InstanceCommand) -> Result<Option<String>, Error>,
+    ) -> io::Result<()> {
+        let mut connection = Connection::open(&self.settings.host, self.settings.port, {
+            let credential =
+                |value: &str| Some(value.to_string()).filter(|value| !value.is_empty());
+            Connect {
+                client_id: format!("volume_mixer-{}", self.node_id),
+                keep_alive_secs: KEEP_ALIVE.as_secs() as u16,
+                username: credential(&self.settings.username),
+                password: credential(&self.settings.password),
+                will: Some(Message::new(self.topic("availability"), OFFLINE, true)),
+            }
+        })?;
+        info!(
+            "Connected to MQTT broker {}:{}",
+            self.settings.host, self.settings.port
+        );
+
+        let set_topic = self.topic("set");
+        connection.send(&Packet::Subscribe {
+            packet_id: 1,
+            topics: vec![set_topic.clone()],
+        })?;
+        for message in self.discovery_messages() {
+            connection.send(&Packet::Publish(message))?;
+        }
+        connection.publish(self.topic("availability"), ONLINE, true)?;
+        self.publish_status(&mut connection, dispatch)?;
+
+        loop {
+            while let Some(packet) = connection.next_packet()? {
+                match packet {
+                    Packet::Publish(message) if message.topic == set_topic => {
+                        if message.retain {
+                            warn!(
+                                "Ignore retained MQTT command \"{}\"",
+                                String::from_utf8_lossy(&message.payload)
+                            );
+                        } else {
+                            run_command(&message.payload, &mut *dispatch)
+                        }
+                    }
+                    Packet::SubAck { codes, .. } if codes.contains(&SUBSCRIPTION_FAILED) => {
+                        warn!("MQTT broker refused the subscription to {}", set_topic)
+                    }
+                    Packet::SubAck { .. } | Packet::PingResp | Packet::Publish(_) => {}
+                    packet => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("unexpected packet {:?}", packet),
+                        ))
+                    }
+                }
+            }
+
+            loop {
+                match subscription.recv_timeout(Duration::ZERO) {
+                    Received::Event(event) => {
+                        let payload = serde_json::to_vec(&event).expect("events are plain data");
+                        connection.publish(self.topic("event"), payload, false)?;
+                        match event {
+                            MixerEvent::MixerShown => {
+                                connection.publish(self.topic("state"), "ON", true)?
+                            }
+                            MixerEvent::MixerHidden => {
+                                connection.publish(self.topic("state"), "OFF", true)?
+                            }
+                            MixerEvent::MixerRestarted { .. } | MixerEvent::ConfigReloaded => {
+                                self.publish_status(&mut connection, dispatch)?
+                            }
+                            MixerEvent::Exiting => return self.disconnect(connection),
+                        }
+                    }
+                    Received::Lagged { .. } => self.publish_status(&mut connection, dispatch)?,
+                    Received::Timeout => break,
+                    Received::Closed => return self.disconnect(connection),
+                }
+            }
+
+            connection.keep_alive()?;
+        }
+    }
+
+    fn publish_status(
+        &self,
+        connection: &mut Connection,
+        dispatch: &mut impl FnMut(InstanceCommand) -> Result<Option<String>, Error>,
+    ) -> io::Result<()> {
+        let status = match dispatch(InstanceCommand::Status) {
+            Ok(reply) => reply.as_deref().and_then(MixerStatus::decode),
+            Err(err) => {
+                warn!("Could not publish the mixer status: {}", err);
+                return Ok(());
+            }
+        };
+        let Some(status) = status else {
+            warn!(
+                "Could not publish the mixer status: {}",
+                Error::InvalidReply
+            );
+            return Ok(());
+        };
+
+        let state = if status.visible { "ON" } else { "OFF" };
+        connection.publish(self.topic("state"), state, true)?;
+        let attributes = serde_json::to_vec(&status).expect("status is plain data");
+        connection.publish(self.topic("attributes"), attributes, true)
+    }
+
+    fn disconnect(&self, mut connection: Connection) -> io::Result<()> {
+        connection.publish(self.topic("availability"), OFFLINE, true)?;
+        connection.send(&Packet::Disconnect)
+    }
+
+    /// Retained configs for Home Assistant MQTT discovery.
+    fn discovery_messages(&self) -> Vec<Message> {
+        let prefix = &self.settings.discovery_prefix;
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+
+        let device = json!({
+            "identifiers": [format!("volume_mixer_{}", self.node_id)],
+            "name": format!("Volume Mixer {}", self.node_id),
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let switch = json!({
+            "name": "Mixer",
+            "unique_id": format!("volume_mixer_{}_mixer", self.node_id),
+            "icon": "mdi:volume-high",
+            "state_topic": self.topic("state"),
+            "command_topic": self.topic("set"),
+            "availability_topic": self.topic("availability"),
+            "json_attributes_topic": self.topic("attributes"),
+            "device": device,
+        });
+        let button = json!({
+            "name": "Restart mixer",
+            "unique_id": format!("volume_mixer_{}_restart_mixer", self.node_id),
+            "icon": "mdi:restart",
+            "command_topic": self.topic("set"),
+            "payload_press": InstanceCommand::RestartMixer.name(),
+            "availability_topic": self.topic("availability"),
+            "device": device,
+        });
+
+        [
+            ("switch", "mixer", switch),
+            ("button", "restart_mixer", button),
+        ]
+        .into_iter()
+        .map(|(component, object_id, config)| {
+            Message::new(
+                format!(
+                    "{}/{}/{}/{}/config",
+                    prefix, component, self.node_id, object_id
+                ),
+                config.to_string(),
+                true,
+            )
+        })
+        .collect()
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}/{}", self.settings.topic_prefix, self.node_id, name)
+    }
+}
+
+impl Connection {
+    /// Connect and wait for the broker to accept `connect`.
+    fn open(host: &str, port: u16, connect: Connect) -> io::Result<Connection> {
+        let mut last_err = None;
+        for addr in (host, port).to_socket_addrs()? {
+            let stream = match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    last_err = Some(err);
+                    continue;
+                }
+            };
+            stream.set_read_timeout(Some(POLL_INTERVAL))?;
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            stream.set_nodelay(true)?;
+
+            let now = Instant::now();
+            let mut connection = Connection {
+                stream,
+                buf: Vec::new(),
+                last_received: now,
+                last_ping: now,
+            };
+            connection.send(&Packet::Connect(connect))?;
+            return connection.wait_for_connack().map(|_| connection);
+        }
+
+        Err(last_err
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address")))
+    }
+
+    fn wait_for_connack(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        while Instant::now() < deadline {
+            match self.next_packet()? {
+                Some(Packet::ConnAck { code, .. }) if code == CONNECTION_ACCEPTED => return Ok(()),
+                Some(Packet::ConnAck { code, .. }) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionRefused,
+                        format!("broker refused the connection: {}", connack_reason(code)),
+                    ))
+                }
+                Some(packet) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("expected CONNACK, got {:?}", packet),
+                    ))
+                }
+                None => {}
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "broker did not answer CONNECT",
+        ))
+    }
+
+    fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        self.stream.write_all(&packet.encode()?)
+    }
+
+    fn publish(
+        &mut self,
+        topic: String,
+        payload: impl Into<Vec<u8>>,
+        retain: bool,
+    ) -> io::Result<()> {
+        self.send(&Packet::Publish(Message::new(topic, payload, retain)))
+    }
+
+    /// The next packet of the broker, `None` if none arrived for a while.
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            if let Some((packet, used)) = Packet::decode(&self.buf)? {
+                self.buf.drain(..used);
+                return Ok(Some(packet));
+            }
+            if self.buf.len() > MAX_PACKET_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "broker sent a packet that is too large",
+                ));
+            }
+
+            let mut chunk = [0; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "broker closed the connection",
+                    ))
+                }
+                Ok(read) => {
+                    self.buf.extend_from_slice(&chunk[..read]);
+                    self.last_received = Instant::now();
+                }
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Ping the broker so both sides notice a dead connection.
+    fn keep_alive(&mut self) -> io::Result<()> {
+        if self.last_received.elapsed() > KEEP_ALIVE * 3 / 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "broker stopped answering",
+            ));
+        }
+        if self.last_ping.elapsed() >= KEEP_ALIVE / 2 {
+            self.send(&Packet::PingReq)?;
+            self.last_ping = Instant::now();
+        }
+
+        Ok(())
+    }
+}
+
+/// Run a command received on the `set` topic.
+fn run_command(
+    payload: &[u8],
+    dispatch: impl FnOnce(InstanceCommand) -> Result<Option<String>, Error>,
+) {
+    let Some(command) = command_for_payload(payload) else {
+        warn!(
+            "Ignore unknown MQTT command \"{}\"",
+            String::from_utf8_lossy(payload)
+        );
+        return;
+    };
+
+    debug!("Run MQTT command \"{}\"", command.name());
+    if let Err(err) = dispatch(command) {
+        warn!("Could not run MQTT command \"{}\": {}", command.name(), err);
+    }
+}
+
+/// `ON` and `OFF` of a Home Assistant switch, or a command name. `status`
+/// has nothing to answer to.
+fn command_for_payload(payload: &[u8]) -> Option<InstanceCommand> {
+    let payload = std::str::from_utf8(payload).ok()?.trim();
+    if payload.eq_ignore_ascii_case("ON") {
+        Some(InstanceCommand::Show)
+    } else if payload.eq_ignore_ascii_case("OFF") {
+        Some(InstanceCommand::Hide)
+    } else {
+        InstanceCommand::from_name(payload).filter(|command| *command != InstanceCommand::Status)
+    }
+}
+
+fn connack_reason(code: u8) -> String {
+    match code {
+        1 => "unsupported protocol version".to_string(),
+        2 => "client id rejected".to_string(),
+        3 => "server unavailable".to_string(),
+        4 => "bad user name or password".to_string(),
+        5 => "not authorized".to_string(),
+        code => format!("code {}", code),
+    }
+}
+
+/// Topic level safe for Home Assistant discovery, made of the letters,
+/// digits, `_` and `-` of `name`.
+fn sanitize_node_id(name: &str) -> String {
+    let node_id: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if node_id.is_empty() {
+        "pc".to_string()
+    } else {
+        node_id
+    }
+}
+
+/// Sleep for `delay`, `false` if the tray exits meanwhile.
+fn wait_for_retry(subscription: &Subscription, delay: Duration) -> bool {
+    let deadline = Instant::now() + delay;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match subscription.recv_timeout(remaining) {
+            Received::Timeout => return true,
+            Received::Event(MixerEvent::Exiting) | Received::Closed => return false,
+            // The state is published again after reconnecting.
+            Received::Event(_) | Received::Lagged { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread::JoinHandle;
+
+    struct Broker {
+        stream: TcpStream,
+        buf: Vec<u8>,
+    }
+
+    impl Broker {
+        fn accept(listener: &TcpListener) -> Broker {
+            let (stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            Broker {
+                stream,
+                buf: Vec::new(),
+            }
+        }
+
+        fn recv(&mut self) -> Packet {
+            loop {
+                if let Some((packet, used)) = Packet::decode(&self.buf).unwrap() {
+                    self.buf.drain(..used);
+                    return packet;
+                }
+                let mut chunk = [0; 4096];
+                let read = self.stream.read(&mut chunk).unwrap();
+                assert!(read > 0, "bridge closed the connection");
+                self.buf.extend_from_slice(&chunk[..read]);
+            }
+        }
+
+        fn recv_publish(&mut self) -> Message {
+            match self.recv() {
+                Packet::Publish(message) => message,
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            }
+        }
+
+        fn send(&mut self, packet: Packet) {
+            self.stream.write_all(&packet.encode().unwrap()).unwrap();
+        }
+
+        /// Accept the connection and take what the bridge publishes up to
+        /// its state.
+        fn handshake(&mut self, code: u8) -> Vec<Message> {
+            assert!(matches!(self.recv(), Packet::Connect(_)));
+            self.send(Packet::ConnAck {
+                session_present: false,
+                code,
+            });
+            if code != CONNECTION_ACCEPTED {
+                return Vec::new();
+            }
+
+            assert!(matches!(self.recv(), Packet::Subscribe { .. }));
+            self.send(Packet::SubAck {
+                packet_id: 1,
+                codes: vec![0],
+            });
+            let mut published = Vec::new();
+            while published
+                .last()
+                .is_none_or(|message: &Message| !message.topic.ends_with("/attributes"))
+            {
+                published.push(self.recv_publish());
+            }
+            published
+        }
+    }
+
+    fn start(
+        discovery_prefix: &str,
+    ) -> (
+        TcpListener,
+        EventBus,
+        mpsc::Receiver<InstanceCommand>,
+        JoinHandle<()>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut bridge = MqttBridge::new(MqttSettings {
+            enabled: true,
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            node_id: "test-pc".to_string(),
+            discovery_prefix: discovery_prefix.to_string(),
+            ..MqttSettings::default()
+        });
+        bridge.reconnect = RestartPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+            stable_after: Duration::from_secs(60),
+        };
+
+        let events = EventBus::default();
+        let (sender, commands) = mpsc::channel();
+        let thread = std::thread::spawn({
+            let events = events.clone();
+            move || {
+                bridge.run(&events, |command| {
+                    sender.send(command).unwrap();
+                    let status = MixerStatus {
+                        pid: 42,
+                        hwnd: 0x1234,
+                        running: true,
+                        visible: false,
+                        uptime: None,
+                        restart_count: 1,
+                    };
+                    Ok(Some(status.encode()).filter(|_| command == InstanceCommand::Status))
+                })
+            }
+        });
+
+        (listener, events, commands, thread)
+    }
+
+    #[test]
+    fn publishes_state_and_runs_commands() {
+        let (listener, events, commands, thread) = start("homeassistant");
+        let mut broker = Broker::accept(&listener);
+
+        let Packet::Connect(connect) = broker.recv() else {
+            panic!("expected CONNECT");
+        };
+        assert_eq!(connect.client_id, "volume_mixer-test-pc");
+        assert_eq!(connect.username, None);
+        assert_eq!(
+            connect.will,
+            Some(Message::new(
+                "volume_mixer/test-pc/availability",
+                "offline",
+                true
+            ))
+        );
+        broker.send(Packet::ConnAck {
+            session_present: false,
+            code: CONNECTION_ACCEPTED,
+        });
+        assert_eq!(
+            broker.recv(),
+            Packet::Subscribe {
+                packet_id: 1,
+                topics: vec!["volume_mixer/test-pc/set".to_string()],
+            }
+        );
+
+        let switch = broker.recv_publish();
+        assert_eq!(switch.topic, "homeassistant/switch/test-pc/mixer/config");
+        let config: serde_json::Value = serde_json::from_slice(&switch.payload).unwrap();
+        assert_eq!(config["command_topic"], "volume_mixer/test-pc/set");
+        assert_eq!(config["state_topic"], "volume_mixer/test-pc/state");
+        let button = broker.recv_publish();
+        assert_eq!(
+            button.topic,
+            "homeassistant/button/test-pc/restart_mixer/config"
+        );
+
+        assert_eq!(
+            broker.recv_publish(),
+            Message::new("volume_mixer/test-pc/availability", "online", true)
+        );
+        assert_eq!(
+            broker.recv_publish(),
+            Message::new("volume_mixer/test-pc/state", "OFF", true)
+        );
+        let attributes = broker.recv_publish();
+        let attributes: serde_json::Value = serde_json::from_slice(&attributes.payload).unwrap();
+        assert_eq!(attributes["pid"], 42);
+
+        for payload in ["toggle", "OFF", "status", "mute"] {
+            broker.send(Packet::Publish(Message::new(
+                "volume_mixer/test-pc/set",
+                payload,
+                false,
+            )));
+        }
+        events.publish(MixerEvent::MixerShown);
+        assert_eq!(
+            broker.recv_publish(),
+            Message::new(
+                "volume_mixer/test-pc/event",
+                r#"{"event":"mixer-shown"}"#,
+                false
+            )
+        );
+        assert_eq!(
+            broker.recv_publish(),
+            Message::new("volume_mixer/test-pc/state", "ON", true)
+        );
+
+        events.publish(MixerEvent::Exiting);
+        assert_eq!(broker.recv_publish().topic, "volume_mixer/test-pc/event");
+        assert_eq!(
+            broker.recv_publish(),
+            Message::new("volume_mixer/test-pc/availability", "offline", true)
+        );
+        assert_eq!(broker.recv(), Packet::Disconnect);
+        thread.join().unwrap();
+
+        assert_eq!(
+            commands.try_iter().collect::<Vec<_>>(),
+            vec![
+                InstanceCommand::Status,
+                InstanceCommand::Toggle,
+                InstanceCommand::Hide,
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_retained_commands() {
+        let (listener, events, commands, thread) = start("");
+        let mut broker = Broker::accept(&listener);
+        broker.handshake(CONNECTION_ACCEPTED);
+
+        broker.send(Packet::Publish(Message::new(
+            "volume_mixer/test-pc/set",
+            "restart-mixer",
+            true,
+        )));
+        broker.send(Packet::Publish(Message::new(
+            "volume_mixer/test-pc/set",
+            "hide",
+            false,
+        )));
+        // Commands are taken before events, so both were handled by then.
+        events.publish(MixerEvent::Exiting);
+        assert_eq!(broker.recv_publish().topic, "volume_mixer/test-pc/event");
+        thread.join().unwrap();
+
+        assert_eq!(
+            commands.try_iter().collect::<Vec<_>>(),
+            vec![InstanceCommand::Status, InstanceCommand::Hide]
+        );
+    }
+
+    #[test]
+    fn reconnects_after_refusal_and_connection_loss() {
+        let (listener, events, _commands, thread) = start("");
+
+        assert!(Broker::accept(&listener).handshake(5).is_empty());
+        let published = Broker::accept(&listener).handshake(CONNECTION_ACCEPTED);
+        // No discovery configs, just the availability and state.
+        assert_eq!(published.len(), 3);
+
+        let mut broker = Broker::accept(&listener);
+        broker.handshake(CONNECTION_ACCEPTED);
+        events.close();
+        assert_eq!(
+            broker.recv_publish(),
+            Message::new("volume_mixer/test-pc/availability", "offline", true)
+        );
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn maps_payloads_to_commands() {
+        assert_eq!(command_for_payload(b"ON"), Some(InstanceCommand::Show));
+        assert_eq!(command_for_payload(b"off\n"), Some(InstanceCommand::Hide));
+        assert_eq!(
+            command_for_payload(b"restart-mixer"),
+            Some(InstanceCommand::RestartMixer)
+        );
+        assert_eq!(command_for_payload(b"status"), None);
+        assert_eq!(command_for_payload(&[0xFF]), None);
+    }
+
+    #[test]
+    fn node_id_is_safe_topic_level() {
+        assert_eq!(sanitize_node_id("DESKTOP-AB12"), "desktop-ab12");
+        assert_eq!(sanitize_node_id("my.pc local"), "my_pc_local");
+        assert_eq!(sanitize_node_id(""), "pc");
+    }
+}
@@ -0,0 +1,458 @@
+//! Where to show the mixer window, as pure geometry over rectangles.
+
+use crate::config::{Corner, PlacementSettings, PlacementStrategy};
+use crate::dpi;
+use crate::platform::{Monitor, Point, Rect};
+
+/// Side of the screen the taskbar is docked to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskbarEdge {
+    Left,
+    Top,
+    Right,
+    Bottom,
+}
+
+/// Positions the strategies can anchor to, `None` when not available.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Anchors {
+    pub tray_icon: Option<Rect>,
+    pub cursor: Option<Point>,
+    /// Where the mixer was when it was last hidden.
+    pub last_position: Option<Rect>,
+}
+
+/// Compute the new window rectangle on the monitor the strategy refers to:
+/// the one with the tray icon, the cursor or the last position. `None` if
+/// there is no monitor at all.
+///
+/// `window` is in physical pixels of the monitor it is currently on. A DPI
+/// aware window is rescaled by the system when it moves to a monitor with a
+/// different DPI, so the returned rect already has the rescaled size.
+pub fn place(
+    settings: &PlacementSettings,
+    window: Rect,
+    monitors: &[Monitor],
+    anchors: Anchors,
+) -> Option<Rect> {
+    let icon = anchors.tray_icon.map(|icon| icon.center());
+    let last = anchors.last_position.map(|last| last.center());
+    let reference = match settings.strategy {
+        PlacementStrategy::Cursor => anchors.cursor.or(icon),
+        PlacementStrategy::LastPosition => last.or(icon).or(anchors.cursor),
+        _ => icon.or(anchors.cursor),
+    };
+
+    let monitor = reference
+        .and_then(|point| monitor_at(monitors, point))
+        .or_else(|| monitors.iter().find(|monitor| monitor.primary))
+        .or_else(|| monitors.first())?;
+
+    let window_dpi = monitor_at(monitors, window.center())
+        .map(|current| current.dpi)
+        .unwrap_or(monitor.dpi);
+    let window = dpi::rescale_rect(window, window_dpi, monitor.dpi);
+    let margin = monitor.dpi.to_physical(settings.margin as i32);
+    let work_area = Rect::new(
+        monitor.work_area.left + margin,
+        monitor.work_area.top + margin,
+        monitor.work_area.right - margin,
+        monitor.work_area.bottom - margin,
+    );
+
+    Some(place_in(settings, window, work_area, anchors))
+}
+
+/// The monitor containing `point`, or the closest one if the point is
+/// between monitors.
+pub fn monitor_at(monitors: &[Monitor], point: Point) -> Option<&Monitor> {
+    monitors
+        .iter()
+        .find(|monitor| monitor.bounds.contains(point))
+        .or_else(|| {
+            monitors
+                .iter()
+                .min_by_key(|monitor| distance_squared(monitor.bounds, point))
+        })
+}
+
+fn distance_squared(rect: Rect, point: Point) -> i64 {
+    let dx = (rect.left - point.x).max(point.x - (rect.right - 1)).max(0) as i64;
+    let dy = (rect.top - point.y).max(point.y - (rect.bottom - 1)).max(0) as i64;
+
+    dx * dx + dy * dy
+}
+
+/// Compute the new window rectangle within one work area. Strategies whose
+/// anchor is missing fall back to the configured corner.
+pub fn place_in(
+    settings: &PlacementSettings,
+    window: Rect,
+    work_area: Rect,
+    anchors: Anchors,
+) -> Rect {
+    let placed = match settings.strategy {
+        PlacementStrategy::TrayIcon => anchors
+            .tray_icon
+            .map(|icon| anchored_to(icon, window, work_area)),
+        PlacementStrategy::Cursor => anchors.cursor.map(|cursor| {
+            anchored_to(
+                Rect::new(cursor.x, cursor.y, cursor.x, cursor.y),
+                window,
+                work_area,
+            )
+        }),
+        PlacementStrategy::Corner => None,
+        PlacementStrategy::Centered => Some(centered(window, work_area)),
+        PlacementStrategy::LastPosition => anchors
+            .last_position
+            .map(|last| clamped(sized(last.left, last.top, window), work_area)),
+    };
+
+    placed.unwrap_or_else(|| in_corner(settings.corner, window, work_area))
+}
+
+/// The taskbar lies outside the work area, so an anchor inside the taskbar
+/// tells on which edge it is docked. `None` if the anchor is inside the work area.
+pub fn taskbar_edge(anchor: Rect, work_area: Rect) -> Option<TaskbarEdge> {
+    if anchor.top >= work_area.bottom {
+        Some(TaskbarEdge::Bottom)
+    } else if anchor.bottom <= work_area.top && anchor.top < work_area.top {
+        Some(TaskbarEdge::Top)
+    } else if anchor.left >= work_area.right {
+        Some(TaskbarEdge::Right)
+    } else if anchor.right <= work_area.left && anchor.left < work_area.left {
+        Some(TaskbarEdge::Left)
+    } else {
+        None
+    }
+}
+
+/// Put the window next to `anchor`, against the taskbar if the anchor is on it,
+/// otherwise above the anchor (or below it if there is no room above).
+pub fn anchored_to(anchor: Rect, window: Rect, work_area: Rect) -> Rect {
+    let (width, height) = (window.width(), window.height());
+    let center_x = anchor.left + anchor.width() / 2;
+    let center_y = anchor.top + anchor.height() / 2;
+
+    let (left, top) = match taskbar_edge(anchor, work_area) {
+        Some(TaskbarEdge::Bottom) => (center_x - width / 2, work_area.bottom - height),
+        Some(TaskbarEdge::Top) => (center_x - width / 2, work_area.top),
+        Some(TaskbarEdge::Right) => (work_area.right - width, center_y - height / 2),
+        Some(TaskbarEdge::Left) => (work_area.left, center_y - height / 2),
+        None if anchor.top - height >= work_area.top => (center_x - width / 2, anchor.top - height),
+        None => (center_x - width / 2, anchor.bottom),
+    };
+
+    clamped(sized(left, top, window), work_area)
+}
+
+pub fn in_corner(corner: Corner, window: Rect, work_area: Rect) -> Rect {
+    let (width, height) = (window.width(), window.height());
+    let left = match corner {
+        Corner::TopLeft | Corner::BottomLeft => work_area.left,
+        Corner::TopRight | Corner::BottomRight => work_area.right - width,
+    };
+    let top = match corner {
+        Corner::TopLeft | Corner::TopRight => work_area.top,
+        Corner::BottomLeft | Corner::BottomRight => work_area.bottom - height,
+    };
+
+    sized(left, top, window)
+}
+
+pub fn centered(window: Rect, work_area: Rect) -> Rect {
+    sized(
+        work_area.left + (work_area.width() - window.width()) / 2,
+        work_area.top + (work_area.height() - window.height()) / 2,
+        window,
+    )
+}
+
+/// Move `rect` into `work_area`, keeping its size. A rect larger than the
+/// area is aligned to its top-left corner.
+pub fn clamped(rect: Rect, work_area: Rect) -> Rect {
+    let left = rect
+        .left
+        .min(work_area.right - rect.width())
+        .max(work_area.left);
+    let top = rect
+        .top
+        .min(work_area.bottom - rect.height())
+        .max(work_area.top);
+
+    sized(left, top, rect)
+}
+
+/// `window`'s size at the given position.
+fn sized(left: i32, top: i32, window: Rect) -> Rect {
+    Rect::new(left, top, left + window.width(), top + window.height())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dpi::Dpi;
+
+    const WINDOW: Rect = Rect {
+        left: 0,
+        top: 0,
+        right: 300,
+        bottom: 200,
+    };
+
+    fn settings(strategy: PlacementStrategy) -> PlacementSettings {
+        PlacementSettings {
+            strategy,
+            ..PlacementSettings::default()
+        }
+    }
+
+    fn with_icon(icon: Rect) -> Anchors {
+        Anchors {
+            tray_icon: Some(icon),
+            ..Anchors::default()
+        }
+    }
+
+    #[test]
+    fn tray_icon_on_every_taskbar_edge() {
+        let tray_icon = settings(PlacementStrategy::TrayIcon);
+
+        // Bottom taskbar, icon near the right end.
+        let work_area = Rect::new(0, 0, 1920, 1040);
+        let icon = Rect::new(1800, 1050, 1824, 1074);
+        assert_eq!(taskbar_edge(icon, work_area), Some(TaskbarEdge::Bottom));
+        assert_eq!(
+            place_in(&tray_icon, WINDOW, work_area, with_icon(icon)),
+            Rect::new(1620, 840, 1920, 1040)
+        );
+
+        // Top taskbar.
+        let work_area = Rect::new(0, 40, 1920, 1080);
+        let icon = Rect::new(900, 8, 924, 32);
+        assert_eq!(taskbar_edge(icon, work_area), Some(TaskbarEdge::Top));
+        assert_eq!(
+            place_in(&tray_icon, WINDOW, work_area, with_icon(icon)),
+            Rect::new(762, 40, 1062, 240)
+        );
+
+        // Left taskbar, icon near the bottom.
+        let work_area = Rect::new(60, 0, 1920, 1080);
+        let icon = Rect::new(18, 1000, 42, 1024);
+        assert_eq!(taskbar_edge(icon, work_area), Some(TaskbarEdge::Left));
+        assert_eq!(
+            place_in(&tray_icon, WINDOW, work_area, with_icon(icon)),
+            Rect::new(60, 880, 360, 1080)
+        );
+
+        // Right taskbar.
+        let work_area = Rect::new(0, 0, 1860, 1080);
+        let icon = Rect::new(1878, 500, 1902, 524);
+        assert_eq!(taskbar_edge(icon, work_area), Some(TaskbarEdge::Right));
+        assert_eq!(
+            place_in(&tray_icon, WINDOW, work_area, with_icon(icon)),
+            Rect::new(1560, 412, 1860, 612)
+        );
+    }
+
+    #[test]
+    fn cursor_inside_work_area_opens_above_or_below() {
+        let cursor = settings(PlacementStrategy::Cursor);
+        let work_area = Rect::new(0, 0, 1920, 1040);
+        let at = |x, y| Anchors {
+            cursor: Some(Point { x, y }),
+            ..Anchors::default()
+        };
+
+        assert_eq!(
+            place_in(&cursor, WINDOW, work_area, at(500, 500)),
+            Rect::new(350, 300, 650, 500)
+        );
+        assert_eq!(
+            place_in(&cursor, WINDOW, work_area, at(10, 50)),
+            Rect::new(0, 50, 300, 250)
+        );
+    }
+
+    #[test]
+    fn fixed_strategies() {
+        let work_area = Rect::new(0, 40, 1920, 1080);
+
+        let corner = PlacementSettings {
+            strategy: PlacementStrategy::Corner,
+            corner: Corner::TopLeft,
+            ..PlacementSettings::default()
+        };
+        assert_eq!(
+            place_in(
+                &corner,
+                WINDOW,
+                work_area,
+                with_icon(Rect::new(0, 0, 24, 24))
+            ),
+            Rect::new(0, 40, 300, 240)
+        );
+        assert_eq!(
+            place_in(
+                &settings(PlacementStrategy::Centered),
+                WINDOW,
+                work_area,
+                Anchors::default()
+            ),
+            Rect::new(810, 460, 1110, 660)
+        );
+    }
+
+    #[test]
+    fn last_position_is_kept_on_screen() {
+        let last_position = settings(PlacementStrategy::LastPosition);
+        let work_area = Rect::new(0, 0, 1920, 1040);
+        let at = |rect| Anchors {
+            last_position: Some(rect),
+            ..Anchors::default()
+        };
+
+        assert_eq!(
+            place_in(
+                &last_position,
+                WINDOW,
+                work_area,
+                at(Rect::new(100, 100, 400, 300))
+            ),
+            Rect::new(100, 100, 400, 300)
+        );
+        assert_eq!(
+            place_in(
+                &last_position,
+                WINDOW,
+                work_area,
+                at(Rect::new(1800, -50, 2100, 150))
+            ),
+            Rect::new(1620, 0, 1920, 200)
+        );
+    }
+
+    /// Secondary monitor left of the primary one, both with a bottom taskbar.
+    fn dual_monitors() -> Vec<Monitor> {
+        vec![
+            Monitor {
+                bounds: Rect::new(0, 0, 1920, 1080),
+                work_area: Rect::new(0, 0, 1920, 1040),
+                primary: true,
+                dpi: Dpi::DEFAULT,
+            },
+            Monitor {
+                bounds: Rect::new(-1280, -200, 0, 824),
+                work_area: Rect::new(-1280, -200, 0, 784),
+                primary: false,
+                dpi: Dpi::DEFAULT,
+            },
+        ]
+    }
+
+    #[test]
+    fn picks_monitor_of_tray_icon_or_cursor() {
+        let monitors = dual_monitors();
+        let tray_icon = settings(PlacementStrategy::TrayIcon);
+
+        let icon = Rect::new(-100, 794, -76, 818);
+        assert_eq!(
+            place(&tray_icon, WINDOW, &monitors, with_icon(icon)),
+            Some(Rect::new(-300, 584, 0, 784))
+        );
+
+        let corner = settings(PlacementStrategy::Corner);
+        let cursor = Anchors {
+            cursor: Some(Point { x: -640, y: 0 }),
+            ..Anchors::default()
+        };
+        assert_eq!(
+            place(&corner, WINDOW, &monitors, cursor),
+            Some(Rect::new(-300, 584, 0, 784))
+        );
+        assert_eq!(
+            place(&corner, WINDOW, &monitors, Anchors::default()),
+            Some(Rect::new(1620, 840, 1920, 1040))
+        );
+        assert_eq!(place(&corner, WINDOW, &[], Anchors::default()), None);
+    }
+
+    #[test]
+    fn point_between_monitors_uses_closest_one() {
+        let monitors = dual_monitors();
+
+        let below_secondary = Point { x: -500, y: 1000 };
+        assert_eq!(
+            monitor_at(&monitors, below_secondary).map(|monitor| monitor.primary),
+            Some(false)
+        );
+        let right_of_primary = Point { x: 2500, y: 500 };
+        assert_eq!(
+            monitor_at(&monitors, right_of_primary).map(|monitor| monitor.primary),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn last_position_is_clamped_to_its_monitor() {
+        let monitors = dual_monitors();
+        let last_position = Anchors {
+            last_position: Some(Rect::new(-200, -300, 100, -100)),
+            ..Anchors::default()
+        };
+
+        assert_eq!(
+            place(
+                &settings(PlacementStrategy::LastPosition),
+                WINDOW,
+                &monitors,
+                last_position
+            ),
+            Some(Rect::new(-300, -200, 0, 0))
+        );
+    }
+
+    #[test]
+    fn rescales_window_and_margin_for_target_monitor_dpi() {
+        let mut monitors = dual_monitors();
+        monitors[1].dpi = Dpi(144);
+        let corner = PlacementSettings {
+            strategy: PlacementStrategy::Corner,
+            margin: 10,
+            ..PlacementSettings::default()
+        };
+        let cursor_on = |x, y| Anchors {
+            cursor: Some(Point { x, y }),
+            ..Anchors::default()
+        };
+
+        // 300x200 on the 96 DPI primary becomes 450x300 on the 144 DPI secondary.
+        assert_eq!(
+            place(&corner, WINDOW, &monitors, cursor_on(-640, 0)),
+            Some(Rect::new(-465, 469, -15, 769))
+        );
+        // And back again.
+        let on_secondary = Rect::new(-1000, 0, -550, 300);
+        assert_eq!(
+            place(&corner, on_secondary, &monitors, cursor_on(500, 500)),
+            Some(Rect::new(1610, 830, 1910, 1030))
+        );
+    }
+
+    #[test]
+    fn missing_anchor_falls_back_to_corner() {
+        let work_area = Rect::new(0, 0, 1920, 1040);
+
+        for strategy in [
+            PlacementStrategy::TrayIcon,
+            PlacementStrategy::Cursor,
+            PlacementStrategy::LastPosition,
+        ] {
+            assert_eq!(
+                place_in(&settings(strategy), WINDOW, work_area, Anchors::default()),
+                Rect::new(1620, 840, 1920, 1040)
+            );
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::dpi::Dpi;
+use crate::error::Error;
+use crate::hotkey::{Hotkey, HotkeyAction};
+use crate::instance::InstanceCommand;
+use crate::menu::{Menu, MenuCommand};
+use crate::process_query::{ProcessInfo, ProcessQuery};
+use crate::window_match::{WindowInfo, WindowMatcher};
+use crate::window_wait::WindowWatch;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Opaque, platform-neutral window handle (`HWND` on Windows).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct WindowHandle(pub isize);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub fn new(left: i32, top: i32, right: i32, bottom: i32) -> Rect {
+        Rect {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> i32 {
+        self.bottom - self.top
+    }
+
+    pub fn center(&self) -> Point {
+        Point {
+            x: self.left + self.width() / 2,
+            y: self.top + self.height() / 2,
+        }
+    }
+
+    /// Whether `point` is inside, the right and bottom edges being exclusive.
+    pub fn contains(&self, point: Point) -> bool {
+        (self.left..self.right).contains(&point.x) && (self.top..self.bottom).contains(&point.y)
+    }
+}
+
+/// A display in virtual screen coordinates, which are negative for monitors
+/// left of or above the primary one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Monitor {
+    pub bounds: Rect,
+    /// Part of `bounds` not covered by the taskbar or docked toolbars.
+    pub work_area: Rect,
+    pub primary: bool,
+    pub dpi: Dpi,
+}
+
+pub trait ChildProcess {
+    fn pid(&self) -> u32;
+
+    fn terminate(&mut self) -> Result<(), Error>;
+
+    /// Exit code of the process, `None` while it is still running.
+    fn try_wait(&mut self) -> Result<Option<u32>, Error>;
+
+    /// Like `try_wait`, but give the process up to `timeout` to exit.
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<u32>, Error>;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpawnOptions {
+    /// Command line arguments after the executable.
+    pub args: Vec<String>,
+    /// Directory the process starts in, the one of the tray if `None`.
+    pub working_dir: Option<PathBuf>,
+    /// Variables set for the process on top of the environment of the tray.
+    pub env: Vec<(String, String)>,
+    /// Start without showing the main window.
+    pub hidden: bool,
+    /// End the process together with the tray, even if the tray crashes or is killed.
+    pub kill_on_tray_exit: bool,
+}
+
+pub trait ProcessSpawner {
+    type Process: ChildProcess;
+
+    /// Running processes `query` matches, in no particular order.
+    fn find_processes(&self, query: &ProcessQuery) -> Result<Vec<ProcessInfo>, Error>;
+
+    /// Session the tray runs in, to tell processes of other users apart.
+    fn current_session_id(&self) -> Result<u32, Error>;
+
+    fn is_process_running(&self, pid: u32) -> Result<bool, Error>;
+
+    /// Open a handle to a process started by someone else.
+    fn open_process(&self, pid: u32) -> Result<Self::Process, Error>;
+
+    fn system_exec_path(&self, exec_name: &str) -> Result<PathBuf, Error>;
+
+    /// Start the executable, without showing its main window if `options.hidden`.
+    fn spawn(&self, exec_path: &Path, options: &SpawnOptions) -> Result<Self::Process, Error>;
+
+    /// Open a file or URI with its default handler.
+    fn shell_open(&self, target: &str) -> Result<(), Error>;
+}
+
+pub trait WindowFinder {
+    /// The first top-level window `matcher` matches.
+    fn find_window(&self, matcher: &WindowMatcher) -> Result<WindowInfo, Error>;
+
+    /// Whether the handle still refers to an existing window.
+    fn is_window(&self, hwnd: WindowHandle) -> bool;
+
+    /// Watch windows appear, only those of process `pid` if given. The watch
+    /// is cancelled once that process exits.
+    fn watch_windows(&self, pid: Option<u32>) -> Result<WindowWatch, Error>;
+}
+
+pub trait WindowManager {
+    fn is_visible(&self, hwnd: WindowHandle) -> bool;
+
+    fn show(&self, hwnd: WindowHandle);
+
+    fn hide(&self, hwnd: WindowHandle);
+
+    /// Ask the window to close, as if the user clicked its close button.
+    fn close(&self, hwnd: WindowHandle) -> Result<(), Error>;
+
+    fn set_foreground(&self, hwnd: WindowHandle);
+
+    /// Top-level owner of the window that has the keyboard focus.
+    fn foreground_window(&self) -> Option<WindowHandle>;
+
+    fn window_rect(&self, hwnd: WindowHandle) -> Result<Rect, Error>;
+
+    fn monitors(&self) -> Result<Vec<Monitor>, Error>;
+
+    fn move_window(&self, hwnd: WindowHandle, rect: Rect) -> Result<(), Error>;
+
+    fn cursor_pos(&self) -> Result<Point, Error>;
+}
+
+/// Everything the application needs from the OS apart from the tray icon.
+pub trait Platform: ProcessSpawner + WindowFinder + WindowManager + Clone {}
+
+impl<T: ProcessSpawner + WindowFinder + WindowManager + Clone> Platform for T {}
+
+pub trait TrayShell {
+    fn add_icon(&mut self, tooltip: &str) -> Result<(), Error>;
+
+    fn remove_icon(&mut self) -> Result<(), Error>;
+
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error>;
+
+    /// Screen rectangle of the icon, which may be in the overflow area.
+    fn icon_rect(&self) -> Result<Rect, Error>;
+
+    /// Show the menu at the cursor. The chosen command is delivered later
+    /// through `EventHandler::handle_menu_command`.
+    fn show_context_menu(&self, menu: &Menu) -> Result<(), Error>;
+
+    fn show_message(&self, title: &str, text: &str);
+
+    /// Register a system-wide hotkey, delivered through `EventHandler::handle_hotkey`.
+    fn register_hotkey(&mut self, action: HotkeyAction, hotkey: Hotkey) -> Result<(), Error>;
+
+    fn unregister_hotkey(&mut self, action: HotkeyAction);
+
+    /// Deliver every change of the foreground window through
+    /// `EventHandler::handle_foreground_changed` until `unwatch_foreground`.
+    fn watch_foreground(&mut self) -> Result<(), Error>;
+
+    fn unwatch_foreground(&mut self);
+
+    /// Ask the message loop to finish.
+    fn post_quit(&self);
+}
+
+/// Named after the `WM_*BUTTONDOWN` notifications they come from.
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrayEvent {
+    LeftButtonDown,
+    MiddleButtonDown,
+    RightButtonDown,
+}
+
+/// Periodic timers driven by the message loop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timer {
+    ConfigReload,
+    /// Checks that the mixer is still running.
+    Supervise,
+}
+
+/// Receiver of everything the message loop dispatches to the application.
+pub trait EventHandler {
+    fn handle_tray_event(&mut self, event: TrayEvent);
+
+    fn handle_timer(&mut self, timer: Timer);
+
+    /// Another window became the foreground window while it is watched.
+    fn handle_foreground_changed(&mut self);
+
+    fn handle_menu_command(&mut self, command: MenuCommand);
+
+    fn handle_hotkey(&mut self, action: HotkeyAction);
+
+    /// A second launch forwarded its command. The reply, if any, is sent back to it.
+    fn handle_instance_command(&mut self, command: InstanceCommand) -> Option<String>;
+}
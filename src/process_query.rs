@@ -0,0 +1,180 @@
+//! Descriptors of running processes and the criteria to pick processes among
+//! them, independent of how the processes are enumerated.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// What is known about a running process.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub parent_pid: u32,
+    /// File name of the executable.
+    pub exe_name: String,
+    /// `None` if the process could not be opened, e.g. because it belongs to
+    /// another user.
+    pub exe_path: Option<PathBuf>,
+    pub thread_count: u32,
+    /// Logon session the process runs in, `None` if unknown.
+    pub session_id: Option<u32>,
+}
+
+/// Criteria a process must all meet, unset ones matching any process.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessQuery {
+    /// File name of the executable, ignoring case like Windows does.
+    pub exe_name: Option<String>,
+    /// Full path of the executable, ignoring case.
+    pub exe_path: Option<PathBuf>,
+    pub session_id: Option<u32>,
+}
+
+impl ProcessQuery {
+    pub fn exe_name(exe_name: &str) -> ProcessQuery {
+        ProcessQuery {
+            exe_name: Some(exe_name.to_string()),
+            ..ProcessQuery::default()
+        }
+    }
+
+    pub fn matches(&self, process: &ProcessInfo) -> bool {
+        let exe_name_matches = self
+            .exe_name
+            .as_ref()
+            .is_none_or(|exe_name| process.exe_name.eq_ignore_ascii_case(exe_name));
+        let exe_path_matches = self.exe_path.as_ref().is_none_or(|exe_path| {
+            process
+                .exe_path
+                .as_ref()
+                .is_some_and(|process_path| same_path(process_path, exe_path))
+        });
+        let session_matches = self
+            .session_id
+            .is_none_or(|session_id| process.session_id == Some(session_id));
+
+        exe_name_matches && exe_path_matches && session_matches
+    }
+
+    /// All of `processes` that match, in their order.
+    pub fn filter(&self, processes: impl IntoIterator<Item = ProcessInfo>) -> Vec<ProcessInfo> {
+        processes
+            .into_iter()
+            .filter(|process| self.matches(process))
+            .collect()
+    }
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+/// Describes the query in log messages, e.g. `"SndVol.exe" in session 1`.
+impl fmt::Display for ProcessQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut criteria = Vec::new();
+        if let Some(exe_name) = &self.exe_name {
+            criteria.push(format!("\"{}\"", exe_name));
+        }
+        if let Some(exe_path) = &self.exe_path {
+            criteria.push(format!("at \"{}\"", exe_path.display()));
+        }
+        if let Some(session_id) = self.session_id {
+            criteria.push(format!("in session {}", session_id));
+        }
+
+        if criteria.is_empty() {
+            write!(f, "any process")
+        } else {
+            write!(f, "{}", criteria.join(" "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, exe_path: &str, session_id: u32) -> ProcessInfo {
+        let exe_path = PathBuf::from(exe_path);
+        ProcessInfo {
+            pid,
+            exe_name: exe_path
+                .to_string_lossy()
+                .rsplit('\\')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            exe_path: Some(exe_path),
+            session_id: Some(session_id),
+            ..ProcessInfo::default()
+        }
+    }
+
+    #[test]
+    fn returns_all_matching_processes() {
+        let processes = vec![
+            process(4, r"C:\Windows\System32\SndVol.exe", 0),
+            process(8, r"C:\Windows\explorer.exe", 1),
+            process(15, r"C:\WINDOWS\system32\sndvol.exe", 1),
+            process(16, r"D:\Tools\SndVol.exe", 1),
+            process(23, r"C:\Windows\System32\SndVol.exe", 2),
+        ];
+        let pids = |query: ProcessQuery| -> Vec<u32> {
+            query
+                .filter(processes.clone())
+                .iter()
+                .map(|process| process.pid)
+                .collect()
+        };
+
+        assert_eq!(
+            pids(ProcessQuery::exe_name("SNDVOL.EXE")),
+            vec![4, 15, 16, 23]
+        );
+        assert_eq!(
+            pids(ProcessQuery {
+                exe_path: Some(PathBuf::from(r"c:\windows\System32\SndVol.exe")),
+                ..ProcessQuery::exe_name("SndVol.exe")
+            }),
+            vec![4, 15, 23]
+        );
+        assert_eq!(
+            pids(ProcessQuery {
+                session_id: Some(1),
+                ..ProcessQuery::exe_name("SndVol.exe")
+            }),
+            vec![15, 16]
+        );
+        assert_eq!(pids(ProcessQuery::default()).len(), processes.len());
+    }
+
+    #[test]
+    fn unknown_path_or_session_does_not_match() {
+        let process = ProcessInfo {
+            exe_name: "SndVol.exe".to_string(),
+            ..ProcessInfo::default()
+        };
+
+        assert!(ProcessQuery::exe_name("SndVol.exe").matches(&process));
+        assert!(!ProcessQuery {
+            exe_path: Some(PathBuf::from(r"C:\Windows\System32\SndVol.exe")),
+            ..ProcessQuery::default()
+        }
+        .matches(&process));
+        assert!(!ProcessQuery {
+            session_id: Some(0),
+            ..ProcessQuery::default()
+        }
+        .matches(&process));
+    }
+
+    #[test]
+    fn describes_query() {
+        let query = ProcessQuery {
+            session_id: Some(1),
+            ..ProcessQuery::exe_name("SndVol.exe")
+        };
+        assert_eq!(query.to_string(), "\"SndVol.exe\" in session 1");
+        assert_eq!(ProcessQuery::default().to_string(), "any process");
+    }
+}
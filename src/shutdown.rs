@@ -0,0 +1,145 @@
+//! Staged shutdown of a process: ask its window to close, wait for it, and
+//! only terminate it if it does not exit in time.
+//!
+//! Kept out of `windows_utils`, which is only compiled on Windows, so that
+//! the sequence runs against the platform traits and is tested on Linux.
+
+use crate::error::Error;
+use crate::platform::ChildProcess;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use std::fmt;
+use std::time::Duration;
+
+/// The stage that ended the process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownStage {
+    /// The process was gone before the shutdown started.
+    AlreadyExited,
+    /// The process exited by itself after its window was asked to close.
+    Closed,
+    Terminated,
+}
+
+impl fmt::Display for ShutdownStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownStage::AlreadyExited => write!(f, "had already exited"),
+            ShutdownStage::Closed => write!(f, "closed gracefully"),
+            ShutdownStage::Terminated => write!(f, "was terminated"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShutdownSequence {
+    /// How long the process gets to exit after the close request. Zero skips
+    /// the close request and terminates right away.
+    pub close_timeout: Duration,
+}
+
+impl ShutdownSequence {
+    pub fn new(close_timeout: Duration) -> ShutdownSequence {
+        ShutdownSequence { close_timeout }
+    }
+
+    /// Shut down `process`, calling `close` to ask it to exit by itself.
+    pub fn run<C: ChildProcess>(
+        &self,
+        process: &mut C,
+        close: impl FnOnce() -> Result<(), Error>,
+    ) -> Result<ShutdownStage, Error> {
+        if process.try_wait()?.is_some() {
+            return Ok(ShutdownStage::AlreadyExited);
+        }
+
+        if !self.close_timeout.is_zero() {
+            match close() {
+                Ok(()) => {
+                    if process.wait_timeout(self.close_timeout)?.is_some() {
+                        return Ok(ShutdownStage::Closed);
+                    }
+                    debug!(
+                        "Process {} did not exit within {:?} after close request",
+                        process.pid(),
+                        self.close_timeout
+                    );
+                }
+                Err(err) => warn!("Could not ask process {} to close: {}", process.pid(), err),
+            }
+        }
+
+        process.terminate()?;
+        Ok(ShutdownStage::Terminated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_platform::FakePlatform;
+    use crate::platform::{ProcessSpawner, SpawnOptions, WindowManager};
+    use std::path::Path;
+
+    fn spawn(platform: &FakePlatform) -> (<FakePlatform as ProcessSpawner>::Process, u32) {
+        let process = platform
+            .spawn(Path::new("SndVol.exe"), &SpawnOptions::default())
+            .unwrap();
+        let pid = process.pid();
+        (process, pid)
+    }
+
+    #[test]
+    fn obeying_process_is_closed() {
+        let platform = FakePlatform::default();
+        let (mut process, pid) = spawn(&platform);
+        let hwnd = platform.state().windows[0].hwnd;
+        let sequence = ShutdownSequence::new(Duration::from_secs(2));
+
+        let stage = sequence.run(&mut process, || platform.close(hwnd));
+
+        assert_eq!(stage.unwrap(), ShutdownStage::Closed);
+        assert_eq!(platform.state().close_requests, vec![hwnd]);
+        assert_eq!(platform.state().exit_codes.get(&pid), Some(&0));
+    }
+
+    #[test]
+    fn ignoring_process_is_terminated() {
+        let platform = FakePlatform::default();
+        platform.state_mut().ignore_close = true;
+        let (mut process, pid) = spawn(&platform);
+        let hwnd = platform.state().windows[0].hwnd;
+        let sequence = ShutdownSequence::new(Duration::from_secs(2));
+
+        let stage = sequence.run(&mut process, || platform.close(hwnd));
+
+        assert_eq!(stage.unwrap(), ShutdownStage::Terminated);
+        assert_eq!(platform.state().close_requests, vec![hwnd]);
+        assert!(!platform.state().is_process_alive(pid));
+    }
+
+    #[test]
+    fn zero_timeout_skips_close_request() {
+        let platform = FakePlatform::default();
+        let (mut process, _) = spawn(&platform);
+        let hwnd = platform.state().windows[0].hwnd;
+
+        let stage =
+            ShutdownSequence::new(Duration::ZERO).run(&mut process, || platform.close(hwnd));
+
+        assert_eq!(stage.unwrap(), ShutdownStage::Terminated);
+        assert!(platform.state().close_requests.is_empty());
+    }
+
+    #[test]
+    fn exited_process_is_left_alone() {
+        let platform = FakePlatform::default();
+        let (mut process, pid) = spawn(&platform);
+        platform.state_mut().exit_process(pid, 0);
+
+        let stage = ShutdownSequence::new(Duration::from_secs(2))
+            .run(&mut process, || unreachable!("no window to close"));
+
+        assert_eq!(stage.unwrap(), ShutdownStage::AlreadyExited);
+    }
+}
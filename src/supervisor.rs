@@ -0,0 +1,234 @@
+//! Restart policy for the mixer process, independent of how the process is
+//! watched or started.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Why the supervised mixer stopped running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The process ended by itself, e.g. the user closed the mixer window.
+    Exited { code: u32 },
+    /// The process ended with an NTSTATUS error code such as an access violation.
+    Crashed { code: u32 },
+    /// An adopted process is gone, its exit code is unknown.
+    Vanished,
+}
+
+impl ExitReason {
+    pub fn from_exit_code(code: u32) -> ExitReason {
+        // NTSTATUS values with the error severity bits set.
+        if code >= 0xC000_0000 {
+            ExitReason::Crashed { code }
+        } else {
+            ExitReason::Exited { code }
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitReason::Exited { code } => write!(f, "exited with code {}", code),
+            ExitReason::Crashed { code } => write!(f, "crashed with code {:#010X}", code),
+            ExitReason::Vanished => write!(f, "disappeared"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Delay before the first restart attempt.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// A mixer that ran at least this long is considered healthy, so its exit
+    /// starts again with `initial_delay`.
+    pub stable_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            stable_after: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Exponential backoff after `failures` quick exits or failed starts in a row.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures);
+        self.initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Running { since: Instant },
+    Waiting { until: Instant },
+}
+
+/// Diagnostics of the supervised mixer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SupervisorStatus {
+    pub restart_count: u32,
+    pub last_exit: Option<ExitReason>,
+}
+
+pub struct Supervisor {
+    policy: RestartPolicy,
+    state: State,
+    failures: u32,
+    restart_count: u32,
+    last_exit: Option<ExitReason>,
+}
+
+impl Supervisor {
+    /// Supervise a mixer that was started at `now`.
+    pub fn new(policy: RestartPolicy, now: Instant) -> Supervisor {
+        Supervisor {
+            policy,
+            state: State::Running { since: now },
+            failures: 0,
+            restart_count: 0,
+            last_exit: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, State::Running { .. })
+    }
+
+    /// How long the mixer has been running, `None` while it is down.
+    pub fn uptime(&self, now: Instant) -> Option<Duration> {
+        match self.state {
+            State::Running { since } => Some(now.duration_since(since)),
+            State::Waiting { .. } => None,
+        }
+    }
+
+    pub fn status(&self) -> SupervisorStatus {
+        SupervisorStatus {
+            restart_count: self.restart_count,
+            last_exit: self.last_exit,
+        }
+    }
+
+    pub fn on_exit(&mut self, reason: ExitReason, now: Instant) {
+        if let State::Running { since } = self.state {
+            if now.duration_since(since) >= self.policy.stable_after {
+                self.failures = 0;
+            }
+        }
+
+        self.last_exit = Some(reason);
+        self.wait(now);
+    }
+
+    /// The mixer is running again, either restarted by the supervisor or by the user.
+    pub fn on_started(&mut self, now: Instant) {
+        if !self.is_running() {
+            self.restart_count += 1;
+        }
+        self.state = State::Running { since: now };
+    }
+
+    pub fn on_start_failed(&mut self, now: Instant) {
+        self.wait(now);
+    }
+
+    /// Whether the mixer is down and the backoff delay has passed.
+    pub fn should_restart(&self, now: Instant) -> bool {
+        match self.state {
+            State::Running { .. } => false,
+            State::Waiting { until } => now >= until,
+        }
+    }
+
+    fn wait(&mut self, now: Instant) {
+        self.state = State::Waiting {
+            until: now + self.policy.delay(self.failures),
+        };
+        self.failures = self.failures.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn delay_doubles_up_to_maximum() {
+        let policy = RestartPolicy::default();
+
+        assert_eq!(policy.delay(0), ms(500));
+        assert_eq!(policy.delay(1), ms(1000));
+        assert_eq!(policy.delay(3), ms(4000));
+        assert_eq!(policy.delay(7), Duration::from_secs(60));
+        assert_eq!(policy.delay(40), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn quick_exits_back_off() {
+        let start = Instant::now();
+        let mut supervisor = Supervisor::new(RestartPolicy::default(), start);
+
+        supervisor.on_exit(ExitReason::from_exit_code(0xC000_0005), start + ms(100));
+        assert!(!supervisor.should_restart(start + ms(599)));
+        assert!(supervisor.should_restart(start + ms(600)));
+
+        supervisor.on_started(start + ms(600));
+        supervisor.on_exit(ExitReason::Vanished, start + ms(700));
+        assert!(!supervisor.should_restart(start + ms(1699)));
+        assert!(supervisor.should_restart(start + ms(1700)));
+
+        supervisor.on_start_failed(start + ms(1700));
+        assert!(!supervisor.should_restart(start + ms(3699)));
+        assert!(supervisor.should_restart(start + ms(3700)));
+
+        assert_eq!(
+            supervisor.status(),
+            SupervisorStatus {
+                restart_count: 1,
+                last_exit: Some(ExitReason::Vanished),
+            }
+        );
+    }
+
+    #[test]
+    fn stable_run_resets_backoff() {
+        let start = Instant::now();
+        let mut supervisor = Supervisor::new(RestartPolicy::default(), start);
+        supervisor.on_exit(ExitReason::Exited { code: 0 }, start + ms(100));
+        supervisor.on_start_failed(start + ms(600));
+        supervisor.on_started(start + ms(2000));
+        assert!(supervisor.is_running());
+
+        let exit = start + Duration::from_secs(60);
+        supervisor.on_exit(ExitReason::Exited { code: 0 }, exit);
+
+        assert!(supervisor.should_restart(exit + ms(500)));
+        assert_eq!(supervisor.status().restart_count, 1);
+    }
+
+    #[test]
+    fn classifies_exit_codes() {
+        assert_eq!(
+            ExitReason::from_exit_code(0),
+            ExitReason::Exited { code: 0 }
+        );
+        assert_eq!(
+            ExitReason::from_exit_code(0xC000_0005).to_string(),
+            "crashed with code 0xC0000005"
+        );
+    }
+}
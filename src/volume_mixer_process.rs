@@ -1,117 +1,606 @@
-use crate::windows_utils::{
-    find_window_by_pattern_in_title, get_pid_by_name, run_exec, WindowsHandle,
+use crate::config::{AdoptMode, MixerMode, MixerSettings, QuitAction};
+use crate::error::Error;
+use crate::platform::{
+    ChildProcess, Point, ProcessSpawner, SpawnOptions, WindowFinder, WindowHandle, WindowManager,
 };
-use core::time;
-use log::{info, warn};
-use std::env;
-use std::path::PathBuf;
-use windows::Win32::Foundation::HWND;
-use windows::Win32::System::Threading::STARTUPINFOW;
-use windows::Win32::System::Threading::{
-    TerminateProcess, STARTF_PREVENTPINNING, STARTF_USESHOWWINDOW,
-};
-use windows::Win32::UI::WindowsAndMessaging::SW_HIDE;
+use crate::process_query::{ProcessInfo, ProcessQuery};
+use crate::shutdown::ShutdownSequence;
+use crate::supervisor::ExitReason;
+use crate::window_match::{WindowInfo, WindowMatcher};
+use log::{debug, info, warn};
+use std::path::Path;
 
-#[derive(Default)]
-pub struct VolumeMixerProcess {
-    pub pid: u32,
-    pub hwnd: HWND,
-    hprocess: WindowsHandle,
+/// Result of checking on a running mixer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Health {
+    Running,
+    /// The process is alive but its window was recreated.
+    WindowChanged(WindowHandle),
+    Exited(ExitReason),
 }
 
-impl VolumeMixerProcess {
-    const VOLUME_MIXER_EXEC_NAME: &'static str = "SndVol.exe";
+/// How the tray holds on to the mixer process.
+enum Ownership<C> {
+    /// Started by the tray.
+    Spawned(C),
+    /// Started by the user and only known by pid.
+    Borrowed,
+    /// Started by the user, the tray opened a handle to it.
+    TakenOver(C),
+    /// The process has exited or was shut down.
+    Released,
+}
 
-    pub fn new() -> Result<VolumeMixerProcess, String> {
-        if Self::is_volume_mixer_running() {
-            Self::from_running_process()
-        } else {
-            Self::from_new_process()
+pub struct VolumeMixerProcess<P: ProcessSpawner + WindowFinder> {
+    pub pid: u32,
+    pub hwnd: WindowHandle,
+    ownership: Ownership<P::Process>,
+    /// Whether the process ends with the tray even if the tray crashes.
+    kill_on_tray_exit: bool,
+}
+
+impl<P: ProcessSpawner + WindowFinder> VolumeMixerProcess<P> {
+    /// Adopt a running mixer, other than those with a pid in `excluded`, or
+    /// start one.
+    pub fn new(
+        platform: &P,
+        settings: &MixerSettings,
+        excluded: &[u32],
+    ) -> Result<VolumeMixerProcess<P>, Error> {
+        match Self::from_running_process(platform, settings, excluded) {
+            Err(Error::ProcessNotFound { .. }) => Self::from_new_process(platform, settings),
+            result => result,
         }
     }
 
-    fn is_volume_mixer_running() -> bool {
-        if let Ok(pid_op) = get_pid_by_name(Self::VOLUME_MIXER_EXEC_NAME) {
-            pid_op.is_some()
-        } else {
-            false
+    /// Find out whether the mixer is still running and still has the same window.
+    pub fn check(&mut self, platform: &P, settings: &MixerSettings) -> Health {
+        let exit = match &mut self.ownership {
+            Ownership::Spawned(process) | Ownership::TakenOver(process) => process
+                .try_wait()
+                .map(|exit_code| exit_code.map(ExitReason::from_exit_code)),
+            Ownership::Borrowed => platform
+                .is_process_running(self.pid)
+                .map(|running| (!running).then_some(ExitReason::Vanished)),
+            Ownership::Released => Ok(Some(ExitReason::Vanished)),
+        };
+
+        match exit {
+            Ok(Some(reason)) => {
+                // Nothing left to shut down.
+                self.ownership = Ownership::Released;
+                return Health::Exited(reason);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warn!("Could not check volume mixer process: {}", err);
+                return Health::Running;
+            }
         }
+
+        if platform.is_window(self.hwnd) {
+            return Health::Running;
+        }
+        match platform.find_window(&settings.window_matcher(WindowMatcher::OwnerPid(self.pid))) {
+            Ok(window) => {
+                self.hwnd = window.hwnd;
+                Health::WindowChanged(window.hwnd)
+            }
+            Err(err) => {
+                warn!("Volume mixer window is gone: {}", err);
+                Health::Running
+            }
+        }
+    }
+
+    /// Whether the mixer ends with the tray even if the tray crashes.
+    pub fn is_killed_on_tray_exit(&self) -> bool {
+        self.kill_on_tray_exit
+    }
+
+    /// Whether the mixer was already running when it was found.
+    pub fn is_adopted(&self) -> bool {
+        matches!(
+            self.ownership,
+            Ownership::Borrowed | Ownership::TakenOver(_)
+        )
     }
 
-    fn from_running_process() -> Result<VolumeMixerProcess, String> {
-        if let Some(pid) = get_pid_by_name(Self::VOLUME_MIXER_EXEC_NAME).unwrap() {
-            let hwnd = Self::try_find_volume_mixer_window(pid)?;
+    /// Whether the mixer was started by the user and the tray holds no handle to it.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.ownership, Ownership::Borrowed)
+    }
 
-            Ok(VolumeMixerProcess {
-                pid,
-                hwnd,
-                hprocess: WindowsHandle::default(),
-            })
+    /// Mixers the tray may adopt: started from the executable the tray would
+    /// start, and in the session of the tray unless configured otherwise.
+    fn find_running_volume_mixers(
+        platform: &P,
+        settings: &MixerSettings,
+    ) -> Result<Vec<ProcessInfo>, Error> {
+        let session_id = if settings.adopt_current_session_only {
+            Some(platform.current_session_id()?)
         } else {
-            Err(format!(
-                "Could not find pid related to exec {}",
-                Self::VOLUME_MIXER_EXEC_NAME
-            ))
-        }
+            None
+        };
+        let query = ProcessQuery {
+            exe_path: Some(platform.system_exec_path(&settings.exec_name)?),
+            session_id,
+            ..ProcessQuery::exe_name(&settings.exec_name)
+        };
+
+        let mixers = platform.find_processes(&query)?;
+        debug!("Found {} processes {}", mixers.len(), query);
+        Ok(mixers)
     }
 
-    fn from_new_process() -> Result<VolumeMixerProcess, String> {
-        let startup_info = STARTUPINFOW {
-            dwFlags: STARTF_PREVENTPINNING | STARTF_USESHOWWINDOW,
-            wShowWindow: SW_HIDE.0 as u16,
-            ..Default::default()
+    fn from_running_process(
+        platform: &P,
+        settings: &MixerSettings,
+        excluded: &[u32],
+    ) -> Result<VolumeMixerProcess<P>, Error> {
+        let mut mixers =
+            Self::find_running_volume_mixers(platform, settings).unwrap_or_else(|err| {
+                warn!("Could not look for a running volume mixer: {}", err);
+                Vec::new()
+            });
+        mixers.retain(|mixer| !excluded.contains(&mixer.pid));
+        if mixers.is_empty() {
+            return Err(Error::ProcessNotFound {
+                exec_name: settings.exec_name.clone(),
+            });
+        }
+
+        // Of several running mixers, adopt the one that has a mixer window.
+        // The exe name guards against pids reused since the query.
+        let owner = WindowMatcher::all([
+            WindowMatcher::ExeName(settings.exec_name.clone()),
+            WindowMatcher::any(
+                mixers
+                    .iter()
+                    .map(|mixer| WindowMatcher::OwnerPid(mixer.pid)),
+            ),
+        ]);
+        let WindowInfo { pid, hwnd, .. } =
+            Self::wait_for_volume_mixer_window(platform, settings, owner, None)?;
+
+        let ownership = match settings.adopt {
+            AdoptMode::Borrow => Ownership::Borrowed,
+            AdoptMode::TakeOver => match platform.open_process(pid) {
+                Ok(process) => Ownership::TakenOver(process),
+                Err(err) => {
+                    warn!("Could not take over volume mixer, borrow it: {}", err);
+                    Ownership::Borrowed
+                }
+            },
         };
 
-        let exec_path = Self::construct_volume_mixer_exec_path();
+        Ok(VolumeMixerProcess {
+            pid,
+            hwnd,
+            ownership,
+            kill_on_tray_exit: false,
+        })
+    }
+
+    fn from_new_process(
+        platform: &P,
+        settings: &MixerSettings,
+    ) -> Result<VolumeMixerProcess<P>, Error> {
+        let exec_path = platform.system_exec_path(&settings.exec_name)?;
 
-        let (pid, hprocess) = run_exec(exec_path.as_path(), &startup_info)?;
+        // A mixer that is ended with the tray anyway must not outlive a
+        // crashed tray either, it would linger hidden.
+        let options = SpawnOptions {
+            hidden: true,
+            kill_on_tray_exit: settings.spawned_ends_with_tray(),
+            // Placed by the tray before it is shown.
+            ..Self::spawn_options(exec_path.as_path(), settings.mode, Point::default())
+        };
+        let process = platform.spawn(exec_path.as_path(), &options)?;
+        let pid = process.pid();
 
-        let hwnd = Self::try_find_volume_mixer_window(pid)?;
+        let owner = WindowMatcher::OwnerPid(pid);
+        let hwnd = Self::wait_for_volume_mixer_window(platform, settings, owner, Some(pid))?.hwnd;
 
         Ok(VolumeMixerProcess {
             pid,
             hwnd,
-            hprocess,
+            ownership: Ownership::Spawned(process),
+            kill_on_tray_exit: options.kill_on_tray_exit,
         })
     }
 
-    fn try_find_volume_mixer_window(pid: u32) -> Result<HWND, String> {
-        // Give OS some time when trying to get HWND
-        // just after creating a process.
-        let window_title_pattern = "Volume Mixer";
-        for _ in 0..4 {
-            if let Ok(hwnd) = find_window_by_pattern_in_title(window_title_pattern, pid) {
-                return Ok(hwnd);
-            } else {
-                std::thread::sleep(time::Duration::from_millis(250));
-            }
+    /// Start another mixer in `mode` at `anchor` for the user to close,
+    /// without tracking it.
+    pub fn launch(
+        platform: &P,
+        settings: &MixerSettings,
+        mode: MixerMode,
+        anchor: Point,
+    ) -> Result<u32, Error> {
+        let exec_path = platform.system_exec_path(&settings.exec_name)?;
+        let process = platform.spawn(
+            exec_path.as_path(),
+            &Self::spawn_options(exec_path.as_path(), mode, anchor),
+        )?;
+
+        Ok(process.pid())
+    }
+
+    /// Start the mixer in `mode` from its own directory, so that it does not
+    /// keep the working directory of the tray in use.
+    fn spawn_options(exec_path: &Path, mode: MixerMode, anchor: Point) -> SpawnOptions {
+        SpawnOptions {
+            args: mode.args(anchor),
+            working_dir: exec_path.parent().map(Path::to_path_buf),
+            ..SpawnOptions::default()
         }
+    }
 
-        find_window_by_pattern_in_title(window_title_pattern, pid)
+    /// Give a mixer that just started time to create its window, `pid` being
+    /// the mixer process if known.
+    fn wait_for_volume_mixer_window(
+        platform: &P,
+        settings: &MixerSettings,
+        owner: WindowMatcher,
+        pid: Option<u32>,
+    ) -> Result<WindowInfo, Error> {
+        let matcher = settings.window_matcher(owner);
+        match platform.watch_windows(pid) {
+            Ok(watch) => watch.wait_for_window(platform, &matcher, settings.find_window_timeout),
+            Err(err) => {
+                warn!(
+                    "Could not wait for volume mixer window, look only once: {}",
+                    err
+                );
+                platform.find_window(&matcher)
+            }
+        }
     }
+}
+
+impl<P: ProcessSpawner + WindowFinder + WindowManager> VolumeMixerProcess<P> {
+    /// Leave the mixer as configured for a spawned or adopted mixer, when the
+    /// tray exits.
+    pub fn shutdown(&mut self, platform: &P, settings: &MixerSettings) {
+        if let Ownership::Released = self.ownership {
+            return;
+        }
+        let action = if self.is_adopted() {
+            settings.adopted_on_quit
+        } else {
+            settings.spawned_on_quit
+        };
+        debug!("Shut down volume mixer with pid {}: {:?}", self.pid, action);
 
-    fn construct_volume_mixer_exec_path() -> PathBuf {
-        let env_var_name = "WINDIR";
-        let mut exec_path = PathBuf::new();
+        match action {
+            QuitAction::LeaveRunning => {}
+            QuitAction::Hide => platform.hide(self.hwnd),
+            QuitAction::Close => {
+                if let Err(err) = platform.close(self.hwnd) {
+                    warn!("{}", err);
+                }
+            }
+            QuitAction::Terminate => {
+                self.take_over(platform);
+                self.terminate(platform, settings);
+            }
+        }
+        self.ownership = Ownership::Released;
+    }
 
-        let windir = env::var(env_var_name).expect("WINDIR should be available");
-        exec_path.push(windir);
-        exec_path.push("System32");
-        exec_path.push("SndVol.exe");
+    /// Open a handle to a borrowed mixer, so that it can be terminated.
+    /// `false` if the mixer stays borrowed.
+    pub fn take_over(&mut self, platform: &P) -> bool {
+        if let Ownership::Borrowed = self.ownership {
+            match platform.open_process(self.pid) {
+                Ok(process) => self.ownership = Ownership::TakenOver(process),
+                Err(err) => {
+                    warn!("{}", err);
+                    return false;
+                }
+            }
+        }
 
-        assert!(exec_path.exists());
+        true
+    }
 
-        exec_path
+    /// Close the mixer, terminating it if it does not exit in time, if the
+    /// tray holds a handle to it. A borrowed mixer is left running.
+    pub fn terminate(&mut self, platform: &P, settings: &MixerSettings) {
+        match std::mem::replace(&mut self.ownership, Ownership::Released) {
+            Ownership::Spawned(mut process) | Ownership::TakenOver(mut process) => {
+                let sequence = ShutdownSequence::new(settings.close_timeout);
+                match sequence.run(&mut process, || platform.close(self.hwnd)) {
+                    Ok(stage) => info!("Volume Mixer process {} {}", self.pid, stage),
+                    Err(err) => warn!("{}", err),
+                }
+            }
+            ownership => self.ownership = ownership,
+        }
     }
 }
 
-impl Drop for VolumeMixerProcess {
-    fn drop(&mut self) {
-        unsafe {
-            if let Err(err) = TerminateProcess(self.hprocess.as_raw_handle(), 0).ok() {
-                warn!("TerminateProcess failed: {}", err);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_platform::FakePlatform;
+    use crate::platform::WindowManager;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn spawns_volume_mixer_when_not_running() {
+        let platform = FakePlatform::default();
+
+        let process = VolumeMixerProcess::new(&platform, &MixerSettings::default(), &[]).unwrap();
+
+        let state = platform.state();
+        assert_eq!(state.spawned.len(), 1);
+        assert!(state.spawned[0].ends_with("SndVol.exe"));
+        assert!(state.is_process_alive(process.pid));
+        assert_eq!(state.window(process.hwnd).unwrap().pid, process.pid);
+    }
+
+    #[test]
+    fn adopts_running_volume_mixer() {
+        let platform = FakePlatform::default();
+        let (pid, hwnd) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+
+        let process = VolumeMixerProcess::new(&platform, &MixerSettings::default(), &[]).unwrap();
+
+        assert_eq!(process.pid, pid);
+        assert_eq!(process.hwnd, hwnd);
+        assert!(platform.state().spawned.is_empty());
+    }
+
+    #[test]
+    fn finds_localized_window_by_default() {
+        let platform = FakePlatform::default();
+        let (pid, _) = platform.add_process_with_window("SndVol.exe", "Default IME");
+        let hwnd = platform.state_mut().add_window(pid, "Lautstärkemixer");
+
+        let process = VolumeMixerProcess::new(&platform, &MixerSettings::default(), &[]).unwrap();
+        assert_eq!(process.hwnd, hwnd);
+    }
+
+    #[test]
+    fn adopts_running_volume_mixer_with_window() {
+        let platform = FakePlatform::default();
+        platform.add_process_with_window("SndVol.exe", "Default IME");
+        let (pid, hwnd) = platform.add_process_with_window("sndvol.exe", "Volume Mixer");
+
+        let process = VolumeMixerProcess::new(&platform, &MixerSettings::default(), &[]).unwrap();
+
+        assert_eq!(process.pid, pid);
+        assert_eq!(process.hwnd, hwnd);
+    }
+
+    #[test]
+    fn adopts_only_system_mixer_of_current_session() {
+        let platform = FakePlatform::default();
+        let (other_user_pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let (copy_pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        {
+            let mut state = platform.state_mut();
+            for process in state.processes.iter_mut() {
+                if process.pid == other_user_pid {
+                    process.session_id = 2;
+                } else if process.pid == copy_pid {
+                    process.exec_path = PathBuf::from(r"D:\Tools\SndVol.exe");
+                }
             }
         }
-        info!("Terminate Volume Mixer process");
+
+        let process = VolumeMixerProcess::new(&platform, &MixerSettings::default(), &[]).unwrap();
+        assert!(!process.is_adopted());
+        assert_eq!(platform.state().spawned.len(), 1);
+
+        let settings = MixerSettings {
+            adopt_current_session_only: false,
+            ..MixerSettings::default()
+        };
+        let process = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+        assert_eq!(process.pid, other_user_pid);
+    }
+
+    #[test]
+    fn fails_when_volume_mixer_window_is_missing() {
+        let platform = FakePlatform::default();
+        platform.add_process_with_window("SndVol.exe", "Something else");
+        let settings = MixerSettings {
+            find_window_timeout: Duration::from_millis(50),
+            ..MixerSettings::default()
+        };
+
+        let err = VolumeMixerProcess::new(&platform, &settings, &[])
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::WindowNotFound { .. }));
+    }
+
+    #[test]
+    fn stops_waiting_for_window_of_exited_mixer() {
+        let platform = FakePlatform::default();
+        platform.state_mut().spawned_exit_code = Some(1);
+        let settings = MixerSettings {
+            find_window_timeout: Duration::from_secs(30),
+            ..MixerSettings::default()
+        };
+
+        let started = Instant::now();
+        let err = VolumeMixerProcess::new(&platform, &settings, &[])
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::WindowWaitCancelled { .. }));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn uses_configured_exec_name_and_title() {
+        let platform = FakePlatform::default();
+        platform.state_mut().spawned_window_title = Some("Mikser".to_string());
+        let settings = MixerSettings {
+            exec_name: "Mixer.exe".to_string(),
+            window_titles: vec!["Mikser".to_string()],
+            ..MixerSettings::default()
+        };
+
+        let process = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+
+        assert!(platform.state().spawned[0].ends_with("Mixer.exe"));
+        assert!(platform.state().window(process.hwnd).is_some());
+    }
+
+    #[test]
+    fn check_reports_exit_and_new_window() {
+        let platform = FakePlatform::default();
+        let settings = MixerSettings::default();
+        let mut process = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+        assert_eq!(process.check(&platform, &settings), Health::Running);
+
+        let old_hwnd = process.hwnd;
+        let new_hwnd = {
+            let mut state = platform.state_mut();
+            state.windows.retain(|window| window.hwnd != old_hwnd);
+            state.add_window(process.pid, "Volume Mixer")
+        };
+        assert_eq!(
+            process.check(&platform, &settings),
+            Health::WindowChanged(new_hwnd)
+        );
+
+        platform.state_mut().exit_process(process.pid, 0xC000_0005);
+        assert_eq!(
+            process.check(&platform, &settings),
+            Health::Exited(ExitReason::Crashed { code: 0xC000_0005 })
+        );
+    }
+
+    #[test]
+    fn check_notices_adopted_process_is_gone() {
+        let platform = FakePlatform::default();
+        let (pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let settings = MixerSettings::default();
+        let mut process = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+
+        platform.state_mut().exit_process(pid, 0);
+
+        assert_eq!(
+            process.check(&platform, &settings),
+            Health::Exited(ExitReason::Vanished)
+        );
+    }
+
+    #[test]
+    fn default_shutdown_terminates_only_spawned_process() {
+        let platform = FakePlatform::default();
+        let settings = MixerSettings::default();
+        let mut spawned = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+        spawned.shutdown(&platform, &settings);
+        assert!(!platform.state().is_process_alive(spawned.pid));
+
+        let (adopted_pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let mut adopted = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+        assert!(adopted.is_adopted());
+        adopted.shutdown(&platform, &settings);
+        assert!(platform.state().is_process_alive(adopted_pid));
+    }
+
+    #[test]
+    fn spawned_mixer_ends_with_tray_unless_left_behind() {
+        let platform = FakePlatform::default();
+        let mut settings = MixerSettings::default();
+        VolumeMixerProcess::new(&platform, &settings, &[])
+            .unwrap()
+            .terminate(&platform, &settings);
+
+        settings.spawned_on_quit = QuitAction::LeaveRunning;
+        VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+
+        let kill_on_tray_exit: Vec<bool> = platform
+            .state()
+            .spawn_options
+            .iter()
+            .map(|options| options.kill_on_tray_exit)
+            .collect();
+        assert_eq!(kill_on_tray_exit, vec![true, false]);
+    }
+
+    #[test]
+    fn spawns_mixer_hidden_in_configured_mode() {
+        let platform = FakePlatform::default();
+        let settings = MixerSettings {
+            mode: MixerMode::TrayMixer,
+            ..MixerSettings::default()
+        };
+
+        VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+        let launched = VolumeMixerProcess::launch(
+            &platform,
+            &settings,
+            MixerMode::Mixer,
+            Point { x: 10, y: 20 },
+        )
+        .unwrap();
+
+        let state = platform.state();
+        let exec_path = platform.system_exec_path("SndVol.exe").unwrap();
+        let system_dir = exec_path.parent().map(Path::to_path_buf);
+        assert_eq!(state.spawn_options[0].args, vec!["-m", "0"]);
+        assert_eq!(state.spawn_options[0].working_dir, system_dir);
+        assert!(state.spawn_options[0].hidden);
+        assert_eq!(
+            state.spawn_options[1],
+            SpawnOptions {
+                working_dir: system_dir,
+                ..SpawnOptions::default()
+            }
+        );
+        assert!(state.is_process_alive(launched));
+    }
+
+    #[test]
+    fn shutdown_follows_configured_action() {
+        let platform = FakePlatform::default();
+        let settings = MixerSettings {
+            spawned_on_quit: QuitAction::Hide,
+            adopted_on_quit: QuitAction::Close,
+            ..MixerSettings::default()
+        };
+
+        let mut spawned = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+        platform.show(spawned.hwnd);
+        spawned.shutdown(&platform, &settings);
+        assert!(platform.state().is_process_alive(spawned.pid));
+        assert!(!platform.is_visible(spawned.hwnd));
+
+        platform.state_mut().exit_process(spawned.pid, 0);
+        let (adopted_pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let mut adopted = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+        adopted.shutdown(&platform, &settings);
+        assert!(!platform.state().is_process_alive(adopted_pid));
+    }
+
+    #[test]
+    fn taken_over_process_reports_exit_code_and_terminates() {
+        let platform = FakePlatform::default();
+        let settings = MixerSettings {
+            adopt: AdoptMode::TakeOver,
+            ..MixerSettings::default()
+        };
+        let (pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let mut process = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+
+        platform.state_mut().exit_process(pid, 3);
+        assert_eq!(
+            process.check(&platform, &settings),
+            Health::Exited(ExitReason::Exited { code: 3 })
+        );
+
+        let (pid, _) = platform.add_process_with_window("SndVol.exe", "Volume Mixer");
+        let mut process = VolumeMixerProcess::new(&platform, &settings, &[]).unwrap();
+        process.terminate(&platform, &settings);
+        assert!(!platform.state().is_process_alive(pid));
     }
 }
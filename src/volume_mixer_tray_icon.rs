@@ -1,174 +1,636 @@
-use std::ffi::c_void;
-use crate::windows_utils::ExtendPCWSTR;
+use crate::config::{AutoHideSettings, HotkeySettings, PlacementSettings, Settings};
+use crate::error::Error;
+use crate::hotkey::HotkeyAction;
+use crate::menu::tray_menu;
+use crate::placement::{self, Anchors};
+use crate::platform::{Rect, TrayEvent, TrayShell, WindowHandle, WindowManager};
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
-use windows::core::{Error, PCWSTR};
-use windows::Win32::Foundation::{FALSE, HANDLE, HMODULE, HWND, LPARAM, LRESULT, RECT, WPARAM};
-use windows::Win32::UI::Shell::{
-    Shell_NotifyIconA, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NOTIFYICONDATAA,
-};
-use windows::Win32::UI::WindowsAndMessaging::{
-    DefWindowProcW, GetPropW, GetWindowRect, IsWindowVisible, LoadIconW, MoveWindow,
-    PostQuitMessage, SetForegroundWindow, ShowWindow, SystemParametersInfoA, IDI_APPLICATION,
-    SPI_GETWORKAREA, SW_HIDE, SW_SHOW, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS, WM_APP, WM_LBUTTONDOWN,
-    WM_RBUTTONDOWN,
-};
-
-pub const PROP_VOLUME_MIXER_HWND: &str = "PROP_VOLUME_MIXER_HWND";
-
-#[derive(Default)]
-pub struct VolumeMixerTrayIcon {
-    notif_data: NOTIFYICONDATAA,
+use std::time::{Duration, Instant};
+
+/// Flyout behaviour: hide the mixer once another window takes the focus.
+///
+/// Clicking the tray icon while the mixer is open first moves the focus to
+/// the taskbar, which auto-hides the mixer, and only then delivers the click.
+/// Clicks shortly after an auto-hide are therefore swallowed instead of
+/// showing the mixer again. Focus losses shortly after showing are ignored
+/// as well, since the mixer only becomes the foreground window asynchronously.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoHide {
+    Hidden,
+    Visible { since: Instant },
+    AutoHidden { at: Instant },
 }
 
-impl VolumeMixerTrayIcon {
-    pub const MSG_ID: u32 = WM_APP + 1;
+impl AutoHide {
+    pub fn on_shown(&mut self, now: Instant) {
+        *self = AutoHide::Visible { since: now };
+    }
 
-    pub fn new(hwnd: HWND) -> VolumeMixerTrayIcon {
-        let tip_msg_buf = Self::construct_tip_msg_buf("Custom Volume Mixer");
+    pub fn on_hidden(&mut self) {
+        *self = AutoHide::Hidden;
+    }
 
-        let mut notif_data = NOTIFYICONDATAA::default();
+    /// Whether the mixer has to be hidden because it lost the focus.
+    pub fn on_focus_check(&mut self, mixer_focused: bool, now: Instant, grace: Duration) -> bool {
+        match *self {
+            AutoHide::Visible { since } if !mixer_focused && now >= since + grace => {
+                *self = AutoHide::AutoHidden { at: now };
+                true
+            }
+            _ => false,
+        }
+    }
 
-        notif_data.cbSize = std::mem::size_of_val(&notif_data) as u32;
-        notif_data.hWnd = hwnd;
-        notif_data.uFlags = NIF_TIP | NIF_ICON | NIF_MESSAGE;
-        notif_data.uCallbackMessage = Self::MSG_ID;
-        notif_data.szTip = tip_msg_buf;
-        notif_data.hIcon = unsafe { LoadIconW(HMODULE::default(), IDI_APPLICATION).unwrap() };
-        let notif_result = unsafe { Shell_NotifyIconA(NIM_ADD, &notif_data) };
-        if notif_result.as_bool() {
-            info!("Send message to add icon");
+    /// Whether a click on the tray icon should toggle the mixer.
+    pub fn on_tray_click(&mut self, now: Instant, grace: Duration) -> bool {
+        match *self {
+            AutoHide::AutoHidden { at } if now < at + grace => {
+                *self = AutoHide::Hidden;
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+pub struct VolumeMixerTrayIcon<S: TrayShell, W: WindowManager> {
+    shell: S,
+    windows: W,
+    volume_mixer_hwnd: WindowHandle,
+    placement: PlacementSettings,
+    /// Rect of the mixer when it was last hidden by the tray.
+    last_position: Option<Rect>,
+    registered_hotkeys: Vec<HotkeyAction>,
+    /// `false` when running without a tray icon, controlled by hotkeys and commands only.
+    show_icon: bool,
+    auto_hide_settings: AutoHideSettings,
+    auto_hide: AutoHide,
+    /// Whether foreground changes are delivered, only while an auto-hiding mixer is shown.
+    watching_foreground: bool,
+}
+
+impl<S: TrayShell, W: WindowManager> VolumeMixerTrayIcon<S, W> {
+    pub fn new(
+        mut shell: S,
+        windows: W,
+        volume_mixer_hwnd: WindowHandle,
+        settings: &Settings,
+        show_icon: bool,
+    ) -> VolumeMixerTrayIcon<S, W> {
+        if !show_icon {
+            info!("Run without tray icon");
+        } else if let Err(err) = shell.add_icon(&settings.tray.tooltip) {
+            error!("Failed to add icon: {}", err);
         } else {
-            error!("Failed to send message that adds icon");
+            info!("Send message to add icon");
         }
 
-        VolumeMixerTrayIcon { notif_data }
+        let mut tray_icon = VolumeMixerTrayIcon {
+            shell,
+            windows,
+            volume_mixer_hwnd,
+            placement: settings.placement.clone(),
+            last_position: None,
+            registered_hotkeys: Vec::new(),
+            show_icon,
+            auto_hide_settings: settings.tray.auto_hide.clone(),
+            auto_hide: AutoHide::Hidden,
+            watching_foreground: false,
+        };
+        tray_icon.set_hotkeys(&settings.hotkeys);
+
+        tray_icon
     }
 
-    pub unsafe extern "system" fn wnd_proc(
-        hwnd: HWND,
-        umsg: u32,
-        wparam: WPARAM,
-        lparam: LPARAM,
-    ) -> LRESULT {
-        match umsg {
-            VolumeMixerTrayIcon::MSG_ID => match lparam.0 as u32 {
-                WM_LBUTTONDOWN => Self::on_left_mouse_pressed(hwnd),
-                WM_RBUTTONDOWN => Self::on_right_mouse_pressed(),
-                _ => {}
-            },
-            _ => return DefWindowProcW(hwnd, umsg, wparam, lparam),
+    pub fn handle_tray_event(&mut self, event: TrayEvent) {
+        match event {
+            TrayEvent::LeftButtonDown | TrayEvent::MiddleButtonDown => self.on_toggle_click(),
+            TrayEvent::RightButtonDown => self.on_right_mouse_pressed(),
         }
+    }
 
-        LRESULT::default()
+    pub fn set_tooltip(&mut self, tooltip: &str) {
+        if !self.show_icon {
+            return;
+        }
+        if let Err(err) = self.shell.set_tooltip(tooltip) {
+            error!("Failed to update icon tooltip: {}", err);
+        }
     }
 
-    fn construct_tip_msg_buf(tip_msg: &str) -> [u8; 128] {
-        let mut array: [u8; 128] = [0; 128];
-        array[..tip_msg.len()].copy_from_slice(tip_msg.as_bytes());
+    pub fn set_placement(&mut self, placement: &PlacementSettings) {
+        self.placement = placement.clone();
+    }
 
-        array
+    pub fn set_auto_hide(&mut self, auto_hide: &AutoHideSettings) {
+        self.auto_hide_settings = auto_hide.clone();
+        self.update_foreground_watch();
     }
 
-    fn on_left_mouse_pressed(hwnd: HWND) {
-        let utf16_prop_name = PCWSTR::from_str(PROP_VOLUME_MIXER_HWND);
-        let data: HANDLE = { unsafe { GetPropW(hwnd, utf16_prop_name) } };
+    /// Hide the mixer if auto-hide is on and another window took the focus.
+    pub fn check_focus(&mut self) {
+        if !self.auto_hide_settings.enabled {
+            return;
+        }
 
-        if data.is_invalid() {
-            error!(
-                "GetPropW failed for property \"{}\": {}",
-                unsafe { utf16_prop_name.display() },
-                Error::from_win32()
-            );
+        let mixer_focused = self.windows.foreground_window() == Some(self.volume_mixer_hwnd);
+        if self.auto_hide.on_focus_check(
+            mixer_focused,
+            Instant::now(),
+            self.auto_hide_settings.grace_period,
+        ) {
+            debug!("Volume mixer lost focus, hide it");
+            self.hide_window();
+            self.update_foreground_watch();
+        }
+    }
 
-            return;
+    /// Replace the registered hotkeys. Hotkeys that cannot be registered are
+    /// logged and skipped.
+    pub fn set_hotkeys(&mut self, hotkeys: &HotkeySettings) {
+        for action in self.registered_hotkeys.drain(..) {
+            self.shell.unregister_hotkey(action);
         }
 
-        let volume_mixer_hwnd = HWND(data.0);
-        let is_visible = unsafe { IsWindowVisible(volume_mixer_hwnd).as_bool() };
-        let show_cmd = {
-            if is_visible {
-                SW_HIDE
+        let wanted = [
+            (HotkeyAction::ToggleMixer, hotkeys.toggle),
+            (HotkeyAction::HideMixer, hotkeys.hide),
+        ];
+        for (action, hotkey) in wanted {
+            let Some(hotkey) = hotkey else {
+                continue;
+            };
+            if let Err(err) = self.shell.register_hotkey(action, hotkey) {
+                error!("Failed to register hotkey: {}", err);
             } else {
-                if let Err(err_str) = Self::move_window_to_right_bottom_corner(volume_mixer_hwnd) {
-                    error!("Failed to move volume mixer window. Reason: {}", err_str);
-                }
-
-                SW_SHOW
+                info!("Register hotkey {} for {:?}", hotkey, action);
+                self.registered_hotkeys.push(action);
             }
-        };
-        unsafe {
-            ShowWindow(volume_mixer_hwnd, show_cmd);
-            SetForegroundWindow(volume_mixer_hwnd);
         }
     }
 
-    fn on_right_mouse_pressed() {
-        unsafe { PostQuitMessage(0) };
+    pub fn handle_hotkey(&mut self, action: HotkeyAction) {
+        match action {
+            HotkeyAction::ToggleMixer => self.toggle_volume_mixer(),
+            HotkeyAction::HideMixer => self.hide_volume_mixer(),
+        }
+    }
+
+    pub fn set_volume_mixer_hwnd(&mut self, hwnd: WindowHandle) {
+        self.volume_mixer_hwnd = hwnd;
+        self.last_position = None;
     }
 
-    fn move_window_to_right_bottom_corner(hwnd: HWND) -> Result<(), String> {
-        let (window_width, window_height) = Self::get_window_size(hwnd)?;
-        let (desktop_width, desktop_height) = Self::get_desktop_size_without_taskbar()?;
+    pub fn show_message(&self, title: &str, text: &str) {
+        self.shell.show_message(title, text);
+    }
 
-        let move_result = unsafe {
-            MoveWindow(
-                hwnd,
-                desktop_width - window_width,
-                desktop_height - window_height,
-                window_width,
-                window_height,
-                FALSE,
-            )
-        };
-        if let Err(err) = move_result.ok() {
-            Err(format!("MoveWindow failed: {}", err))
+    pub fn quit(&self) {
+        self.shell.post_quit();
+    }
+
+    /// Hide the mixer if it is visible, otherwise show it in the configured corner.
+    pub fn toggle_volume_mixer(&mut self) {
+        let volume_mixer_hwnd = self.volume_mixer_hwnd;
+        if self.windows.is_visible(volume_mixer_hwnd) {
+            self.hide_volume_mixer();
+            self.windows.set_foreground(volume_mixer_hwnd);
         } else {
-            Ok(())
+            self.show_volume_mixer();
         }
     }
 
-    fn get_window_size(hwnd: HWND) -> Result<(i32, i32), String> {
-        let mut window_rect = RECT::default();
-        let get_rect_result = unsafe { GetWindowRect(hwnd, &mut window_rect) };
-        if let Err(err) = get_rect_result.ok() {
-            Err(format!("GetWindowRect failed: {}", err))
+    /// Show the mixer if hidden and bring it to the front.
+    pub fn show_volume_mixer(&mut self) {
+        let volume_mixer_hwnd = self.volume_mixer_hwnd;
+        if !self.windows.is_visible(volume_mixer_hwnd) {
+            if let Err(err) = self.place_volume_mixer(volume_mixer_hwnd) {
+                error!("Failed to move volume mixer window. Reason: {}", err);
+            }
+
+            self.windows.show(volume_mixer_hwnd);
+            self.auto_hide.on_shown(Instant::now());
+            self.update_foreground_watch();
+        }
+        self.windows.set_foreground(volume_mixer_hwnd);
+    }
+
+    pub fn hide_volume_mixer(&mut self) {
+        self.hide_window();
+        self.auto_hide.on_hidden();
+        self.update_foreground_watch();
+    }
+
+    /// Watch the foreground window exactly while auto-hide may have to act.
+    fn update_foreground_watch(&mut self) {
+        let watch =
+            self.auto_hide_settings.enabled && matches!(self.auto_hide, AutoHide::Visible { .. });
+        if watch == self.watching_foreground {
+            return;
+        }
+
+        if !watch {
+            self.shell.unwatch_foreground();
+            self.watching_foreground = false;
+        } else if let Err(err) = self.shell.watch_foreground() {
+            warn!("Volume mixer will not be hidden automatically: {}", err);
         } else {
-            Ok((
-                window_rect.right - window_rect.left,
-                window_rect.bottom - window_rect.top,
-            ))
-        }
-    }
-
-    fn get_desktop_size_without_taskbar() -> Result<(i32, i32), String> {
-        let mut desktop_rect = RECT::default();
-        let sysinfo_req_result = unsafe {
-            SystemParametersInfoA(
-                SPI_GETWORKAREA,
-                0,
-                Some(&mut desktop_rect as *mut _ as *mut c_void),
-                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS::default(),
-            )
+            self.watching_foreground = true;
+        }
+    }
+
+    fn hide_window(&mut self) {
+        let volume_mixer_hwnd = self.volume_mixer_hwnd;
+        if self.windows.is_visible(volume_mixer_hwnd) {
+            self.last_position = self.windows.window_rect(volume_mixer_hwnd).ok();
+            self.windows.hide(volume_mixer_hwnd);
+        }
+    }
+
+    fn on_toggle_click(&mut self) {
+        if self.auto_hide_settings.enabled
+            && !self
+                .auto_hide
+                .on_tray_click(Instant::now(), self.auto_hide_settings.grace_period)
+        {
+            debug!("Ignore click on tray icon that just auto-hid the mixer");
+            return;
+        }
+
+        self.toggle_volume_mixer();
+    }
+
+    fn on_right_mouse_pressed(&self) {
+        let menu = tray_menu(self.windows.is_visible(self.volume_mixer_hwnd));
+        if let Err(err) = self.shell.show_context_menu(&menu) {
+            error!("Failed to show context menu: {}", err);
+        }
+    }
+
+    fn place_volume_mixer(&self, hwnd: WindowHandle) -> Result<(), Error> {
+        let window_rect = self.windows.window_rect(hwnd)?;
+        let monitors = self.windows.monitors()?;
+
+        let anchors = Anchors {
+            tray_icon: self
+                .show_icon
+                .then(|| self.shell.icon_rect())
+                .and_then(|rect| {
+                    rect.map_err(|err| debug!("Tray icon position unknown: {}", err))
+                        .ok()
+                }),
+            cursor: self
+                .windows
+                .cursor_pos()
+                .map_err(|err| debug!("Cursor position unknown: {}", err))
+                .ok(),
+            last_position: self.last_position,
         };
-        if let Err(err) = sysinfo_req_result.ok() {
-            Err(format!("SystemParametersInfoA failed: {}", err))
-        } else {
-            Ok((
-                desktop_rect.right - desktop_rect.left,
-                desktop_rect.bottom - desktop_rect.top,
-            ))
+        let placed = placement::place(&self.placement, window_rect, &monitors, anchors);
+        let placed_on = placed.and_then(|rect| placement::monitor_at(&monitors, rect.center()));
+        if let (Some(rect), Some(monitor)) = (placed, placed_on) {
+            debug!(
+                "Place volume mixer at {:?}, {}x{} logical pixels at {} DPI",
+                rect,
+                monitor.dpi.to_logical(rect.width()),
+                monitor.dpi.to_logical(rect.height()),
+                monitor.dpi.0
+            );
+        }
+        match placed {
+            Some(rect) if rect.width() != window_rect.width() => {
+                // The window is rescaled when it reaches a monitor with another
+                // DPI. Moving it there first lets that happen before the final
+                // position is set, instead of the system scaling the new size again.
+                let moved = Rect::new(
+                    rect.left,
+                    rect.top,
+                    rect.left + window_rect.width(),
+                    rect.top + window_rect.height(),
+                );
+                self.windows.move_window(hwnd, moved)?;
+                self.windows.move_window(hwnd, rect)
+            }
+            Some(rect) => self.windows.move_window(hwnd, rect),
+            None => {
+                warn!("No monitor found, leave volume mixer where it is");
+                Ok(())
+            }
         }
     }
 }
 
-impl Drop for VolumeMixerTrayIcon {
+impl<S: TrayShell, W: WindowManager> Drop for VolumeMixerTrayIcon<S, W> {
     fn drop(&mut self) {
-        let notif_delete_result = unsafe { Shell_NotifyIconA(NIM_DELETE, &self.notif_data) };
-        if notif_delete_result.as_bool() {
-            info!("Send message to delete icon");
+        for action in self.registered_hotkeys.drain(..) {
+            self.shell.unregister_hotkey(action);
+        }
+        if self.watching_foreground {
+            self.shell.unwatch_foreground();
+        }
+
+        if !self.show_icon {
+            return;
+        }
+        if let Err(err) = self.shell.remove_icon() {
+            warn!("Failed to delete icon: {}", err);
         } else {
-            warn!("Failed to send message that deletes icon");
+            info!("Send message to delete icon");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Corner, MixerSettings, PlacementStrategy};
+    use crate::dpi::Dpi;
+    use crate::fake_platform::FakePlatform;
+    use crate::hotkey::Hotkey;
+    use crate::platform::Monitor;
+    use crate::volume_mixer_process::VolumeMixerProcess;
+
+    type FakeTrayIcon = VolumeMixerTrayIcon<FakePlatform, FakePlatform>;
+
+    fn start(platform: &FakePlatform) -> (VolumeMixerProcess<FakePlatform>, FakeTrayIcon) {
+        start_with(platform, &Settings::default())
+    }
+
+    fn start_with(
+        platform: &FakePlatform,
+        settings: &Settings,
+    ) -> (VolumeMixerProcess<FakePlatform>, FakeTrayIcon) {
+        let process = VolumeMixerProcess::new(platform, &MixerSettings::default(), &[]).unwrap();
+        let tray_icon = VolumeMixerTrayIcon::new(
+            platform.clone(),
+            platform.clone(),
+            process.hwnd,
+            settings,
+            true,
+        );
+
+        (process, tray_icon)
+    }
+
+    #[test]
+    fn startup_adds_icon_and_drop_removes_it() {
+        let platform = FakePlatform::default();
+
+        let (_process, tray_icon) = start(&platform);
+        assert_eq!(
+            platform.state().tray_tooltip.as_deref(),
+            Some("Custom Volume Mixer")
+        );
+        assert!(platform.state().hotkeys.is_empty());
+
+        drop(tray_icon);
+        assert_eq!(platform.state().tray_tooltip, None);
+    }
+
+    #[test]
+    fn runs_without_icon() {
+        let platform = FakePlatform::default();
+        let process = VolumeMixerProcess::new(&platform, &MixerSettings::default(), &[]).unwrap();
+        let mut tray_icon = VolumeMixerTrayIcon::new(
+            platform.clone(),
+            platform.clone(),
+            process.hwnd,
+            &Settings::default(),
+            false,
+        );
+        tray_icon.set_tooltip("Mixer");
+        assert_eq!(platform.state().tray_tooltip, None);
+
+        tray_icon.handle_hotkey(HotkeyAction::ToggleMixer);
+        assert!(platform.is_visible(process.hwnd));
+    }
+
+    #[test]
+    fn left_click_toggles_volume_mixer() {
+        let platform = FakePlatform::default();
+        platform
+            .state_mut()
+            .set_work_area(Rect::new(0, 0, 1920, 1040));
+        let (_process, mut tray_icon) = start(&platform);
+        let hwnd = tray_icon.volume_mixer_hwnd;
+
+        tray_icon.handle_tray_event(TrayEvent::LeftButtonDown);
+        {
+            let state = platform.state();
+            let window = state.window(hwnd).unwrap();
+            assert!(window.visible);
+            assert_eq!(window.rect, Rect::new(1620, 840, 1920, 1040));
+            assert_eq!(state.foreground, Some(hwnd));
+        }
+
+        tray_icon.handle_tray_event(TrayEvent::LeftButtonDown);
+        assert!(!platform.state().window(hwnd).unwrap().visible);
+    }
+
+    #[test]
+    fn uses_configured_tooltip_and_corner() {
+        let platform = FakePlatform::default();
+        platform
+            .state_mut()
+            .set_work_area(Rect::new(0, 40, 1920, 1080));
+        let mut settings = Settings::default();
+        settings.tray.tooltip = "Mixer".to_string();
+        settings.placement.corner = Corner::TopLeft;
+        settings.placement.strategy = PlacementStrategy::Corner;
+        let (_process, mut tray_icon) = start_with(&platform, &settings);
+
+        tray_icon.handle_tray_event(TrayEvent::LeftButtonDown);
+
+        let state = platform.state();
+        assert_eq!(state.tray_tooltip.as_deref(), Some("Mixer"));
+        let window = state.window(tray_icon.volume_mixer_hwnd).unwrap();
+        assert_eq!(window.rect, Rect::new(0, 40, 300, 240));
+    }
+
+    #[test]
+    fn tray_icon_strategy_follows_taskbar_and_remembers_last_position() {
+        let platform = FakePlatform::default();
+        {
+            let mut state = platform.state_mut();
+            state.set_work_area(Rect::new(0, 40, 1920, 1080));
+            state.tray_icon_rect = Some(Rect::new(900, 8, 924, 32));
+        }
+        let mut settings = Settings::default();
+        settings.placement.strategy = PlacementStrategy::TrayIcon;
+        let (_process, mut tray_icon) = start_with(&platform, &settings);
+        let hwnd = tray_icon.volume_mixer_hwnd;
+
+        tray_icon.handle_tray_event(TrayEvent::LeftButtonDown);
+        assert_eq!(
+            platform.state().window(hwnd).unwrap().rect,
+            Rect::new(762, 40, 1062, 240)
+        );
+
+        tray_icon.set_placement(&PlacementSettings {
+            strategy: PlacementStrategy::LastPosition,
+            ..PlacementSettings::default()
+        });
+        platform.state_mut().window_mut(hwnd).unwrap().rect = Rect::new(100, 100, 400, 300);
+        tray_icon.handle_tray_event(TrayEvent::LeftButtonDown);
+        platform.state_mut().window_mut(hwnd).unwrap().rect = Rect::new(0, 0, 300, 200);
+        tray_icon.handle_tray_event(TrayEvent::LeftButtonDown);
+        assert_eq!(
+            platform.state().window(hwnd).unwrap().rect,
+            Rect::new(100, 100, 400, 300)
+        );
+    }
+
+    #[test]
+    fn opens_on_monitor_of_tray_icon() {
+        let platform = FakePlatform::default();
+        {
+            let mut state = platform.state_mut();
+            state.monitors.push(Monitor {
+                bounds: Rect::new(-1280, -200, 0, 824),
+                work_area: Rect::new(-1280, -200, 0, 784),
+                primary: false,
+                dpi: Dpi::DEFAULT,
+            });
+            state.tray_icon_rect = Some(Rect::new(-100, 794, -76, 818));
+        }
+        let (_process, mut tray_icon) = start(&platform);
+
+        tray_icon.handle_tray_event(TrayEvent::LeftButtonDown);
+
+        let state = platform.state();
+        let window = state.window(tray_icon.volume_mixer_hwnd).unwrap();
+        assert_eq!(window.rect, Rect::new(-300, 584, 0, 784));
+    }
+
+    #[test]
+    fn hotkeys_toggle_and_hide_mixer() {
+        let platform = FakePlatform::default();
+        let mut settings = Settings::default();
+        settings.hotkeys.toggle = Some("Win+Shift+V".parse().unwrap());
+        settings.hotkeys.hide = Some("Ctrl+Alt+H".parse().unwrap());
+        let (_process, mut tray_icon) = start_with(&platform, &settings);
+        let hwnd = tray_icon.volume_mixer_hwnd;
+        assert_eq!(platform.state().hotkeys.len(), 2);
+
+        tray_icon.handle_hotkey(HotkeyAction::ToggleMixer);
+        assert!(platform.state().window(hwnd).unwrap().visible);
+        tray_icon.handle_hotkey(HotkeyAction::HideMixer);
+        assert!(!platform.state().window(hwnd).unwrap().visible);
+        tray_icon.handle_hotkey(HotkeyAction::HideMixer);
+        assert!(!platform.state().window(hwnd).unwrap().visible);
+
+        drop(tray_icon);
+        assert!(platform.state().hotkeys.is_empty());
+    }
+
+    #[test]
+    fn conflicting_hotkey_is_skipped() {
+        let platform = FakePlatform::default();
+        let taken: Hotkey = "Win+Shift+V".parse().unwrap();
+        platform.state_mut().taken_hotkeys.push(taken);
+        let mut settings = Settings::default();
+        settings.hotkeys.toggle = Some(taken);
+        settings.hotkeys.hide = Some("Ctrl+Alt+H".parse().unwrap());
+
+        let (_process, _tray_icon) = start_with(&platform, &settings);
+
+        let state = platform.state();
+        assert_eq!(
+            state.hotkeys.keys().copied().collect::<Vec<_>>(),
+            vec![HotkeyAction::HideMixer]
+        );
+    }
+
+    #[test]
+    fn auto_hide_ignores_focus_loss_during_grace_period() {
+        let grace = Duration::from_millis(300);
+        let shown = Instant::now();
+        let mut auto_hide = AutoHide::Hidden;
+
+        assert!(!auto_hide.on_focus_check(false, shown, grace));
+        auto_hide.on_shown(shown);
+        assert!(!auto_hide.on_focus_check(false, shown + Duration::from_millis(100), grace));
+        assert!(!auto_hide.on_focus_check(true, shown + Duration::from_millis(400), grace));
+        assert!(auto_hide.on_focus_check(false, shown + Duration::from_millis(500), grace));
+        assert!(!auto_hide.on_focus_check(false, shown + Duration::from_millis(600), grace));
+    }
+
+    #[test]
+    fn auto_hide_swallows_click_that_caused_it() {
+        let grace = Duration::from_millis(300);
+        let shown = Instant::now();
+        let mut auto_hide = AutoHide::Hidden;
+        auto_hide.on_shown(shown);
+
+        // Click on the tray icon: focus goes to the taskbar first.
+        let hidden = shown + Duration::from_secs(2);
+        assert!(auto_hide.on_focus_check(false, hidden, grace));
+        assert!(!auto_hide.on_tray_click(hidden + Duration::from_millis(50), grace));
+        assert_eq!(auto_hide, AutoHide::Hidden);
+        // The next click shows the mixer again.
+        assert!(auto_hide.on_tray_click(hidden + Duration::from_millis(100), grace));
+
+        // Click long after the mixer was auto-hidden by clicking elsewhere.
+        auto_hide.on_shown(shown);
+        assert!(auto_hide.on_focus_check(false, hidden, grace));
+        assert!(auto_hide.on_tray_click(hidden + Duration::from_secs(5), grace));
+    }
+
+    #[test]
+    fn auto_hide_hides_mixer_when_it_loses_focus() {
+        let platform = FakePlatform::default();
+        let mut settings = Settings::default();
+        settings.tray.auto_hide = AutoHideSettings {
+            enabled: true,
+            grace_period: Duration::ZERO,
+        };
+        let (_process, mut tray_icon) = start_with(&platform, &settings);
+        let hwnd = tray_icon.volume_mixer_hwnd;
+
+        assert!(!platform.state().watching_foreground);
+        tray_icon.handle_tray_event(TrayEvent::LeftButtonDown);
+        assert!(platform.state().watching_foreground);
+        tray_icon.check_focus();
+        assert!(platform.state().window(hwnd).unwrap().visible);
+
+        platform.state_mut().foreground = Some(WindowHandle(1));
+        tray_icon.check_focus();
+        assert!(!platform.state().window(hwnd).unwrap().visible);
+        assert!(!platform.state().watching_foreground);
+    }
+
+    #[test]
+    fn foreground_is_watched_only_for_auto_hide() {
+        let platform = FakePlatform::default();
+        let (_process, mut tray_icon) = start(&platform);
+
+        tray_icon.show_volume_mixer();
+        assert!(!platform.state().watching_foreground);
+
+        tray_icon.set_auto_hide(&AutoHideSettings {
+            enabled: true,
+            grace_period: Duration::ZERO,
+        });
+        assert!(platform.state().watching_foreground);
+
+        tray_icon.hide_volume_mixer();
+        assert!(!platform.state().watching_foreground);
+
+        tray_icon.show_volume_mixer();
+        drop(tray_icon);
+        assert!(!platform.state().watching_foreground);
+    }
+
+    #[test]
+    fn right_click_shows_menu_instead_of_quitting() {
+        let platform = FakePlatform::default();
+        let (_process, mut tray_icon) = start(&platform);
+
+        tray_icon.handle_tray_event(TrayEvent::RightButtonDown);
+
+        let state = platform.state();
+        assert!(!state.quit_posted);
+        assert_eq!(state.shown_menu, Some(tray_menu(false)));
+    }
+}
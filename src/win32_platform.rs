@@ -0,0 +1,995 @@
+use crate::control::ControlListener;
+use crate::dpi::Dpi;
+use crate::error::{Error, OsError};
+use crate::hotkey::{Hotkey, HotkeyAction};
+use crate::instance::{InstanceChannel, InstanceCommand};
+use crate::menu::{Menu, MenuCommand, MenuItem};
+use crate::message_only_window::MessageOnlyWindow;
+use crate::platform::{
+    ChildProcess, EventHandler, Monitor, Point, ProcessSpawner, Rect, SpawnOptions, Timer,
+    TrayEvent, TrayShell, WindowFinder, WindowHandle, WindowManager,
+};
+use crate::process_query::{ProcessInfo, ProcessQuery};
+use crate::window_match::{WindowInfo, WindowMatcher};
+use crate::window_wait::WindowWatch;
+use crate::windows_utils::{
+    current_session_id, kill_on_exit_job, open_process, processes, run_exec, wait_process, windows,
+    WindowEventHook, WindowsHandle,
+};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::fs::File;
+use std::io;
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use windows::core::{HRESULT, HSTRING, PCWSTR};
+use windows::Win32::Foundation::{
+    GetLastError, BOOL, ERROR_ALREADY_EXISTS, ERROR_HOTKEY_ALREADY_REGISTERED,
+    ERROR_INVALID_PARAMETER, ERROR_PIPE_CONNECTED, FALSE, HANDLE, HMODULE, HWND,
+    INVALID_HANDLE_VALUE, LPARAM, LRESULT, POINT, RECT, TRUE, WPARAM,
+};
+use windows::Win32::Graphics::Gdi::{
+    EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO,
+};
+use windows::Win32::Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX};
+use windows::Win32::System::DataExchange::COPYDATASTRUCT;
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+    PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows::Win32::System::Threading::{
+    CreateMutexW, TerminateProcess, STARTF_PREVENTPINNING, STARTF_USESHOWWINDOW, STARTUPINFOW,
+};
+use windows::Win32::UI::Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK};
+use windows::Win32::UI::HiDpi::{
+    GetDpiForMonitor, SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+    MDT_EFFECTIVE_DPI,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_NOREPEAT,
+};
+use windows::Win32::UI::Shell::{
+    ShellExecuteW, Shell_NotifyIconA, Shell_NotifyIconGetRect, NIF_ICON, NIF_MESSAGE, NIF_TIP,
+    NIM_ADD, NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAA, NOTIFYICONIDENTIFIER,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    AllowSetForegroundWindow, AppendMenuW, CreatePopupMenu, DefWindowProcW, DestroyMenu,
+    FindWindowExW, GetAncestor, GetCursorPos, GetForegroundWindow, GetPropW, GetWindowRect,
+    IsWindow, IsWindowVisible, LoadIconW, MessageBoxW, MoveWindow, PostMessageW, PostQuitMessage,
+    SendMessageTimeoutW, SetForegroundWindow, SetMenuDefaultItem, SetTimer, ShowWindow,
+    TrackPopupMenu, ASFW_ANY, EVENT_SYSTEM_FOREGROUND, GA_ROOTOWNER, HMENU, HWND_MESSAGE,
+    IDI_APPLICATION, MB_ICONINFORMATION, MB_OK, MF_GRAYED, MF_SEPARATOR, MF_STRING,
+    MONITORINFOF_PRIMARY, SMTO_ABORTIFHUNG, SW_HIDE, SW_SHOW, SW_SHOWNORMAL, TPM_NONOTIFY,
+    TPM_RETURNCMD, TPM_RIGHTBUTTON, WINEVENT_OUTOFCONTEXT, WM_APP, WM_CLOSE, WM_COMMAND,
+    WM_COPYDATA, WM_HOTKEY, WM_LBUTTONDOWN, WM_MBUTTONDOWN, WM_NULL, WM_RBUTTONDOWN, WM_TIMER,
+    WNDPROC,
+};
+
+/// Window property of the message-only window that holds a pointer to its
+/// `EventHandler`.
+pub const PROP_EVENT_HANDLER: &str = "PROP_EVENT_HANDLER";
+
+pub const TRAY_ICON_MSG_ID: u32 = WM_APP + 1;
+/// Posted to the message-only window when the foreground window changed.
+const FOREGROUND_CHANGED_MSG_ID: u32 = WM_APP + 2;
+
+/// Named mutex held by the running tray, per user session.
+const INSTANCE_MUTEX_NAME: &str = "Local\\VolumeMixerTray";
+
+/// `dwData` of the `WM_COPYDATA` messages that carry forwarded commands.
+const COPYDATA_INSTANCE_COMMAND: usize = 0x564D_4958;
+/// `dwData` of the `WM_COPYDATA` messages that carry replies to forwarded commands.
+const COPYDATA_INSTANCE_REPLY: usize = 0x564D_5250;
+
+const REPLY_WINDOW_CLASS_NAME: &str = "VolumeMixerReplyWindowClass";
+
+/// How long a second launch waits for the running tray to take its command.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTROL_PIPE_BUFFER_SIZE: u32 = 4096;
+
+#[derive(Clone, Copy, Default)]
+pub struct Win32Platform;
+
+pub struct Win32Process {
+    pid: u32,
+    hprocess: WindowsHandle,
+}
+
+impl ChildProcess for Win32Process {
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn terminate(&mut self) -> Result<(), Error> {
+        let result = unsafe { TerminateProcess(self.hprocess.as_raw_handle(), 0) };
+        result.ok().map_err(|err| Error::ProcessTerminate {
+            pid: self.pid,
+            source: OsError::from_win32("TerminateProcess", err),
+        })
+    }
+
+    fn try_wait(&mut self) -> Result<Option<u32>, Error> {
+        wait_process(&self.hprocess, Duration::ZERO)
+    }
+
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<u32>, Error> {
+        wait_process(&self.hprocess, timeout)
+    }
+}
+
+impl ProcessSpawner for Win32Platform {
+    type Process = Win32Process;
+
+    fn find_processes(&self, query: &ProcessQuery) -> Result<Vec<ProcessInfo>, Error> {
+        Ok(query.filter(processes()?))
+    }
+
+    fn current_session_id(&self) -> Result<u32, Error> {
+        current_session_id()
+    }
+
+    fn is_process_running(&self, pid: u32) -> Result<bool, Error> {
+        match open_process(pid) {
+            Ok(hprocess) => {
+                wait_process(&hprocess, Duration::ZERO).map(|exit_code| exit_code.is_none())
+            }
+            // The pid no longer exists.
+            Err(Error::ProcessQuery(source))
+                if source.code == HRESULT::from(ERROR_INVALID_PARAMETER).0 =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn open_process(&self, pid: u32) -> Result<Win32Process, Error> {
+        let hprocess = open_process(pid)?;
+
+        Ok(Win32Process { pid, hprocess })
+    }
+
+    fn system_exec_path(&self, exec_name: &str) -> Result<PathBuf, Error> {
+        let env_var_name = "WINDIR";
+        let mut exec_path = PathBuf::new();
+
+        let windir = env::var(env_var_name).map_err(|_| Error::MissingEnvVar {
+            name: env_var_name.to_string(),
+        })?;
+        exec_path.push(windir);
+        exec_path.push("System32");
+        exec_path.push(exec_name);
+
+        if exec_path.exists() {
+            Ok(exec_path)
+        } else {
+            Err(Error::ExecNotFound { exec_path })
+        }
+    }
+
+    fn spawn(&self, exec_path: &Path, options: &SpawnOptions) -> Result<Win32Process, Error> {
+        let show_window = if options.hidden {
+            SW_HIDE
+        } else {
+            SW_SHOWNORMAL
+        };
+        let startup_info = STARTUPINFOW {
+            dwFlags: STARTF_PREVENTPINNING | STARTF_USESHOWWINDOW,
+            wShowWindow: show_window.0 as u16,
+            ..Default::default()
+        };
+
+        let job = if options.kill_on_tray_exit {
+            kill_on_exit_job()
+        } else {
+            None
+        };
+        let (pid, hprocess) = run_exec(exec_path, options, &startup_info, job)?;
+
+        Ok(Win32Process { pid, hprocess })
+    }
+
+    fn shell_open(&self, target: &str) -> Result<(), Error> {
+        let result = unsafe {
+            ShellExecuteW(
+                HWND::default(),
+                &HSTRING::from("open"),
+                &HSTRING::from(target),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                SW_SHOWNORMAL,
+            )
+        };
+        // Values up to 32 are error codes, anything above is a fake instance handle.
+        if result.0 > 32 {
+            Ok(())
+        } else {
+            Err(Error::ShellOpen {
+                target: target.to_string(),
+                source: OsError::last_win32("ShellExecuteW"),
+            })
+        }
+    }
+}
+
+impl WindowFinder for Win32Platform {
+    fn find_window(&self, matcher: &WindowMatcher) -> Result<WindowInfo, Error> {
+        matcher
+            .find(windows()?)
+            .ok_or_else(|| Error::WindowNotFound {
+                matcher: matcher.clone(),
+            })
+    }
+
+    fn is_window(&self, hwnd: WindowHandle) -> bool {
+        unsafe { IsWindow(HWND(hwnd.0)).as_bool() }
+    }
+
+    fn watch_windows(&self, pid: Option<u32>) -> Result<WindowWatch, Error> {
+        WindowWatch::start(|sender| WindowEventHook::start(pid, sender))
+    }
+}
+
+impl WindowManager for Win32Platform {
+    fn is_visible(&self, hwnd: WindowHandle) -> bool {
+        unsafe { IsWindowVisible(HWND(hwnd.0)).as_bool() }
+    }
+
+    fn show(&self, hwnd: WindowHandle) {
+        unsafe { ShowWindow(HWND(hwnd.0), SW_SHOW) };
+    }
+
+    fn hide(&self, hwnd: WindowHandle) {
+        unsafe { ShowWindow(HWND(hwnd.0), SW_HIDE) };
+    }
+
+    fn set_foreground(&self, hwnd: WindowHandle) {
+        unsafe { SetForegroundWindow(HWND(hwnd.0)) };
+    }
+
+    fn foreground_window(&self) -> Option<WindowHandle> {
+        let hwnd = unsafe { GetForegroundWindow() };
+        if hwnd.0 == 0 {
+            return None;
+        }
+
+        // Dialogs and popups of the mixer count as the mixer itself.
+        let owner = unsafe { GetAncestor(hwnd, GA_ROOTOWNER) };
+        Some(WindowHandle(if owner.0 == 0 { hwnd.0 } else { owner.0 }))
+    }
+
+    fn window_rect(&self, hwnd: WindowHandle) -> Result<Rect, Error> {
+        let mut window_rect = RECT::default();
+        let get_rect_result = unsafe { GetWindowRect(HWND(hwnd.0), &mut window_rect) };
+        if let Err(err) = get_rect_result.ok() {
+            Err(Error::Window(OsError::from_win32("GetWindowRect", err)))
+        } else {
+            Ok(Rect::from(window_rect))
+        }
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, Error> {
+        let mut monitors: Vec<Monitor> = Vec::new();
+        let enum_result = unsafe {
+            EnumDisplayMonitors(
+                HDC::default(),
+                None,
+                Some(enum_monitors_proc),
+                LPARAM(&mut monitors as *mut Vec<Monitor> as isize),
+            )
+        };
+        if let Err(err) = enum_result.ok() {
+            Err(Error::Window(OsError::from_win32(
+                "EnumDisplayMonitors",
+                err,
+            )))
+        } else {
+            Ok(monitors)
+        }
+    }
+
+    fn close(&self, hwnd: WindowHandle) -> Result<(), Error> {
+        let post_result = unsafe { PostMessageW(HWND(hwnd.0), WM_CLOSE, WPARAM(0), LPARAM(0)) };
+        if let Err(err) = post_result.ok() {
+            Err(Error::Window(OsError::from_win32("PostMessageW", err)))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn move_window(&self, hwnd: WindowHandle, rect: Rect) -> Result<(), Error> {
+        let move_result = unsafe {
+            MoveWindow(
+                HWND(hwnd.0),
+                rect.left,
+                rect.top,
+                rect.width(),
+                rect.height(),
+                FALSE,
+            )
+        };
+        if let Err(err) = move_result.ok() {
+            Err(Error::Window(OsError::from_win32("MoveWindow", err)))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn cursor_pos(&self) -> Result<Point, Error> {
+        let mut cursor = POINT::default();
+        if let Err(err) = unsafe { GetCursorPos(&mut cursor) }.ok() {
+            Err(Error::Window(OsError::from_win32("GetCursorPos", err)))
+        } else {
+            Ok(Point {
+                x: cursor.x,
+                y: cursor.y,
+            })
+        }
+    }
+}
+
+unsafe extern "system" fn enum_monitors_proc(
+    hmonitor: HMONITOR,
+    _hdc: HDC,
+    _rect: *mut RECT,
+    lparam: LPARAM,
+) -> BOOL {
+    let monitors = &mut *(lparam.0 as *mut Vec<Monitor>);
+
+    let mut info = MONITORINFO {
+        cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+        ..Default::default()
+    };
+    if GetMonitorInfoW(hmonitor, &mut info).as_bool() {
+        let (mut dpi_x, mut dpi_y) = (0, 0);
+        let dpi = match GetDpiForMonitor(hmonitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) {
+            Ok(()) => Dpi(dpi_x),
+            Err(err) => {
+                warn!(
+                    "Assume default DPI for monitor: {}",
+                    OsError::from_win32("GetDpiForMonitor", err)
+                );
+                Dpi::DEFAULT
+            }
+        };
+
+        monitors.push(Monitor {
+            bounds: Rect::from(info.rcMonitor),
+            work_area: Rect::from(info.rcWork),
+            primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+            dpi,
+        });
+    } else {
+        warn!("Skip monitor: {}", OsError::last_win32("GetMonitorInfoW"));
+    }
+
+    TRUE
+}
+
+impl From<RECT> for Rect {
+    fn from(rect: RECT) -> Self {
+        Rect::new(rect.left, rect.top, rect.right, rect.bottom)
+    }
+}
+
+/// Single-instance lock and forwarding to the message-only window of the
+/// running tray.
+pub struct Win32InstanceChannel {
+    window_class_name: &'static str,
+    mutex: Option<WindowsHandle>,
+    /// Receives replies of the running tray, created on the first forward.
+    reply_window: Option<MessageOnlyWindow>,
+}
+
+impl Win32InstanceChannel {
+    pub fn new(window_class_name: &'static str) -> Win32InstanceChannel {
+        Win32InstanceChannel {
+            window_class_name,
+            mutex: None,
+            reply_window: None,
+        }
+    }
+}
+
+impl InstanceChannel for Win32InstanceChannel {
+    fn try_lock(&mut self) -> Result<bool, Error> {
+        let mutex = unsafe { CreateMutexW(None, FALSE, &HSTRING::from(INSTANCE_MUTEX_NAME)) }
+            .map(WindowsHandle::from_raw_handle)
+            .map_err(|err| Error::InstanceLock(OsError::from_win32("CreateMutexW", err)))?;
+
+        // The handle of an existing mutex is closed again right away.
+        if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+            Ok(false)
+        } else {
+            self.mutex = Some(mutex);
+            Ok(true)
+        }
+    }
+
+    fn forward(&mut self, command: InstanceCommand) -> Result<Option<String>, Error> {
+        if self.reply_window.is_none() {
+            self.reply_window = Some(MessageOnlyWindow::new(
+                REPLY_WINDOW_CLASS_NAME,
+                &WNDPROC::Some(reply_wnd_proc),
+            )?);
+        }
+        let reply_hwnd = self
+            .reply_window
+            .as_ref()
+            .map(|window| window.hwnd)
+            .unwrap_or_default();
+
+        let hwnd = unsafe {
+            FindWindowExW(
+                HWND_MESSAGE,
+                HWND::default(),
+                &HSTRING::from(self.window_class_name),
+                PCWSTR::null(),
+            )
+        };
+        if hwnd == HWND::default() {
+            return Err(Error::InstanceForward(OsError::last_win32("FindWindowExW")));
+        }
+
+        // Let the running tray bring the mixer to the front.
+        unsafe { AllowSetForegroundWindow(ASFW_ANY) };
+        INSTANCE_REPLY.with(|reply| reply.take());
+        // The reply arrives while waiting for the command to be taken.
+        let handled = send_copy_data(
+            hwnd,
+            reply_hwnd,
+            COPYDATA_INSTANCE_COMMAND,
+            &command.encode(),
+        )
+        .map_err(Error::InstanceForward)?;
+        if !handled {
+            return Err(Error::TrayBusy {
+                command: command.name().to_string(),
+            });
+        }
+
+        Ok(INSTANCE_REPLY.with(|reply| reply.take()))
+    }
+}
+
+/// Send `payload` tagged with `id` to `hwnd` with `WM_COPYDATA`, `sender`
+/// being the window to reply to. `false` if the receiver did not take it.
+fn send_copy_data(hwnd: HWND, sender: HWND, id: usize, payload: &[u8]) -> Result<bool, OsError> {
+    let data = COPYDATASTRUCT {
+        dwData: id,
+        cbData: payload.len() as u32,
+        lpData: payload.as_ptr() as *mut std::ffi::c_void,
+    };
+    let mut handled = 0;
+    let result = unsafe {
+        SendMessageTimeoutW(
+            hwnd,
+            WM_COPYDATA,
+            WPARAM(sender.0 as usize),
+            LPARAM(&data as *const COPYDATASTRUCT as isize),
+            SMTO_ABORTIFHUNG,
+            FORWARD_TIMEOUT.as_millis() as u32,
+            Some(&mut handled),
+        )
+    };
+    if result.0 == 0 {
+        Err(OsError::last_win32("SendMessageTimeoutW"))
+    } else {
+        Ok(handled != 0)
+    }
+}
+
+/// Payload of a `WM_COPYDATA` message if it is tagged with `id`.
+unsafe fn copy_data<'a>(lparam: LPARAM, id: usize) -> Option<&'a [u8]> {
+    (lparam.0 as *const COPYDATASTRUCT)
+        .as_ref()
+        .filter(|data| data.dwData == id && !data.lpData.is_null())
+        .map(|data| std::slice::from_raw_parts(data.lpData as *const u8, data.cbData as usize))
+}
+
+/// Named pipe the control API is served on.
+pub struct Win32ControlListener {
+    pipe_name: String,
+    /// Pipe instance waiting for the next client. Clients that connect while
+    /// another one is served find it busy instead of missing.
+    pending: File,
+}
+
+impl Win32ControlListener {
+    pub fn bind(pipe_name: String) -> Result<Win32ControlListener, Error> {
+        let pending = create_pipe_instance(&pipe_name, true).map_err(Error::ControlServer)?;
+
+        Ok(Win32ControlListener { pipe_name, pending })
+    }
+}
+
+impl ControlListener for Win32ControlListener {
+    type Stream = File;
+
+    fn accept(&mut self) -> io::Result<File> {
+        let connected =
+            unsafe { ConnectNamedPipe(HANDLE(self.pending.as_raw_handle() as isize), None) };
+        // A client that connected before the call is reported as an error.
+        if !connected.as_bool() && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let next = create_pipe_instance(&self.pipe_name, false)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(std::mem::replace(&mut self.pending, next))
+    }
+}
+
+/// Create an instance of the control pipe. The first one refuses a pipe
+/// another process already created, so no one can listen in its place.
+fn create_pipe_instance(pipe_name: &str, first: bool) -> Result<File, OsError> {
+    let mut open_mode = PIPE_ACCESS_DUPLEX;
+    if first {
+        open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+    }
+    let handle = unsafe {
+        CreateNamedPipeW(
+            &HSTRING::from(pipe_name),
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            CONTROL_PIPE_BUFFER_SIZE,
+            CONTROL_PIPE_BUFFER_SIZE,
+            0,
+            None,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        Err(OsError::last_win32("CreateNamedPipeW"))
+    } else {
+        Ok(unsafe { File::from_raw_handle(handle.0 as _) })
+    }
+}
+
+pub struct Win32TrayShell {
+    notif_data: NOTIFYICONDATAA,
+    foreground_hook: Option<HWINEVENTHOOK>,
+}
+
+impl Win32TrayShell {
+    pub fn new(hwnd: HWND) -> Win32TrayShell {
+        let mut notif_data = NOTIFYICONDATAA::default();
+
+        notif_data.cbSize = std::mem::size_of_val(&notif_data) as u32;
+        notif_data.hWnd = hwnd;
+        notif_data.uFlags = NIF_TIP | NIF_ICON | NIF_MESSAGE;
+        notif_data.uCallbackMessage = TRAY_ICON_MSG_ID;
+
+        Win32TrayShell {
+            notif_data,
+            foreground_hook: None,
+        }
+    }
+
+    fn construct_tip_msg_buf(tip_msg: &str) -> [u8; 128] {
+        let mut array: [u8; 128] = [0; 128];
+        array[..tip_msg.len()].copy_from_slice(tip_msg.as_bytes());
+
+        array
+    }
+
+    fn fill_menu(hmenu: HMENU, menu: &Menu) -> Result<(), Error> {
+        for item in &menu.items {
+            let append_result = match item {
+                MenuItem::Command {
+                    command,
+                    label,
+                    enabled,
+                    default,
+                } => {
+                    let flags = if *enabled {
+                        MF_STRING
+                    } else {
+                        MF_STRING | MF_GRAYED
+                    };
+                    let append_result = unsafe {
+                        AppendMenuW(hmenu, flags, command.id() as usize, &HSTRING::from(label))
+                    };
+                    if append_result.as_bool() && *default {
+                        unsafe { SetMenuDefaultItem(hmenu, command.id() as u32, 0) };
+                    }
+
+                    append_result
+                }
+                MenuItem::Separator => unsafe {
+                    AppendMenuW(hmenu, MF_SEPARATOR, 0, PCWSTR::null())
+                },
+            };
+            if let Err(err) = append_result.ok() {
+                return Err(Error::Menu(OsError::from_win32("AppendMenuW", err)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Show the menu and wait for a choice. The chosen command is posted as
+    /// `WM_COMMAND` instead of being handled here, because the caller is still
+    /// inside the event handler.
+    fn track_menu(&self, hmenu: HMENU) -> Result<(), Error> {
+        let hwnd = self.notif_data.hWnd;
+
+        let mut cursor = POINT::default();
+        if let Err(err) = unsafe { GetCursorPos(&mut cursor) }.ok() {
+            return Err(Error::Menu(OsError::from_win32("GetCursorPos", err)));
+        }
+
+        // Without being the foreground window the menu does not close when
+        // the user clicks elsewhere.
+        unsafe { SetForegroundWindow(hwnd) };
+        let command_id = unsafe {
+            TrackPopupMenu(
+                hmenu,
+                TPM_RETURNCMD | TPM_NONOTIFY | TPM_RIGHTBUTTON,
+                cursor.x,
+                cursor.y,
+                0,
+                hwnd,
+                None,
+            )
+        };
+        unsafe { PostMessageW(hwnd, WM_NULL, WPARAM(0), LPARAM(0)) };
+
+        if command_id.0 != 0 {
+            let post_result =
+                unsafe { PostMessageW(hwnd, WM_COMMAND, WPARAM(command_id.0 as usize), LPARAM(0)) };
+            if let Err(err) = post_result.ok() {
+                return Err(Error::Menu(OsError::from_win32("PostMessageW", err)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TrayShell for Win32TrayShell {
+    fn add_icon(&mut self, tooltip: &str) -> Result<(), Error> {
+        self.notif_data.szTip = Self::construct_tip_msg_buf(tooltip);
+        self.notif_data.hIcon = unsafe { LoadIconW(HMODULE::default(), IDI_APPLICATION) }
+            .map_err(|err| Error::TrayRegistration(OsError::from_win32("LoadIconW", err)))?;
+
+        let notif_result = unsafe { Shell_NotifyIconA(NIM_ADD, &self.notif_data) };
+        if notif_result.as_bool() {
+            Ok(())
+        } else {
+            Err(Error::TrayRegistration(OsError::last_win32(
+                "Shell_NotifyIconA",
+            )))
+        }
+    }
+
+    fn remove_icon(&mut self) -> Result<(), Error> {
+        let notif_delete_result = unsafe { Shell_NotifyIconA(NIM_DELETE, &self.notif_data) };
+        if notif_delete_result.as_bool() {
+            Ok(())
+        } else {
+            Err(Error::TrayRegistration(OsError::last_win32(
+                "Shell_NotifyIconA",
+            )))
+        }
+    }
+
+    fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
+        self.notif_data.szTip = Self::construct_tip_msg_buf(tooltip);
+
+        let notif_result = unsafe { Shell_NotifyIconA(NIM_MODIFY, &self.notif_data) };
+        if notif_result.as_bool() {
+            Ok(())
+        } else {
+            Err(Error::TrayRegistration(OsError::last_win32(
+                "Shell_NotifyIconA",
+            )))
+        }
+    }
+
+    fn icon_rect(&self) -> Result<Rect, Error> {
+        let identifier = NOTIFYICONIDENTIFIER {
+            cbSize: std::mem::size_of::<NOTIFYICONIDENTIFIER>() as u32,
+            hWnd: self.notif_data.hWnd,
+            uID: self.notif_data.uID,
+            ..Default::default()
+        };
+
+        unsafe { Shell_NotifyIconGetRect(&identifier) }
+            .map(Rect::from)
+            .map_err(|err| Error::Window(OsError::from_win32("Shell_NotifyIconGetRect", err)))
+    }
+
+    fn show_context_menu(&self, menu: &Menu) -> Result<(), Error> {
+        let hmenu = unsafe { CreatePopupMenu() }
+            .map_err(|err| Error::Menu(OsError::from_win32("CreatePopupMenu", err)))?;
+
+        let result = Self::fill_menu(hmenu, menu).and_then(|_| self.track_menu(hmenu));
+        unsafe { DestroyMenu(hmenu) };
+
+        result
+    }
+
+    fn show_message(&self, title: &str, text: &str) {
+        unsafe {
+            MessageBoxW(
+                HWND::default(),
+                &HSTRING::from(text),
+                &HSTRING::from(title),
+                MB_OK | MB_ICONINFORMATION,
+            )
+        };
+    }
+
+    fn register_hotkey(&mut self, action: HotkeyAction, hotkey: Hotkey) -> Result<(), Error> {
+        let modifiers = HOT_KEY_MODIFIERS(hotkey.modifiers) | MOD_NOREPEAT;
+        let register_result = unsafe {
+            RegisterHotKey(
+                self.notif_data.hWnd,
+                action.id(),
+                modifiers,
+                hotkey.virtual_key,
+            )
+        };
+        if let Err(err) = register_result.ok() {
+            if err.code() == HRESULT::from(ERROR_HOTKEY_ALREADY_REGISTERED) {
+                Err(Error::HotkeyConflict {
+                    hotkey: hotkey.to_string(),
+                })
+            } else {
+                Err(Error::HotkeyRegistration {
+                    hotkey: hotkey.to_string(),
+                    source: OsError::from_win32("RegisterHotKey", err),
+                })
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    fn unregister_hotkey(&mut self, action: HotkeyAction) {
+        let unregister_result = unsafe { UnregisterHotKey(self.notif_data.hWnd, action.id()) };
+        if let Err(err) = unregister_result.ok() {
+            warn!(
+                "Failed to unregister hotkey for {:?}: {}",
+                action,
+                OsError::from_win32("UnregisterHotKey", err)
+            );
+        }
+    }
+
+    /// The hook is out of context, so its callback runs on this thread
+    /// while it waits for messages and only posts a message to the window.
+    fn watch_foreground(&mut self) -> Result<(), Error> {
+        if self.foreground_hook.is_some() {
+            return Ok(());
+        }
+
+        FOREGROUND_TARGET.with(|target| target.set(self.notif_data.hWnd));
+        let hook = unsafe {
+            SetWinEventHook(
+                EVENT_SYSTEM_FOREGROUND,
+                EVENT_SYSTEM_FOREGROUND,
+                HMODULE::default(),
+                Some(foreground_event_proc),
+                0,
+                0,
+                WINEVENT_OUTOFCONTEXT,
+            )
+        };
+        if hook.is_invalid() {
+            return Err(Error::Window(OsError::last_win32("SetWinEventHook")));
+        }
+
+        self.foreground_hook = Some(hook);
+        Ok(())
+    }
+
+    fn unwatch_foreground(&mut self) {
+        if let Some(hook) = self.foreground_hook.take() {
+            if !unsafe { UnhookWinEvent(hook) }.as_bool() {
+                warn!("{}", OsError::last_win32("UnhookWinEvent"));
+            }
+        }
+    }
+
+    fn post_quit(&self) {
+        unsafe { PostQuitMessage(0) };
+    }
+}
+
+thread_local! {
+    /// Window that `foreground_event_proc` notifies.
+    static FOREGROUND_TARGET: Cell<HWND> = const { Cell::new(HWND(0)) };
+}
+
+unsafe extern "system" fn foreground_event_proc(
+    _hook: HWINEVENTHOOK,
+    _event: u32,
+    _hwnd: HWND,
+    _id_object: i32,
+    _id_child: i32,
+    _event_thread: u32,
+    _event_time: u32,
+) {
+    let target = FOREGROUND_TARGET.with(Cell::get);
+    PostMessageW(target, FOREGROUND_CHANGED_MSG_ID, WPARAM(0), LPARAM(0));
+}
+
+/// Work in physical pixels on every monitor instead of letting the system
+/// scale coordinates. Must be called before any window is created, which
+/// makes it equivalent to declaring the awareness in a manifest, without
+/// needing a resource compiler in the build.
+pub fn enable_per_monitor_dpi_awareness() -> Result<(), Error> {
+    let result =
+        unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) };
+    if let Err(err) = result.ok() {
+        Err(Error::Window(OsError::from_win32(
+            "SetProcessDpiAwarenessContext",
+            err,
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn timer_id(timer: Timer) -> usize {
+    match timer {
+        Timer::ConfigReload => 1,
+        Timer::Supervise => 2,
+    }
+}
+
+fn timer_from_id(id: usize) -> Option<Timer> {
+    match id {
+        1 => Some(Timer::ConfigReload),
+        2 => Some(Timer::Supervise),
+        _ => None,
+    }
+}
+
+/// Start a periodic `WM_TIMER` for `timer` on the given window.
+pub fn set_timer(hwnd: HWND, timer: Timer, interval: Duration) -> Result<(), Error> {
+    let result = unsafe { SetTimer(hwnd, timer_id(timer), interval.as_millis() as u32, None) };
+    if result == 0 {
+        Err(Error::Window(OsError::last_win32("SetTimer")))
+    } else {
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// Set while an event handler runs. Modal loops inside a handler (menus,
+    /// message boxes) keep dispatching messages, which must not reach the
+    /// handler again while it is borrowed.
+    static HANDLER_BUSY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Window procedure of the message-only window. Tray icon notifications,
+/// timers, foreground changes, menu commands, hotkeys and commands of other
+/// launches are forwarded to the handler stored in `PROP_EVENT_HANDLER`.
+pub unsafe extern "system" fn wnd_proc<H: EventHandler>(
+    hwnd: HWND,
+    umsg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match umsg {
+        TRAY_ICON_MSG_ID => {
+            let event = match lparam.0 as u32 {
+                WM_LBUTTONDOWN => Some(TrayEvent::LeftButtonDown),
+                WM_MBUTTONDOWN => Some(TrayEvent::MiddleButtonDown),
+                WM_RBUTTONDOWN => Some(TrayEvent::RightButtonDown),
+                _ => None,
+            };
+            if let Some(event) = event {
+                dispatch::<H>(hwnd, |handler| handler.handle_tray_event(event));
+            }
+        }
+        WM_TIMER => {
+            if let Some(timer) = timer_from_id(wparam.0) {
+                dispatch::<H>(hwnd, |handler| handler.handle_timer(timer));
+            }
+        }
+        FOREGROUND_CHANGED_MSG_ID => {
+            dispatch::<H>(hwnd, |handler| handler.handle_foreground_changed());
+        }
+        WM_HOTKEY => {
+            if let Some(action) = HotkeyAction::from_id(wparam.0 as i32) {
+                dispatch::<H>(hwnd, |handler| handler.handle_hotkey(action));
+            }
+        }
+        WM_COMMAND => {
+            let command_id = (wparam.0 & 0xFFFF) as u16;
+            if let Some(command) = MenuCommand::from_id(command_id) {
+                dispatch::<H>(hwnd, |handler| handler.handle_menu_command(command));
+            }
+        }
+        WM_COPYDATA => {
+            let Some(command) =
+                copy_data(lparam, COPYDATA_INSTANCE_COMMAND).and_then(InstanceCommand::decode)
+            else {
+                return DefWindowProcW(hwnd, umsg, wparam, lparam);
+            };
+
+            let mut reply = None;
+            let handled = dispatch::<H>(hwnd, |handler| {
+                reply = handler.handle_instance_command(command);
+            });
+            if let Some(reply) = reply {
+                let sender = HWND(wparam.0 as isize);
+                match send_copy_data(sender, hwnd, COPYDATA_INSTANCE_REPLY, reply.as_bytes()) {
+                    Ok(true) => {}
+                    Ok(false) => warn!("Reply to command \"{}\" was not taken", command.name()),
+                    Err(err) => {
+                        warn!("Could not reply to command \"{}\": {}", command.name(), err)
+                    }
+                }
+            }
+            // Tell the sender whether the command was taken.
+            return LRESULT(handled as isize);
+        }
+        _ => return DefWindowProcW(hwnd, umsg, wparam, lparam),
+    }
+
+    LRESULT::default()
+}
+
+thread_local! {
+    /// Reply of the running tray to the last forwarded command.
+    static INSTANCE_REPLY: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Window procedure of the window that receives replies to forwarded commands.
+unsafe extern "system" fn reply_wnd_proc(
+    hwnd: HWND,
+    umsg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match umsg {
+        WM_COPYDATA => match copy_data(lparam, COPYDATA_INSTANCE_REPLY) {
+            Some(payload) => {
+                let reply = String::from_utf8_lossy(payload).into_owned();
+                INSTANCE_REPLY.with(|instance_reply| instance_reply.replace(Some(reply)));
+                LRESULT(1)
+            }
+            None => DefWindowProcW(hwnd, umsg, wparam, lparam),
+        },
+        _ => DefWindowProcW(hwnd, umsg, wparam, lparam),
+    }
+}
+
+/// Run `handle` on the stored handler, `false` if it could not run.
+unsafe fn dispatch<H: EventHandler>(hwnd: HWND, handle: impl FnOnce(&mut H)) -> bool {
+    let data: HANDLE = { unsafe { GetPropW(hwnd, &HSTRING::from(PROP_EVENT_HANDLER)) } };
+
+    if data.is_invalid() {
+        error!(
+            "{}",
+            Error::PropertyStore {
+                property: PROP_EVENT_HANDLER.to_string(),
+                source: OsError::last_win32("GetPropW"),
+            }
+        );
+
+        return false;
+    }
+
+    if HANDLER_BUSY.with(|busy| busy.replace(true)) {
+        debug!("Event handler busy, drop nested message");
+        return false;
+    }
+    let handler = (data.0 as *mut H).as_mut();
+    let handled = handler.is_some();
+    if let Some(handler) = handler {
+        handle(handler);
+    }
+    HANDLER_BUSY.with(|busy| busy.set(false));
+
+    handled
+}
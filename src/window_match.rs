@@ -0,0 +1,276 @@
+//! Descriptors of top-level windows and predicates to query them, shared by
+//! the mixer finder and anything else looking for windows, independent of
+//! how the windows are enumerated.
+
+use crate::platform::{Rect, WindowHandle};
+use regex::Regex;
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Not;
+
+/// What is known about a top-level window when looking for one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub hwnd: WindowHandle,
+    /// Process that created the window.
+    pub pid: u32,
+    pub thread_id: u32,
+    /// File name of the executable of `pid`, empty if unknown.
+    pub exe_name: String,
+    pub class_name: String,
+    pub title: String,
+    pub visible: bool,
+    /// `WS_*` and `WS_EX_*` flags.
+    pub style: u32,
+    pub ex_style: u32,
+    /// In screen coordinates.
+    pub rect: Rect,
+    /// Window this one stays on top of, like the main window of a dialog.
+    pub owner: Option<WindowHandle>,
+}
+
+impl WindowInfo {
+    /// Not owned by another window, unlike dialogs and tool windows.
+    pub fn is_top_level(&self) -> bool {
+        self.owner.is_none()
+    }
+}
+
+/// Regular expression over window titles, equal to another one with the
+/// same source.
+#[derive(Clone, Debug)]
+pub struct TitleRegex(Regex);
+
+impl TitleRegex {
+    pub fn new(pattern: &str) -> Result<TitleRegex, regex::Error> {
+        Regex::new(pattern).map(TitleRegex)
+    }
+
+    pub fn is_match(&self, title: &str) -> bool {
+        self.0.is_match(title)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl PartialEq for TitleRegex {
+    fn eq(&self, other: &TitleRegex) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for TitleRegex {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WindowMatcher {
+    /// The whole class name, ignoring case like Windows does.
+    ClassName(String),
+    /// Part of the title.
+    TitleContains(String),
+    /// A match anywhere in the title, unless anchored.
+    TitleRegex(TitleRegex),
+    OwnerPid(u32),
+    /// File name of the executable, ignoring case.
+    ExeName(String),
+    TopLevel,
+    Visible,
+    /// Matches if every matcher matches, so always if there are none.
+    All(Vec<WindowMatcher>),
+    /// Matches if one matcher matches, so never if there are none.
+    Any(Vec<WindowMatcher>),
+    Not(Box<WindowMatcher>),
+}
+
+impl WindowMatcher {
+    pub fn all(matchers: impl IntoIterator<Item = WindowMatcher>) -> WindowMatcher {
+        WindowMatcher::All(matchers.into_iter().collect())
+    }
+
+    pub fn any(matchers: impl IntoIterator<Item = WindowMatcher>) -> WindowMatcher {
+        WindowMatcher::Any(matchers.into_iter().collect())
+    }
+
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        match self {
+            WindowMatcher::ClassName(class_name) => {
+                window.class_name.eq_ignore_ascii_case(class_name)
+            }
+            WindowMatcher::TitleContains(pattern) => window.title.contains(pattern.as_str()),
+            WindowMatcher::TitleRegex(regex) => regex.is_match(&window.title),
+            WindowMatcher::OwnerPid(pid) => window.pid == *pid,
+            WindowMatcher::ExeName(exe_name) => window.exe_name.eq_ignore_ascii_case(exe_name),
+            WindowMatcher::TopLevel => window.is_top_level(),
+            WindowMatcher::Visible => window.visible,
+            WindowMatcher::All(matchers) => matchers.iter().all(|matcher| matcher.matches(window)),
+            WindowMatcher::Any(matchers) => matchers.iter().any(|matcher| matcher.matches(window)),
+            WindowMatcher::Not(matcher) => !matcher.matches(window),
+        }
+    }
+
+    /// The first of `windows` that matches, taking windows or references to them.
+    pub fn find<W: Borrow<WindowInfo>>(&self, windows: impl IntoIterator<Item = W>) -> Option<W> {
+        windows
+            .into_iter()
+            .find(|window| self.matches(window.borrow()))
+    }
+
+    /// Write `self` as part of a larger expression, in parentheses if it
+    /// combines several matchers.
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowMatcher::All(matchers) | WindowMatcher::Any(matchers) if matchers.len() > 1 => {
+                write!(f, "({})", self)
+            }
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl Not for WindowMatcher {
+    type Output = WindowMatcher;
+
+    fn not(self) -> WindowMatcher {
+        WindowMatcher::Not(Box::new(self))
+    }
+}
+
+/// Describes the matcher in error messages, e.g. `pid 42 and (title
+/// containing "Volume Mixer" or title containing "Lautstärkemixer")`.
+impl fmt::Display for WindowMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (matchers, separator, empty) = match self {
+            WindowMatcher::ClassName(class_name) => return write!(f, "class \"{}\"", class_name),
+            WindowMatcher::TitleContains(pattern) => {
+                return write!(f, "title containing \"{}\"", pattern)
+            }
+            WindowMatcher::TitleRegex(regex) => {
+                return write!(f, "title matching /{}/", regex.as_str())
+            }
+            WindowMatcher::OwnerPid(pid) => return write!(f, "pid {}", pid),
+            WindowMatcher::ExeName(exe_name) => return write!(f, "exe \"{}\"", exe_name),
+            WindowMatcher::TopLevel => return write!(f, "top-level"),
+            WindowMatcher::Visible => return write!(f, "visible"),
+            WindowMatcher::Not(matcher) => {
+                write!(f, "not ")?;
+                return matcher.fmt_operand(f);
+            }
+            WindowMatcher::All(matchers) => (matchers, " and ", "any window"),
+            WindowMatcher::Any(matchers) => (matchers, " or ", "no window"),
+        };
+
+        if matchers.is_empty() {
+            return write!(f, "{}", empty);
+        }
+        for (i, matcher) in matchers.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", separator)?;
+            }
+            matcher.fmt_operand(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(pid: u32, class_name: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            hwnd: WindowHandle(pid as isize),
+            pid,
+            exe_name: "SndVol.exe".to_string(),
+            class_name: class_name.to_string(),
+            title: title.to_string(),
+            ..WindowInfo::default()
+        }
+    }
+
+    #[test]
+    fn matches_single_criteria() {
+        let mixer = window(42, "#32770", "Lautstärkemixer - Lautsprecher");
+
+        assert!(WindowMatcher::ClassName("#32770".to_string()).matches(&mixer));
+        assert!(WindowMatcher::TitleContains("Lautstärkemixer".to_string()).matches(&mixer));
+        assert!(!WindowMatcher::TitleContains("Volume Mixer".to_string()).matches(&mixer));
+        assert!(WindowMatcher::OwnerPid(42).matches(&mixer));
+        assert!(WindowMatcher::ExeName("sndvol.EXE".to_string()).matches(&mixer));
+        assert!(!WindowMatcher::ExeName("SndVol".to_string()).matches(&mixer));
+        assert!(WindowMatcher::TopLevel.matches(&mixer));
+        assert!(!WindowMatcher::Visible.matches(&mixer));
+    }
+
+    #[test]
+    fn matches_title_regex() {
+        let mixer = window(42, "#32770", "Volume Mixer - Speakers (Realtek(R) Audio)");
+        let regex = |pattern: &str| WindowMatcher::TitleRegex(TitleRegex::new(pattern).unwrap());
+
+        assert!(regex("Speakers").matches(&mixer));
+        assert!(regex(r"^Volume Mixer - .+\(.*\)$").matches(&mixer));
+        assert!(!regex("^Speakers").matches(&mixer));
+        assert_eq!(regex("^Mixer$"), regex("^Mixer$"));
+        assert!(TitleRegex::new("Mixer (").is_err());
+    }
+
+    #[test]
+    fn combinators() {
+        let mixer = window(42, "#32770", "Mikser głośności");
+        let title = |pattern: &str| WindowMatcher::TitleContains(pattern.to_string());
+
+        assert!(WindowMatcher::all([]).matches(&mixer));
+        assert!(!WindowMatcher::any([]).matches(&mixer));
+        assert!(WindowMatcher::all([
+            WindowMatcher::OwnerPid(42),
+            WindowMatcher::any([title("Volume Mixer"), title("Mikser głośności")]),
+        ])
+        .matches(&mixer));
+        assert!(
+            !WindowMatcher::all([WindowMatcher::OwnerPid(42), !WindowMatcher::TopLevel])
+                .matches(&mixer)
+        );
+        assert!((!WindowMatcher::Visible).matches(&mixer));
+    }
+
+    #[test]
+    fn finds_first_matching_window() {
+        let windows = [
+            window(7, "#32770", "Volume Mixer"),
+            WindowInfo {
+                owner: Some(WindowHandle(7)),
+                ..window(42, "#32770", "Volume Mixer")
+            },
+            window(42, "IME", "Default IME"),
+            window(42, "#32770", "Volume Mixer - Speakers"),
+        ];
+        let matcher = WindowMatcher::all([
+            WindowMatcher::OwnerPid(42),
+            WindowMatcher::ClassName("#32770".to_string()),
+            WindowMatcher::TopLevel,
+        ]);
+
+        assert_eq!(matcher.find(&windows), Some(&windows[3]));
+        assert_eq!(WindowMatcher::OwnerPid(9).find(&windows), None);
+    }
+
+    #[test]
+    fn describes_matcher() {
+        let matcher = WindowMatcher::all([
+            WindowMatcher::OwnerPid(42),
+            WindowMatcher::any([
+                WindowMatcher::TitleContains("Volume Mixer".to_string()),
+                WindowMatcher::TitleContains("Lautstärkemixer".to_string()),
+            ]),
+            !WindowMatcher::all([WindowMatcher::Visible, WindowMatcher::TopLevel]),
+        ]);
+
+        assert_eq!(
+            matcher.to_string(),
+            "pid 42 and (title containing \"Volume Mixer\" or title containing \
+             \"Lautstärkemixer\") and not (visible and top-level)"
+        );
+        assert_eq!(WindowMatcher::any([]).to_string(), "no window");
+    }
+}
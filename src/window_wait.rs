@@ -0,0 +1,217 @@
+//! Waiting for a window to appear, woken by the window events of the platform
+//! instead of polling for it.
+
+use crate::error::Error;
+use crate::platform::WindowFinder;
+use crate::window_match::{WindowInfo, WindowMatcher};
+use std::any::Any;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+/// Why a wait for a window wakes up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowSignal {
+    /// A window was created, shown or renamed, it may be the awaited one.
+    Changed,
+    /// The awaited window will not appear anymore, e.g. its process exited.
+    Cancelled,
+}
+
+/// Signals of a platform source of window events, like a hook, for as long as
+/// the watch lives.
+pub struct WindowWatch {
+    // Keeps the channel open when the source does not hold on to its sender.
+    _sender: Sender<WindowSignal>,
+    signals: Receiver<WindowSignal>,
+    _source: Box<dyn Any>,
+}
+
+impl WindowWatch {
+    /// Start a source with the sender of its signals. The source is dropped
+    /// with the watch, which should stop it.
+    pub fn start<S: 'static>(
+        start: impl FnOnce(Sender<WindowSignal>) -> Result<S, Error>,
+    ) -> Result<WindowWatch, Error> {
+        let (sender, signals) = mpsc::channel();
+        let source = start(sender.clone())?;
+
+        Ok(WindowWatch {
+            _sender: sender,
+            signals,
+            _source: Box::new(source),
+        })
+    }
+
+    /// Wait at most `timeout` until `finder` finds a window `matcher` matches,
+    /// looking again whenever a window changed. Fails like `find_window` once
+    /// the time is up.
+    pub fn wait_for_window<F: WindowFinder + ?Sized>(
+        &self,
+        finder: &F,
+        matcher: &WindowMatcher,
+        timeout: Duration,
+    ) -> Result<WindowInfo, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Ok(window) = finder.find_window(matcher) {
+                return Ok(window);
+            }
+
+            let signal = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) => self.signals.recv_timeout(remaining),
+                None => Err(RecvTimeoutError::Timeout),
+            };
+            let cancelled = match signal {
+                // One look covers every window that changed meanwhile.
+                Ok(WindowSignal::Changed) => self
+                    .signals
+                    .try_iter()
+                    .any(|signal| signal == WindowSignal::Cancelled),
+                Ok(WindowSignal::Cancelled) => true,
+                Err(_) => return finder.find_window(matcher),
+            };
+            if cancelled {
+                return Err(Error::WindowWaitCancelled {
+                    matcher: matcher.clone(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::WindowHandle;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Windows that another thread can add to while a test waits.
+    #[derive(Clone, Default)]
+    struct SharedWindows(Arc<Mutex<Vec<WindowInfo>>>);
+
+    impl SharedWindows {
+        fn add(&self, pid: u32, title: &str) -> WindowHandle {
+            let mut windows = self.0.lock().unwrap();
+            let hwnd = WindowHandle(windows.len() as isize + 1);
+            windows.push(WindowInfo {
+                hwnd,
+                pid,
+                title: title.to_string(),
+                ..WindowInfo::default()
+            });
+            hwnd
+        }
+    }
+
+    impl WindowFinder for SharedWindows {
+        fn find_window(&self, matcher: &WindowMatcher) -> Result<WindowInfo, Error> {
+            matcher
+                .find(self.0.lock().unwrap().iter().cloned())
+                .ok_or_else(|| Error::WindowNotFound {
+                    matcher: matcher.clone(),
+                })
+        }
+
+        fn is_window(&self, hwnd: WindowHandle) -> bool {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|window| window.hwnd == hwnd)
+        }
+
+        fn watch_windows(&self, _pid: Option<u32>) -> Result<WindowWatch, Error> {
+            WindowWatch::start(|_| Ok(()))
+        }
+    }
+
+    /// A watch and the sender of its signals, playing the platform.
+    fn fake_watch() -> (WindowWatch, Sender<WindowSignal>) {
+        let mut events = None;
+        let watch = WindowWatch::start(|sender| {
+            events = Some(sender);
+            Ok(())
+        })
+        .unwrap();
+        (watch, events.unwrap())
+    }
+
+    fn mixer() -> WindowMatcher {
+        WindowMatcher::all([
+            WindowMatcher::OwnerPid(42),
+            WindowMatcher::TitleContains("Volume Mixer".to_string()),
+        ])
+    }
+
+    #[test]
+    fn returns_existing_window_right_away() {
+        let windows = SharedWindows::default();
+        let hwnd = windows.add(42, "Volume Mixer");
+        let (watch, _events) = fake_watch();
+
+        let window = watch
+            .wait_for_window(&windows, &mixer(), Duration::ZERO)
+            .unwrap();
+        assert_eq!(window.hwnd, hwnd);
+    }
+
+    #[test]
+    fn wakes_up_when_window_appears() {
+        let windows = SharedWindows::default();
+        let (watch, events) = fake_watch();
+
+        let creator = {
+            let windows = windows.clone();
+            thread::spawn(move || {
+                windows.add(7, "Volume Mixer");
+                events.send(WindowSignal::Changed).unwrap();
+                thread::sleep(Duration::from_millis(50));
+                let hwnd = windows.add(42, "Volume Mixer");
+                events.send(WindowSignal::Changed).unwrap();
+                hwnd
+            })
+        };
+
+        let started = Instant::now();
+        let window = watch
+            .wait_for_window(&windows, &mixer(), Duration::from_secs(30))
+            .unwrap();
+        assert_eq!(window.hwnd, creator.join().unwrap());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn times_out_without_matching_window() {
+        let windows = SharedWindows::default();
+        windows.add(42, "Default IME");
+        let (watch, events) = fake_watch();
+        events.send(WindowSignal::Changed).unwrap();
+
+        let started = Instant::now();
+        let err = watch
+            .wait_for_window(&windows, &mixer(), Duration::from_millis(100))
+            .unwrap_err();
+        assert!(matches!(err, Error::WindowNotFound { .. }));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let windows = SharedWindows::default();
+        let (watch, events) = fake_watch();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            events.send(WindowSignal::Changed).unwrap();
+            events.send(WindowSignal::Cancelled).unwrap();
+        });
+
+        let started = Instant::now();
+        let err = watch
+            .wait_for_window(&windows, &mixer(), Duration::from_secs(30))
+            .unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!(err, Error::WindowWaitCancelled { matcher: mixer() });
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
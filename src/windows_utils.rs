@@ -1,21 +1,52 @@
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
-use std::os::windows::ffi::OsStrExt;
-use std::path::Path;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::ffi::c_void;
+use std::ffi::OsString;
+use std::mem::size_of;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::OnceLock;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-use windows::core::{Error, PCWSTR, PWSTR};
+use crate::error::{Error, OsError};
+use crate::platform::{Rect, SpawnOptions, WindowHandle};
+use crate::process_query::ProcessInfo;
+use crate::window_match::WindowInfo;
+use crate::window_wait::WindowSignal;
+use windows::core::{PCWSTR, PWSTR};
 use windows::Win32::Foundation::{
-    CloseHandle, SetLastError, BOOL, ERROR_NO_MORE_FILES, ERROR_SUCCESS, FALSE, HANDLE, HWND,
-    INVALID_HANDLE_VALUE, LPARAM, TRUE, WIN32_ERROR,
+    CloseHandle, BOOL, ERROR_NO_MORE_FILES, FALSE, HANDLE, HMODULE, HWND, INVALID_HANDLE_VALUE,
+    LPARAM, RECT, TRUE, WAIT_OBJECT_0, WAIT_TIMEOUT, WIN32_ERROR, WPARAM,
 };
+use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
 use windows::Win32::System::Diagnostics::ToolHelp::{
     CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
 };
+use windows::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+    SetInformationJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+use windows::Win32::System::RemoteDesktop::ProcessIdToSessionId;
 use windows::Win32::System::Threading::{
-    CreateProcessW, PROCESS_CREATION_FLAGS, PROCESS_INFORMATION, STARTUPINFOW,
+    CreateProcessW, GetCurrentProcessId, GetCurrentThreadId, GetExitCodeProcess, OpenProcess,
+    QueryFullProcessImageNameW, ResumeThread, WaitForSingleObject, CREATE_SUSPENDED,
+    CREATE_UNICODE_ENVIRONMENT, INFINITE, PROCESS_CREATION_FLAGS, PROCESS_INFORMATION,
+    PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE, PROCESS_TERMINATE,
+    STARTUPINFOW,
 };
+use windows::Win32::UI::Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK};
 use windows::Win32::UI::WindowsAndMessaging::{
-    EnumWindows, GetWindowTextA, GetWindowThreadProcessId, WNDENUMPROC,
+    EnumWindows, GetClassNameW, GetWindow, GetWindowLongW, GetWindowRect, GetWindowTextLengthW,
+    GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible, MsgWaitForMultipleObjects,
+    PeekMessageW, PostThreadMessageW, CHILDID_SELF, EVENT_OBJECT_CREATE, EVENT_OBJECT_NAMECHANGE,
+    EVENT_OBJECT_SHOW, GWL_EXSTYLE, GWL_STYLE, GW_OWNER, MSG, OBJID_WINDOW, PM_NOREMOVE, PM_REMOVE,
+    QS_ALLINPUT, WINEVENT_OUTOFCONTEXT, WINEVENT_SKIPOWNPROCESS, WM_QUIT, WNDENUMPROC,
 };
 
 pub struct WindowsHandle {
@@ -26,7 +57,7 @@ impl WindowsHandle {
     const NULL: HANDLE = HANDLE(0);
 
     pub fn is_valid(&self) -> bool {
-        self.handle == INVALID_HANDLE_VALUE || self.handle == WindowsHandle::NULL
+        self.handle != INVALID_HANDLE_VALUE && self.handle != WindowsHandle::NULL
     }
 
     pub fn from_raw_handle(handle: HANDLE) -> Self {
@@ -49,34 +80,91 @@ impl Default for WindowsHandle {
 impl Drop for WindowsHandle {
     fn drop(&mut self) {
         if self.is_valid() {
-            unsafe {
-                CloseHandle(self.handle).ok().unwrap();
+            if let Err(err) = unsafe { CloseHandle(self.handle) }.ok() {
+                warn!("CloseHandle failed: {}", err);
             }
         }
     }
 }
 
-pub trait ExtendPCWSTR {
-    fn from_str(str: &str) -> PCWSTR;
+/// All running processes, with as much information as the tray may query.
+pub fn processes() -> Result<Vec<ProcessInfo>, Error> {
+    let mut processes = Vec::new();
+    walk_processes(|proc_entry| {
+        let pid = proc_entry.th32ProcessID;
+        let mut session_id = 0;
+        let session_id = unsafe { ProcessIdToSessionId(pid, &mut session_id) }
+            .as_bool()
+            .then_some(session_id);
+        processes.push(ProcessInfo {
+            pid,
+            parent_pid: proc_entry.th32ParentProcessID,
+            exe_name: exe_file_name(proc_entry),
+            exe_path: process_exe_path(pid),
+            thread_count: proc_entry.cntThreads,
+            session_id,
+        });
+        true
+    })?;
+
+    Ok(processes)
 }
 
-impl ExtendPCWSTR for PCWSTR {
-    fn from_str(str: &str) -> PCWSTR {
-        PCWSTR::from_raw(
-            str.encode_utf16()
-                .chain(Some(0))
-                .collect::<Vec<u16>>()
-                .as_ptr(),
+/// Full path of the executable of `pid`, `None` if the process cannot be opened.
+fn process_exe_path(pid: u32) -> Option<PathBuf> {
+    let hprocess = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) }
+        .ok()
+        .map(WindowsHandle::from_raw_handle)?;
+
+    // Room for paths beyond MAX_PATH, when long paths are enabled.
+    let mut path_buf = [0u16; 1024];
+    let mut len = path_buf.len() as u32;
+    let result = unsafe {
+        QueryFullProcessImageNameW(
+            hprocess.as_raw_handle(),
+            PROCESS_NAME_WIN32,
+            PWSTR(path_buf.as_mut_ptr()),
+            &mut len,
         )
+    };
+
+    result
+        .as_bool()
+        .then(|| PathBuf::from(OsString::from_wide(&path_buf[..len as usize])))
+}
+
+/// Session the tray runs in.
+pub fn current_session_id() -> Result<u32, Error> {
+    let mut session_id = 0;
+    let result = unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session_id) };
+    if let Err(err) = result.ok() {
+        return Err(Error::ProcessQuery(OsError::from_win32(
+            "ProcessIdToSessionId",
+            err,
+        )));
     }
+
+    Ok(session_id)
 }
 
-pub fn get_pid_by_name(proc_name: &str) -> Result<Option<u32>, String> {
-    let mut result_pid: Option<u32> = None;
+/// Executable file names of all processes, by pid.
+fn process_exe_names() -> Result<HashMap<u32, String>, Error> {
+    let mut exe_names = HashMap::new();
+    walk_processes(|proc_entry| {
+        exe_names.insert(proc_entry.th32ProcessID, exe_file_name(proc_entry));
+        true
+    })?;
+
+    Ok(exe_names)
+}
 
+/// Call `visit` with every running process until it returns `false`.
+fn walk_processes(mut visit: impl FnMut(&PROCESSENTRY32W) -> bool) -> Result<(), Error> {
     unsafe {
         let snapshot_handle = {
-            let raw_handle = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0).unwrap();
+            let raw_handle = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0).map_err(|err| {
+                Error::ProcessQuery(OsError::from_win32("CreateToolhelp32Snapshot", err))
+            })?;
             WindowsHandle::from_raw_handle(raw_handle)
         };
 
@@ -85,135 +173,462 @@ pub fn get_pid_by_name(proc_name: &str) -> Result<Option<u32>, String> {
 
         if Process32FirstW(snapshot_handle.as_raw_handle(), &mut proc_entry).as_bool() {
             loop {
-                let curr_proc_name = String::from_utf16(proc_entry.szExeFile.as_slice()).unwrap();
-                if proc_name == curr_proc_name.trim_end_matches('\0') {
-                    result_pid = Some(proc_entry.th32ProcessID);
-                    break;
+                if !visit(&proc_entry) {
+                    return Ok(());
                 }
                 if !Process32NextW(snapshot_handle.as_raw_handle(), &mut proc_entry).as_bool() {
-                    let error = Error::from_win32();
-                    if let Some(win32_error) = WIN32_ERROR::from_error(&error) {
-                        if win32_error == ERROR_NO_MORE_FILES {
-                            break;
-                        } else {
-                            return Err(format!("Process32NextW failed: {}", error));
-                        }
+                    let error = windows::core::Error::from_win32();
+                    if WIN32_ERROR::from_error(&error) == Some(ERROR_NO_MORE_FILES) {
+                        return Ok(());
+                    } else {
+                        return Err(Error::ProcessQuery(OsError::from_win32(
+                            "Process32NextW",
+                            error,
+                        )));
                     }
                 }
             }
         } else {
-            return Err(format!("Process32FirstW failed: {}", Error::from_win32()));
+            Err(Error::ProcessQuery(OsError::last_win32("Process32FirstW")))
         }
     }
+}
 
-    Ok(result_pid)
+fn exe_file_name(proc_entry: &PROCESSENTRY32W) -> String {
+    let exe_file = &proc_entry.szExeFile;
+    let len = exe_file
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(exe_file.len());
+    String::from_utf16_lossy(&exe_file[..len])
 }
 
-#[derive(Default)]
-struct EnumProcUserData {
-    looking_pid: u32,
-    title_pattern: String,
-    found_hwnd: Option<HWND>,
+/// Descriptors of the top-level windows, front to back. Each window is read
+/// when the iterator gets to it, windows closed by then are skipped.
+pub struct Windows {
+    hwnds: std::vec::IntoIter<HWND>,
+    exe_names: HashMap<u32, String>,
 }
 
-unsafe extern "system" fn enum_windows_proc(curr_hwnd: HWND, lparam: LPARAM) -> BOOL {
-    let mut user_data = (lparam.0 as *mut EnumProcUserData).as_mut().unwrap();
-    let mut curr_hwnd_pid: u32 = 0;
+impl Iterator for Windows {
+    type Item = WindowInfo;
 
-    unsafe {
-        let result = GetWindowThreadProcessId(curr_hwnd, Some(&mut curr_hwnd_pid));
-        if result == 0 {
-            warn!(
-                "Failed to call GetWindowThreadProcessId. Windows error: {}",
-                Error::from_win32()
-            );
+    fn next(&mut self) -> Option<WindowInfo> {
+        self.hwnds
+            .by_ref()
+            .find_map(|hwnd| window_info(hwnd, &self.exe_names))
+    }
+}
 
-            return TRUE;
+unsafe extern "system" fn collect_hwnds_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    match (lparam.0 as *mut Vec<HWND>).as_mut() {
+        Some(hwnds) => {
+            hwnds.push(hwnd);
+            TRUE
         }
+        None => FALSE,
     }
+}
+
+pub fn windows() -> Result<Windows, Error> {
+    let mut hwnds: Vec<HWND> = Vec::new();
+    let lparam = LPARAM(&mut hwnds as *mut Vec<HWND> as isize);
 
-    let mut window_text_buf: [u8; 256] = [0; 256];
-    let ret_buf_len = GetWindowTextA(curr_hwnd, window_text_buf.as_mut());
-    if ret_buf_len == 0 {
-        warn!("Failed to get window text: {}", Error::from_win32());
+    let enum_result = unsafe { EnumWindows(WNDENUMPROC::Some(collect_hwnds_proc), lparam) };
+    if let Err(err) = enum_result.ok() {
+        return Err(Error::Window(OsError::from_win32("EnumWindows", err)));
     }
-    let curr_window_title = std::str::from_utf8(window_text_buf.as_slice()).unwrap_or("<unknown>");
+    let exe_names = process_exe_names().unwrap_or_else(|err| {
+        warn!("Executables of windows are unknown: {}", err);
+        HashMap::new()
+    });
 
-    if curr_hwnd_pid == user_data.looking_pid
-        && curr_window_title.contains(user_data.title_pattern.as_str())
-    {
-        user_data.found_hwnd = Some(curr_hwnd);
-        SetLastError(ERROR_SUCCESS);
-        FALSE
-    } else {
-        TRUE
+    Ok(Windows {
+        hwnds: hwnds.into_iter(),
+        exe_names,
+    })
+}
+
+/// What is known about `hwnd`, `None` if it no longer exists.
+fn window_info(hwnd: HWND, exe_names: &HashMap<u32, String>) -> Option<WindowInfo> {
+    unsafe {
+        let mut pid: u32 = 0;
+        let thread_id = GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        let mut rect = RECT::default();
+        if thread_id == 0 || !GetWindowRect(hwnd, &mut rect).as_bool() {
+            return None;
+        }
+
+        // Wide strings, the titles of other languages do not fit the ANSI code page.
+        let mut title_buf = vec![0u16; GetWindowTextLengthW(hwnd).max(0) as usize + 1];
+        let title_len = GetWindowTextW(hwnd, &mut title_buf).max(0) as usize;
+        let mut class_buf = [0u16; 257];
+        let class_len = GetClassNameW(hwnd, &mut class_buf).max(0) as usize;
+        let owner = GetWindow(hwnd, GW_OWNER);
+
+        Some(WindowInfo {
+            hwnd: WindowHandle(hwnd.0),
+            pid,
+            thread_id,
+            exe_name: exe_names.get(&pid).cloned().unwrap_or_default(),
+            class_name: String::from_utf16_lossy(&class_buf[..class_len]),
+            title: String::from_utf16_lossy(&title_buf[..title_len]),
+            visible: IsWindowVisible(hwnd).as_bool(),
+            style: GetWindowLongW(hwnd, GWL_STYLE) as u32,
+            ex_style: GetWindowLongW(hwnd, GWL_EXSTYLE) as u32,
+            rect: Rect::from(rect),
+            owner: (owner != HWND::default()).then_some(WindowHandle(owner.0)),
+        })
     }
 }
 
-pub fn find_window_by_pattern_in_title(
-    pattern: &str,
-    looking_window_owner_pid: u32,
-) -> Result<HWND, String> {
-    let mut user_data = EnumProcUserData {
-        looking_pid: looking_window_owner_pid,
-        found_hwnd: None,
-        title_pattern: pattern.to_string(),
-    };
-    let lparam = LPARAM(&mut user_data as *mut EnumProcUserData as isize);
+thread_local! {
+    /// Where the WinEvent hook of the current thread sends its signals.
+    static WINDOW_SIGNALS: RefCell<Option<Sender<WindowSignal>>> = const { RefCell::new(None) };
+}
 
-    let enum_result = unsafe { EnumWindows(WNDENUMPROC::Some(enum_windows_proc), lparam) };
+unsafe extern "system" fn window_event_proc(
+    _hook: HWINEVENTHOOK,
+    event: u32,
+    _hwnd: HWND,
+    id_object: i32,
+    id_child: i32,
+    _event_thread: u32,
+    _event_time: u32,
+) {
+    // Events of windows themselves, not of their controls or the cursor.
+    let window_changed = matches!(
+        event,
+        EVENT_OBJECT_CREATE | EVENT_OBJECT_SHOW | EVENT_OBJECT_NAMECHANGE
+    ) && id_object == OBJID_WINDOW.0
+        && id_child == CHILDID_SELF as i32;
+    if window_changed {
+        WINDOW_SIGNALS.with(|signals| {
+            if let Some(sender) = signals.borrow().as_ref() {
+                let _ = sender.send(WindowSignal::Changed);
+            }
+        });
+    }
+}
 
-    if let Err(err) = enum_result.ok() {
-        if err.code() == ERROR_SUCCESS.to_hresult() {
-            Ok(user_data.found_hwnd.expect("In this case window handle HWND should be set. Check callback function passed to EnumWindows"))
+/// Thread with a WinEvent hook, signalling every window that is created,
+/// shown or renamed until dropped.
+pub struct WindowEventHook {
+    thread_id: u32,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WindowEventHook {
+    /// Hook the windows of process `pid` if given, which also cancels the
+    /// watch when it exits, or those of all other processes.
+    pub fn start(pid: Option<u32>, sender: Sender<WindowSignal>) -> Result<WindowEventHook, Error> {
+        let process = pid.map(open_process).transpose()?;
+        let (started_sender, started) = mpsc::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("window-events".to_string())
+            .spawn(move || unsafe {
+                // Make sure the thread has a message queue before its id is handed out.
+                let mut msg = MSG::default();
+                PeekMessageW(&mut msg, HWND(0), 0, 0, PM_NOREMOVE);
+
+                let hook = SetWinEventHook(
+                    EVENT_OBJECT_CREATE,
+                    EVENT_OBJECT_NAMECHANGE,
+                    HMODULE(0),
+                    Some(window_event_proc),
+                    pid.unwrap_or(0),
+                    0,
+                    WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
+                );
+                if hook.is_invalid() {
+                    let _ = started_sender.send(Err(OsError::last_win32("SetWinEventHook")));
+                    return;
+                }
+                WINDOW_SIGNALS.with(|signals| *signals.borrow_mut() = Some(sender.clone()));
+                let _ = started_sender.send(Ok(GetCurrentThreadId()));
+
+                pump_window_events(process, &sender);
+
+                WINDOW_SIGNALS.with(|signals| *signals.borrow_mut() = None);
+                if !UnhookWinEvent(hook).as_bool() {
+                    warn!("{}", OsError::last_win32("UnhookWinEvent"));
+                }
+            })
+            .map_err(|err| Error::Window(OsError::from_io("CreateThread", &err)))?;
+
+        match started.recv() {
+            Ok(Ok(thread_id)) => Ok(WindowEventHook {
+                thread_id,
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => Err(Error::Window(err)),
+            Err(_) => Err(Error::Window(OsError::last_win32("SetWinEventHook"))),
+        }
+    }
+}
+
+impl Drop for WindowEventHook {
+    fn drop(&mut self) {
+        let posted = unsafe { PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) };
+        if let Err(err) = posted.ok() {
+            warn!("Window event hook keeps running: {}", err);
+        } else if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Deliver the events of the thread's hook until `WM_QUIT`, cancelling the
+/// watch when `process` exits.
+unsafe fn pump_window_events(mut process: Option<WindowsHandle>, sender: &Sender<WindowSignal>) {
+    loop {
+        let handles: Vec<HANDLE> = process.iter().map(WindowsHandle::as_raw_handle).collect();
+        let wait_result = MsgWaitForMultipleObjects(Some(&handles), FALSE, INFINITE, QS_ALLINPUT);
+        if wait_result == WAIT_OBJECT_0.0 + handles.len() as u32 {
+            // Out-of-context hooks are called while the thread peeks at messages.
+            let mut msg = MSG::default();
+            while PeekMessageW(&mut msg, HWND(0), 0, 0, PM_REMOVE).as_bool() {
+                if msg.message == WM_QUIT {
+                    return;
+                }
+            }
+        } else if wait_result == WAIT_OBJECT_0.0 && process.is_some() {
+            // Nothing of the process can appear anymore.
+            let _ = sender.send(WindowSignal::Cancelled);
+            process = None;
         } else {
-            Err(format!("EnumWindows failed: {}", err))
+            error!("{}", OsError::last_win32("MsgWaitForMultipleObjects"));
+            let _ = sender.send(WindowSignal::Cancelled);
+            return;
         }
-    } else {
-        Err(format!(
-            "Could not find window title with pattern \"{}\" owned by pid {}",
-            pattern, looking_window_owner_pid
-        ))
     }
 }
 
+/// Run `exec_path` with `options`, placing the new process into `job` before it starts
+/// running if given.
 pub fn run_exec(
     exec_path: &Path,
+    options: &SpawnOptions,
     startup_info: &STARTUPINFOW,
-) -> Result<(u32, WindowsHandle), String> {
+    job: Option<&WindowsHandle>,
+) -> Result<(u32, WindowsHandle), Error> {
     let mut process_info = PROCESS_INFORMATION::default();
-    let mut utf16_exec_path: Vec<u16> =
-        exec_path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut command_line = command_line(exec_path, &options.args);
+    let working_dir: Option<Vec<u16>> = options
+        .working_dir
+        .as_ref()
+        .map(|dir| dir.as_os_str().encode_wide().chain(Some(0)).collect());
+    let environment = (!options.env.is_empty()).then(|| environment_block(&options.env));
+
+    let mut creation_flags = if job.is_some() {
+        CREATE_SUSPENDED
+    } else {
+        PROCESS_CREATION_FLAGS::default()
+    };
+    if environment.is_some() {
+        creation_flags |= CREATE_UNICODE_ENVIRONMENT;
+    }
 
     unsafe {
         let process_result = CreateProcessW(
             PCWSTR::null(),
-            PWSTR(utf16_exec_path.as_mut_ptr()),
+            PWSTR(command_line.as_mut_ptr()),
             None,
             None,
             FALSE,
-            PROCESS_CREATION_FLAGS::default(),
-            None,
-            PCWSTR::null(),
+            creation_flags,
+            environment
+                .as_ref()
+                .map(|block| block.as_ptr() as *const c_void),
+            working_dir
+                .as_ref()
+                .map_or(PCWSTR::null(), |dir| PCWSTR(dir.as_ptr())),
             startup_info,
             &mut process_info,
         );
 
         if process_result.as_bool() {
             debug!(
-                "Run exec (pid {}) \"{}\"",
+                "Run exec (pid {}) \"{}\" {:?}",
                 process_info.dwProcessId,
-                exec_path.display()
+                exec_path.display(),
+                options.args
             );
 
-            CloseHandle(process_info.hThread).ok().unwrap();
+            let hthread = WindowsHandle::from_raw_handle(process_info.hThread);
+            if let Some(job) = job {
+                let assign_result =
+                    AssignProcessToJobObject(job.as_raw_handle(), process_info.hProcess);
+                if let Err(err) = assign_result.ok() {
+                    // Rather run the mixer without the guarantee than not at all.
+                    error!(
+                        "{}",
+                        Error::Job(OsError::from_win32("AssignProcessToJobObject", err))
+                    );
+                }
+                ResumeThread(hthread.as_raw_handle());
+            }
+            // Only the process handle is needed.
+            drop(hthread);
 
             Ok((
                 process_info.dwProcessId,
                 WindowsHandle::from_raw_handle(process_info.hProcess),
             ))
         } else {
-            Err(format!("CreateProcessW failed: {}", Error::from_win32()))
+            Err(Error::Spawn {
+                exec_path: exec_path.to_path_buf(),
+                source: OsError::last_win32("CreateProcessW"),
+            })
+        }
+    }
+}
+
+/// Null-terminated command line of `exec_path` and `args`, quoted so the
+/// process parses them back like `CommandLineToArgvW` does.
+fn command_line(exec_path: &Path, args: &[String]) -> Vec<u16> {
+    let mut command_line = OsString::from("\"");
+    command_line.push(exec_path.as_os_str());
+    command_line.push("\"");
+    for arg in args {
+        command_line.push(" ");
+        command_line.push(quote_arg(arg));
+    }
+
+    command_line.encode_wide().chain(Some(0)).collect()
+}
+
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '"']) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Escape the backslashes before a quote and the quote itself.
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
         }
     }
+    // Backslashes before the closing quote would escape it.
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+
+    quoted
+}
+
+/// Environment of the tray with `overrides` applied, as the block of
+/// `NAME=value` strings `CreateProcessW` expects.
+fn environment_block(overrides: &[(String, String)]) -> Vec<u16> {
+    // Names are case-insensitive, and the block must be sorted by them.
+    let mut variables: BTreeMap<String, (OsString, OsString)> = env::vars_os()
+        .map(|(name, value)| (name.to_string_lossy().to_uppercase(), (name, value)))
+        .collect();
+    for (name, value) in overrides {
+        variables.insert(name.to_uppercase(), (name.into(), value.into()));
+    }
+
+    let mut block = Vec::new();
+    for (name, value) in variables.values() {
+        block.extend(name.encode_wide());
+        block.push(u16::from(b'='));
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    block.push(0);
+
+    block
+}
+
+/// Job object that ends its processes when the tray exits, however it exits.
+/// The handle is never closed explicitly, the system closes it with the tray.
+pub fn kill_on_exit_job() -> Option<&'static WindowsHandle> {
+    static JOB: OnceLock<Option<WindowsHandle>> = OnceLock::new();
+
+    JOB.get_or_init(|| match create_kill_on_close_job() {
+        Ok(job) => Some(job),
+        Err(err) => {
+            error!("Children may outlive the tray: {}", err);
+            None
+        }
+    })
+    .as_ref()
+}
+
+fn create_kill_on_close_job() -> Result<WindowsHandle, Error> {
+    let job = unsafe { CreateJobObjectW(None, PCWSTR::null()) }
+        .map(WindowsHandle::from_raw_handle)
+        .map_err(|err| Error::Job(OsError::from_win32("CreateJobObjectW", err)))?;
+
+    let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+    limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+    let set_result = unsafe {
+        SetInformationJobObject(
+            job.as_raw_handle(),
+            JobObjectExtendedLimitInformation,
+            &limits as *const _ as *const c_void,
+            size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        )
+    };
+    if let Err(err) = set_result.ok() {
+        Err(Error::Job(OsError::from_win32(
+            "SetInformationJobObject",
+            err,
+        )))
+    } else {
+        Ok(job)
+    }
+}
+
+/// Print to the console the tray was launched from. The tray has no console
+/// of its own, so output is lost without one.
+pub fn attach_parent_console() -> bool {
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS) }.as_bool()
+}
+
+pub fn open_process(pid: u32) -> Result<WindowsHandle, Error> {
+    let access = PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_SYNCHRONIZE | PROCESS_TERMINATE;
+    let handle = unsafe { OpenProcess(access, FALSE, pid) }
+        .map_err(|err| Error::ProcessQuery(OsError::from_win32("OpenProcess", err)))?;
+
+    Ok(WindowsHandle::from_raw_handle(handle))
+}
+
+/// Exit code of the process once it ends within `timeout`, `None` while it
+/// still runs.
+pub fn wait_process(hprocess: &WindowsHandle, timeout: Duration) -> Result<Option<u32>, Error> {
+    // `INFINITE` is `u32::MAX`, stay below it.
+    let timeout_ms = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+    let wait_result = unsafe { WaitForSingleObject(hprocess.as_raw_handle(), timeout_ms) };
+    if wait_result == WAIT_TIMEOUT {
+        return Ok(None);
+    } else if wait_result != WAIT_OBJECT_0 {
+        return Err(Error::ProcessQuery(OsError::last_win32(
+            "WaitForSingleObject",
+        )));
+    }
+
+    let mut exit_code = 0;
+    let exit_code_result = unsafe { GetExitCodeProcess(hprocess.as_raw_handle(), &mut exit_code) };
+    if let Err(err) = exit_code_result.ok() {
+        Err(Error::ProcessQuery(OsError::from_win32(
+            "GetExitCodeProcess",
+            err,
+        )))
+    } else {
+        Ok(Some(exit_code))
+    }
 }